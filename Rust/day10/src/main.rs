@@ -4,6 +4,8 @@ use std::ops::Add;
 use regex::Regex;
 use lazy_static::*;
 
+use shared::geom::{BoundingBox, Rect};
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 struct Vec2<T>(T, T);
 
@@ -37,19 +39,13 @@ impl Point {
     }
 }
 
-fn calculate_bounding_box(points: &[Point]) -> (Vec2<i32>, Vec2<i32>) {
-    let mut min = Vec2(i32::max_value(), i32::max_value());
-    let mut max = Vec2(i32::min_value(), i32::min_value());
-
-    for Point { pos, .. } in points {
-        if pos.0 > max.0 { max.0 = pos.0; }
-        if pos.1 > max.1 { max.1 = pos.1; }
-
-        if pos.0 < min.0 { min.0 = pos.0; }
-        if pos.1 < min.1 { min.1 = pos.1; }
-    }
-
-    (min, max)
+fn calculate_bounding_box(points: &[Point]) -> Rect<i32> {
+    points
+        .iter()
+        .map(|Point { pos, .. }| (pos.0, pos.1))
+        .collect::<BoundingBox<_>>()
+        .rect()
+        .expect("no points in the sky")
 }
 
 impl FromStr for Point {
@@ -84,14 +80,14 @@ impl FromStr for Point {
 fn draw_sky(points: &[Point]) {
     use std::collections::HashSet;
 
-    let (min, max) = calculate_bounding_box(points);
+    let bounds = calculate_bounding_box(points);
     let sky = points.iter().fold(HashSet::new(), |mut acc, pt| {
         acc.insert(pt.pos);
         acc
     });
     
-    for y in min.1 ..= max.1 {
-        for x in min.0 ..= max.0 {
+    for y in bounds.y.iter() {
+        for x in bounds.x.iter() {
             print!("{}", if sky.contains(&Vec2(x, y)) {
                 '#'
             } else {
@@ -107,17 +103,16 @@ fn main() {
     let input = shared::input::read_stdin_lines().expect("could not lock stdin");
     let mut input: Vec<Point> = input.iter().filter_map(|line| line.parse().ok()).collect();
 
-    let (mut min, mut max) = calculate_bounding_box(&input);
+    let mut bounds = calculate_bounding_box(&input);
     
     for t in 0.. {
         input.iter_mut().for_each(|p| *p = p.tick());
 
-        let (new_min, new_max) = calculate_bounding_box(&input);
+        let new_bounds = calculate_bounding_box(&input);
 
         // Presumably, once all points are aligned, the bounding box is minimal, so any increase in
         // the BB is taken as the message being just past its most coherent form
-        if new_min.0 < min.0 || new_min.1 < min.1
-            || new_max.0 > max.0 || new_max.1 > max.1 {
+        if !bounds.contains_rect(&new_bounds) {
 
             input.iter_mut().for_each(|p| *p = p.untick());
 
@@ -127,8 +122,7 @@ fn main() {
 
             break;
         } else {
-            min = new_min;
-            max = new_max;            
+            bounds = new_bounds;
         }
     }
 }
@@ -1,4 +1,4 @@
-use rayon::prelude::*;
+use shared::grid::SummedAreaTable;
 
 fn get_power_level(x: usize, y: usize, serial: i32) -> i32 {
     let rack_id = x + 10;
@@ -11,63 +11,42 @@ fn get_power_level(x: usize, y: usize, serial: i32) -> i32 {
     pwr
 }
 
-fn get_grid_sum(x: usize, y: usize, serial: i32, grid_size: usize) -> i32 {
-    let mut sum = 0;
-
-    for y in y..y+grid_size {
-        for x in x..x+grid_size {
-            sum += get_power_level(x, y, serial)
-        }
-    }
-
-    sum
-}
-
 fn main() {
     const SERIAL: i32 = 7672;
     
     const MAX: usize = 300;
 
-    let mut max = i32::min_value();
-    let mut maxc = (usize::default(), usize::default());
-
-    // Part 1
-    for y in 1..=MAX - 3 {
-        for x in 1..=MAX - 3 {
-            let pwr = get_grid_sum(x, y, SERIAL, 3);
-
-            if pwr > max {
-                max = pwr;
-                maxc = (x, y);
-            }
-        }
-    }
-
-    println!("Part 1: x:{} y:{} = {}", maxc.0, maxc.1, max);
+    // Coordinates in the puzzle are 1-based, the table is 0-based
+    let grid = SummedAreaTable::from_fn(MAX, MAX, |y, x| get_power_level(x + 1, y + 1, SERIAL));
 
-    // Part 2 - Could be doing this the smart way using a summed area table, but
-    // I decided to rub 16 threads against the problem.
-    let results: Vec<(usize, (usize, usize), i32)> = (1..301_usize).into_par_iter().map(|s| {
-        let mut maxc = (0, 0);
-        let mut maxs = usize::min_value();
-        let mut max = i32::min_value();
+    let best_square = |size: usize| {
+        let mut max = i32::MIN;
+        let mut maxc = (usize::default(), usize::default());
 
-        for y in 1..=MAX - maxs {
-            for x in 1..=MAX - maxs {
-                let pwr = get_grid_sum(x, y, SERIAL, s);
+        for y in 0..=MAX - size {
+            for x in 0..=MAX - size {
+                let pwr = grid.square_sum(y, x, size);
 
                 if pwr > max {
                     max = pwr;
-                    maxs = s;
-                    maxc = (x, y);
+                    maxc = (x + 1, y + 1);
                 }
             }
         }
 
-        (s, maxc, max)
-    }).collect();
+        (maxc, max)
+    };
+
+    // Part 1
+    let (maxc, max) = best_square(3);
+
+    println!("Part 1: x:{} y:{} = {}", maxc.0, maxc.1, max);
 
-    let (bs, (bx, by), bp) = results.iter().max_by(|(_, _, n1), (_, _, n2)| n1.cmp(&n2)).unwrap();
+    // Part 2
+    let (bs, ((bx, by), bp)) = (1..=MAX)
+        .map(|s| (s, best_square(s)))
+        .max_by_key(|(_, (_, p))| *p)
+        .unwrap();
 
     println!("Part 2: x:{} y:{} s:{} = {}", bx, by, bs, bp);
 }
@@ -2,6 +2,8 @@ use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
 use std::cmp::Ordering;
 
+use shared::grid::{self as sg, Graph};
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum Tile {
     Empty, Wall, Elf, Goblin
@@ -120,6 +122,23 @@ impl Unit {
 
 type Map = Vec<Vec<Tile>>;
 
+// Empty squares of the map that aren't taken by a living unit
+struct OpenSquares<'a> {
+    world: &'a Map,
+    units: &'a HashSet<Coord>
+}
+
+impl<'a> Graph for OpenSquares<'a> {
+    type Node = Coord;
+
+    fn edges(&self, node: &Coord) -> Vec<(Coord, isize)> {
+        node.neighbors()
+            .filter(|n| self.world[n.y][n.x].is_passable() && !self.units.contains(n))
+            .map(|n| (n, 1))
+            .collect()
+    }
+}
+
 #[derive(Clone)]
 struct World {
     world: RefCell<Map>,
@@ -220,17 +239,17 @@ impl World {
         if adjacent.len() == 0 {
             let mut unit = units[i].borrow_mut();
 
-            // Have to move somewhere, figure out next move
-            let mut next_moves = Vec::new();
+            // Have to move somewhere, head for the closest reachable target square
+            // and take the first step along a shortest path towards it
+            let open = OpenSquares { world: &world, units: &unit_positions };
 
-            for tsquare in target_squares {
-                if let Some(path) = self.bfs(&unit_positions, unit.pos, tsquare) {
-                    next_moves.push((path.len(), path[1]));
-                }
-            }
+            let reachable = sg::distances(&open, unit.pos);
+
+            if let Some((target, _)) = reachable.nearest(target_squares) {
+                let from_target = sg::distances(&open, target);
+                let (next_move, _) = from_target.nearest(unit.pos.neighbors()).unwrap();
 
-            if let Some((_, next_move)) = next_moves.iter().min_by_key(|(l, _)| l) {
-                unit.pos = *next_move;
+                unit.pos = next_move;
 
                 for (_j, target) in targets.iter() {
                     if unit.is_in_range_of(&target.borrow()) {
@@ -306,42 +325,6 @@ impl World {
         }
     }
 
-    fn bfs(&self,
-        unit_positions: &HashSet<Coord>,
-        source_pos: Coord,
-        target_pos: Coord) -> Option<Vec<Coord>>
-    {
-        use std::collections::{HashSet, VecDeque};
-
-        let world = self.world.borrow();
-
-        let mut queue = VecDeque::new();
-        let mut seen = HashSet::new();
-
-        queue.push_back(vec![source_pos]);
-        seen.insert(source_pos);
-
-        while let Some(path) = queue.pop_front() {
-            let c =  path[path.len() - 1];
-
-            if c == target_pos {
-                return Some(path);
-            }
-
-            for neighbor in c.neighbors() {
-                if world[neighbor.y][neighbor.x].is_passable() && !unit_positions.contains(&neighbor) && !seen.contains(&neighbor) {
-                    let mut npath = path.clone();
-                    npath.push(neighbor);
-
-                    queue.push_back(npath);
-                    seen.insert(neighbor);
-                }
-            }
-        }
-
-        None
-    }
-
     fn hitpoints(&self) -> (i32, i32) {
         self.units.iter().fold((0, 0), |(s_elf, s_gob), u|
             if u.borrow().unit_type == UnitType::Elf {
@@ -1,5 +1,6 @@
 use shared::{
-    grid::{self as sg, Grid, GridTile, Coordinate},
+    geom::{BoundingBox, Rect},
+    grid::{self as sg, Grid, Coordinate},
     input::read_stdin_lines
 };
 
@@ -12,8 +13,7 @@ enum Tile {
 }
 
 struct Map {
-    data: Vec<Vec<Tile>>,
-    xstart: usize
+    data: Vec<Vec<Tile>>
 }
 
 
@@ -22,8 +22,7 @@ impl Map {
         data[0][500 - xstart + 2] = Tile::Spring;
 
         Map {
-            data,
-            xstart
+            data
         }
     }
 
@@ -214,11 +213,11 @@ impl sg::Grid for Map {
 
     fn bounds(&self) -> (Self::Coord, Self::Coord) {
         (sg::Coord::new(0, 0),
-         sg::Coord::new(self.data.len(), self.data[0].len()))
+         sg::Coord::new(self.data.len() as _, self.data[0].len() as _))
     }
 
     fn tile_at(&self, c: &Self::Coord) -> &Self::Tile {
-        &self.data[c.y()][c.x()]
+        &self.data[c.y() as usize][c.x() as usize]
     }
 }
 
@@ -243,40 +242,32 @@ impl sg::GridTile for Tile {
     }
 }
 
-use std::ops::RangeInclusive;
-
-#[derive(Debug)]
-enum ScanEntry {
-    RangeY(RangeInclusive<usize>, usize),
-    RangeX(usize, RangeInclusive<usize>)
-}
-
-impl ScanEntry {
-    fn parse(e: &String) -> Option<ScanEntry> {
-        lazy_static! {
-            static ref PAT: Regex = Regex::new(r"(x|y)=(\d+)(?:..(\d+))?").unwrap();
-        }
+// Clay veins are shifted one column to the right to leave room for water to
+// flow down the left side of the leftmost vein
+fn parse_vein(e: &str) -> Option<Rect<usize>> {
+    lazy_static! {
+        static ref PAT: Regex = Regex::new(r"(x|y)=(\d+)(?:..(\d+))?").unwrap();
+    }
 
-        let mut captures = PAT.captures_iter(e);
+    let mut captures = PAT.captures_iter(e);
 
-        let first_coord = captures.next()?;
-        let second_coord = captures.next()?;
+    let first_coord = captures.next()?;
+    let second_coord = captures.next()?;
 
-        if first_coord.get(1)?.as_str() == "x" {
-            let x: usize = first_coord.get(2)?.as_str().parse().ok()?;
-            let ys = second_coord.get(2)?.as_str().parse().ok()?;
-            let ye = second_coord.get(3)?.as_str().parse().ok()?;
+    if first_coord.get(1)?.as_str() == "x" {
+        let x: usize = first_coord.get(2)?.as_str().parse().ok()?;
+        let ys = second_coord.get(2)?.as_str().parse().ok()?;
+        let ye = second_coord.get(3)?.as_str().parse().ok()?;
 
-            Some(ScanEntry::RangeY(ys..=ye, x + 1))
-        } else if first_coord.get(1)?.as_str() == "y" {
-            let y = first_coord.get(2)?.as_str().parse().ok()?;
-            let xs: usize = second_coord.get(2)?.as_str().parse().ok()?;
-            let xe: usize = second_coord.get(3)?.as_str().parse().ok()?;
+        Some(Rect::inclusive((x + 1, ys), (x + 1, ye)))
+    } else if first_coord.get(1)?.as_str() == "y" {
+        let y = first_coord.get(2)?.as_str().parse().ok()?;
+        let xs: usize = second_coord.get(2)?.as_str().parse().ok()?;
+        let xe: usize = second_coord.get(3)?.as_str().parse().ok()?;
 
-            Some(ScanEntry::RangeX(y, (xs + 1)..=(xe + 1 )))
-        } else {
-            None
-        }
+        Some(Rect::inclusive((xs + 1, y), (xe + 1, y)))
+    } else {
+        None
     }
 }
 
@@ -284,45 +275,24 @@ use std::collections::HashSet;
 
 fn main() {
     let input = read_stdin_lines().expect("could not lock stdin");
-    let mut results = input.iter().filter_map(ScanEntry::parse).collect::<Vec<_>>();
-
-    let (cmin, cmax) = sg::Coord::numeric_limits();
-
-    let (mut min, mut max) = (sg::Coord::new(cmax, cmax), sg::Coord::new(cmin, cmin));
+    let veins = input.iter().map(String::as_str).filter_map(parse_vein).collect::<Vec<_>>();
 
-    let clay = results.iter_mut().fold(HashSet::new(), |mut hs, res| {
-        let mut update = |c: sg::Coord| {
-            hs.insert(c);
-
-            if c.0 > max.0 { max.0 = c.0; }
-            if c.1 > max.1 { max.1 = c.1; }
-            if c.0 < min.0 { min.0 = c.0; }
-            if c.1 < min.1 { min.1 = c.1; }
-        };
+    let bounds = veins.iter().fold(BoundingBox::new(), |mut bb, vein| {
+        bb.add_rect(vein);
+        bb
+    });
 
-        match res {
-            ScanEntry::RangeX(y, xr) => {
-                for x in xr {
-                    update(sg::Coord::new(*y, x));
-                }
-            },
-            ScanEntry::RangeY(yr, x) => {
-                for y in yr {
-                    update(sg::Coord::new(y, *x));
-                }
-            }
-        }
+    let ((min_x, min_y), (max_x, max_y)) = bounds.corners().expect("no clay veins in scan");
 
-        hs
-    });
+    let clay = veins.iter().flat_map(|vein| vein.points()).collect::<HashSet<_>>();
 
-    let mut grid = Vec::with_capacity(max.y());
+    let mut grid = Vec::with_capacity(max_y + 1);
 
-    for y in 0..=max.y() {
-        let mut r = Vec::with_capacity(max.x() - min.x());
+    for y in 0..=max_y {
+        let mut r = Vec::with_capacity(max_x - min_x + 3);
 
-        for x in min.x() - 1 ..= max.x() + 1 {
-            if clay.contains(&sg::Coord::new(y, x)) {
+        for x in min_x - 1 ..= max_x + 1 {
+            if clay.contains(&(x, y)) {
                 r.push(Tile::Clay);
             } else {
                 r.push(Tile::Sand);
@@ -332,17 +302,13 @@ fn main() {
         grid.push(r);
     }
 
-    let mut grid = Map::new(grid, min.x());
+    let mut grid = Map::new(grid, min_x);
 
-    for y in 0.. {
-        if !grid.update() {
-            break;
-        }
-    }
+    while grid.update() { }
 
     grid.draw();
 
-    let (f, r) = grid.count_water(min.y(), max.y());
+    let (f, r) = grid.count_water(min_y, max_y);
 
     println!("Part 1: Total water tiles: {}", f + r);
     println!("Part 2: Remaining tiles: {}", r);
@@ -77,26 +77,31 @@ fn parse_doorex(inp: &[u8]) -> Option<Path> {
     parse_path(&inp[1..inp.len() - 1])
 }
 
-use std::collections::{HashMap};
+use std::collections::HashMap;
 
-fn count_steps(
-    mut pos: (isize, isize),
-    mut steps_so_far: usize,
+use shared::grid;
+
+type Room = (isize, isize);
+
+fn map_doors(
+    mut pos: Room,
     path: &Path,
-    rooms: &mut HashMap<(isize, isize), usize>)
+    doors: &mut HashMap<Room, Vec<Room>>)
 {
     for p in path {
         match p {
             DoorEx::Door(d) => {
-                pos = d.step(pos);
-                steps_so_far += 1;
+                let next = d.step(pos);
 
-                rooms.entry(pos).or_insert(steps_so_far);
+                doors.entry(pos).or_default().push(next);
+                doors.entry(next).or_default().push(pos);
+
+                pos = next;
             },
 
             DoorEx::Branch(choices) => {
                 for choice in choices {
-                    count_steps(pos, steps_so_far, &choice, rooms);
+                    map_doors(pos, choice, doors);
                 }
             }
         }
@@ -106,15 +111,13 @@ fn count_steps(
 fn main() {
     const INPUT: &[u8] = include_bytes!("../input");
 
-    let mut rooms: HashMap<(isize, isize), usize> = HashMap::new();
+    let mut doors = HashMap::new();
     let parsed_input = parse_doorex(INPUT).unwrap();
     
-    count_steps((0, 0), 0, &parsed_input, &mut rooms);
+    map_doors((0, 0), &parsed_input, &mut doors);
 
-    let mut rooms = rooms.iter().collect::<Vec<_>>();
-    
-    rooms.sort_by_key(|(_k, v)| *v);
+    let rooms = grid::distances(&doors, (0, 0));
 
-    println!("Part 1: {}", rooms[rooms.len() - 1].1);
-    println!("Part 2: {}", rooms.iter().filter(|(_k, v)| **v >= 1000).count());
+    println!("Part 1: {}", rooms.max().unwrap().1);
+    println!("Part 2: {}", rooms.count_at_least(1000));
 }
@@ -1,65 +1,32 @@
 use shared::cpu;
 use shared::input;
 
-fn parse_instr(s: &str) -> Option<cpu::Opcode> {
-    let mut p = s.split(" ");
-
-    let mnem = p.next()?;
-    let a = p.next()?.parse().ok()?;
-    let b = p.next()?.parse().ok()?;
-    let c = p.next()?.parse().ok()?;
-
-    Some(cpu::Opcode::build(cpu::Mnemonic::from_str(mnem)?, a, b, c)?)
-}
-
 fn main() {
-    let prog = input::read_stdin_lines().expect("could not lock stdin");
-    
-    let ireg: cpu::Register = prog[0].split(" ").nth(1).unwrap().parse().ok().unwrap();
-    let prog = &prog[1..].iter().filter_map(|l| parse_instr(&l)).collect::<Vec<_>>();
-
-    run_with(ireg, 0, prog);
-}
-
-use std::collections::HashSet;
+    let source = input::read_stdin_lines().expect("could not lock stdin").join("\n");
+    let program = source.parse::<cpu::Program>().unwrap_or_else(|e| panic!("invalid program: {}", e));
 
-fn run_with(ireg: cpu::Register, r0: cpu::Word, prog: &[cpu::Opcode]) {
-    let mut alu = cpu::Alu::new();
-    
-    alu.regs[0] = r0;
+    let mut machine = cpu::CompiledMachine::new(&program);
 
-    let mut reqs = HashSet::new();    
-    let mut last = 0;
+    // Replaces the loop dividing by 256 one step at a time
+    machine.optimize();
 
-    while alu.regs[ireg as usize] < prog.len() as cpu::Word {
-        let ip = alu.regs[ireg as usize] as usize;
-        let op = prog[ip];
-       
-        match alu.eval(&op) {
-            Ok(_) => {
-                if ip == 28 {
-                    let target = alu.regs[1];
-
-                    if !reqs.contains(&target) {
-                        if reqs.len() == 0 {
-                            println!("Part 1: {}", target);
-                        }
-
-                        reqs.insert(target);
-
-                        last = target;
-                    } else {
-                        println!("Part 2: {}", last);
-                        break;
-                    }
-                }
-            },
-
-            Err(e) => {
-                println!("ALU error: {:?}: {}", e, op);
-            }
+    // The program exits once r0 matches a value it computes over and over. Those
+    // eventually repeat, the last new one takes the longest to get to.
+    let analysis = match cpu::halting::analyze(&program, &mut machine, None) {
+        Ok(analysis) => analysis,
+        Err(e) => {
+            println!("Cannot analyze program: {}", e);
+            return;
         }
+    };
 
-        alu.regs[ireg as usize] += 1;
+    if let Some(first) = analysis.first() {
+        println!("Part 1: {} ({} steps)", first.value, first.steps);
     }
-}
\ No newline at end of file
+
+    match (analysis.last(), analysis.halt) {
+        (Some(last), _) => println!("Part 2: {} ({} steps)", last.value, last.steps),
+        (None, Some(halt)) => println!("Machine halted: {}", halt),
+        (None, None) => println!("No value halts the program")
+    }
+}
@@ -1,17 +1,12 @@
-const N: usize = 1000;
+use shared::geom::Rect;
 
-#[derive(Debug, PartialEq, Eq)]
-struct Rect {
-    x: usize,
-    y: usize
-}
+const N: usize = 1000;
 
 #[derive(Debug)]
 struct Claim {
     id: u32,
 
-    start: Rect,
-    size: Rect
+    area: Rect<usize>
 }
 
 impl Claim {
@@ -28,48 +23,28 @@ impl Claim {
             let raw = parts.next()?;
             let mut raw = raw[.. raw.len() - 1].split(',');
 
-            Rect {
-                x: raw.next()?.parse().ok()?,
-                y: raw.next()?.parse().ok()?
-            }
+            (raw.next()?.parse().ok()?, raw.next()?.parse().ok()?)
         };
 
-        let size = {
-            let mut raw = parts.next()?.split('x');
-
-            Rect {
-                // inclusive range, thus -1
-                x: raw.next()?.parse::<usize>().ok()? - 1,
-                y: raw.next()?.parse::<usize>().ok()? - 1
-            }
-        };
+        let mut size = parts.next()?.split('x');
+        let (width, height) = (size.next()?.parse().ok()?, size.next()?.parse().ok()?);
         
         Some(Claim {
             id,
-            start,
-            size
+            area: Rect::from_size(start, width, height)
         })
     }
 
     fn apply_to<F>(&self, board: &mut Board, mut func: F)
         where F: FnMut(&mut u16)
     {
-        for y in self.start.y .. self.start.y + self.size.y {
-            for x in self.start.x .. self.start.x + self.size.x {
-                func(&mut board[y][x]);
-            }
+        for (x, y) in self.area.points() {
+            func(&mut board[y][x]);
         }
     }
 
     fn overlaps(&self, claim: &Claim) -> bool {
-        let in_range = |x, min, max| (x >= min) && (x <= max);
-
-        let yover = in_range(self.start.x, claim.start.x, claim.start.x + claim.size.x)
-            || in_range(claim.start.x, self.start.x, self.start.x + self.size.x);
-        let xover = in_range(self.start.y, claim.start.y, claim.start.y + claim.size.y)
-            || in_range(claim.start.y, self.start.y, self.start.y + self.size.y);
-
-        yover && xover
+        self.area.overlaps(&claim.area)
     }
 }
 
@@ -118,3 +93,32 @@ fn main() {
 
     println!("Non-overlapping: {} ({} total)", non_overlapping[0].id, non_overlapping.len());
 }
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use super::{count_shared, Board, Claim, N};
+
+    #[test]
+    fn test_example() {
+        let claims = ["#1 @ 1,3: 4x4", "#2 @ 3,1: 4x4", "#3 @ 5,5: 2x2"]
+            .iter()
+            .filter_map(Claim::from_string)
+            .collect::<Vec<_>>();
+
+        // Too large for the test thread's stack
+        let mut board: Box<Board> = vec![[0; N]; N].into_boxed_slice().try_into().unwrap();
+
+        for claim in &claims {
+            claim.apply_to(&mut board, |cell| *cell += 1);
+        }
+
+        // Every claim covers exactly its width times height, only 2x2 of them shared
+        assert_eq!(board.iter().map(|row| row.iter().filter(|&&n| n > 0).count()).sum::<usize>(), 32);
+        assert_eq!(count_shared(&board), 4);
+
+        assert!(claims[0].overlaps(&claims[1]));
+        assert!(!claims[2].overlaps(&claims[0]) && !claims[2].overlaps(&claims[1]));
+    }
+}
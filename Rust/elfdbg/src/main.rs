@@ -0,0 +1,74 @@
+use std::io::{self, BufRead, Write};
+
+use shared::cpu::{bytecode, Debugger, InstructionSet, Machine, Program};
+
+const HELP: &str = "\
+commands:
+  s, step [n]        execute n instructions (default 1)
+  c, continue        run until a breakpoint, watchpoint or halt
+  u, until N [K]     run until ip N is reached K times (default 1)
+  b, break N         set a breakpoint at ip N
+  d, delete N        remove the breakpoint at ip N
+  w, watch rX        stop whenever an instruction stores to rX
+  w, watch rX=V      stop when rX changes to V
+  uw, unwatch rX     remove all watchpoints on rX
+  set rX V           change a register, `set ip V` moves the instruction pointer
+  r, regs            show registers and the next instruction
+  l, list            show the program around the instruction pointer
+  p, profile N       run N instructions ignoring breakpoints, then show where they went
+  save               remember the machine state and print it
+  restore            go back to the saved state, showing what changes
+  i, info            show breakpoints, watchpoints and the step count
+  isa                list the instructions programs can use
+  q, quit";
+
+fn main() {
+    let path = std::env::args().nth(1).expect("usage: elfdbg <program> [r0]");
+    let bytes = std::fs::read(&path).expect("could not read program");
+
+    // Programs may use div*, mod*, nop, halt and out on top of the base instructions,
+    // and be given as source or as bytecode
+    let set = InstructionSet::extended();
+    let program = if bytes.starts_with(bytecode::MAGIC) {
+        bytecode::decode(&bytes, &set).map(|(program, _)| program).unwrap_or_else(|e| panic!("invalid bytecode: {}", e))
+    } else {
+        let source = String::from_utf8(bytes).expect("program is not valid UTF-8");
+
+        Program::parse_with(&source, &set).unwrap_or_else(|e| panic!("invalid program: {}", e))
+    };
+
+    let mut machine = Machine::new(program);
+
+    if let Some(r0) = std::env::args().nth(2) {
+        machine.registers_mut()[0] = r0.parse().expect("r0 must be a number");
+    }
+
+    let mut debugger = Debugger::new(machine);
+
+    println!("{}", debugger.status());
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+
+    loop {
+        print!("(elfdbg) ");
+        io::stdout().flush().expect("could not flush stdout");
+
+        let line = match lines.next() {
+            Some(line) => line.expect("could not read stdin"),
+            None => break
+        };
+
+        match line.trim() {
+            "q" | "quit" => break,
+            "h" | "help" => println!("{}", HELP),
+            "isa" => print!("{}", set),
+            "" => { },
+
+            cmd => match debugger.command(cmd) {
+                Ok(out) => println!("{}", out),
+                Err(e) => println!("error: {}", e)
+            }
+        }
+    }
+}
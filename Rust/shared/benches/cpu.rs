@@ -0,0 +1,36 @@
+#![feature(test)]
+
+extern crate test;
+
+use shared::cpu::{CompiledMachine, Machine, Program};
+use test::Bencher;
+
+const STEPS: usize = 100_000;
+
+fn program() -> Program {
+    include_str!("../../day21/input").parse().unwrap()
+}
+
+#[bench]
+fn alu_machine(b: &mut Bencher) {
+    let program = program();
+
+    b.iter(|| {
+        let mut machine = Machine::new(program.clone());
+
+        machine.run_for(STEPS);
+        machine.registers()[1]
+    });
+}
+
+#[bench]
+fn compiled_machine(b: &mut Bencher) {
+    let program = program();
+
+    b.iter(|| {
+        let mut machine = CompiledMachine::new(&program);
+
+        machine.run_for(STEPS);
+        machine.registers()[1]
+    });
+}
@@ -1,57 +1,236 @@
+use std::convert::TryFrom;
+
+pub mod asm;
+pub mod bytecode;
+pub mod cfg;
+pub mod compiled;
+pub mod debugger;
+pub mod decompile;
+pub mod difftest;
+pub mod discovery;
+pub mod halting;
+pub mod isa;
+pub mod machine;
+pub mod observe;
+pub mod optimize;
+pub mod profile;
+pub mod snapshot;
+pub mod symbolic;
+pub mod trace;
+
+pub use self::asm::{ParseError, ParseErrorKind, Program};
+pub use self::bytecode::{BytecodeError, BytecodeErrorKind};
+pub use self::cfg::{BasicBlock, Cfg, Flow, Loop, Target};
+pub use self::compiled::CompiledMachine;
+pub use self::debugger::{Debugger, Stop, Watch};
+pub use self::decompile::decompile;
+pub use self::difftest::{Case, Generator, Mismatch, Outcome};
+pub use self::discovery::{OpcodeMap, Sample, SampleError, SampleErrorKind, SolveError};
+pub use self::halting::{Candidate, ExitCheck, HaltingAnalysis, HaltingError};
+pub use self::isa::{Definition, Effect, InstructionSet, Operand, Operation, Shape};
+pub use self::machine::{Fault, Halt, Machine};
+pub use self::observe::{Comparison, Executor, Observers};
+pub use self::optimize::{find_macros, Idiom, Macro};
+pub use self::profile::{HotLoop, Profile, ValueRange};
+pub use self::snapshot::{Difference, Snapshot, SnapshotError, SnapshotErrorKind};
+pub use self::symbolic::{BinOp, Condition, Domain, End, Explorer, Expr, Path, Solution};
+pub use self::trace::{Trace, TraceEntry, TraceFilter, TraceSink, TraceWrite, TraceWriter};
+
 pub type Register = i8;
 pub type Word = i64;
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[derive(Copy, Clone)]
 pub enum Mnemonic {
-    Addr = 6,
-    Addi = 9,
-    Mulr = 14,
-    Muli = 1,
-    Banr = 2,
-    Bani = 3,
-    Borr = 12,
-    Bori = 0,
-    Setr = 5,
-    Seti = 8,
-    Gtir = 4,
-    Gtri = 15,
-    Gtrr = 13,
-    Eqir = 7,
-    Eqri = 11,
-    Eqrr = 10
+    Addr,
+    Addi,
+    Mulr,
+    Muli,
+    Banr,
+    Bani,
+    Borr,
+    Bori,
+    Setr,
+    Seti,
+    Gtir,
+    Gtri,
+    Gtrr,
+    Eqir,
+    Eqri,
+    Eqrr,
+
+    // An operation registered with an `InstructionSet`
+    Ext(&'static dyn Operation)
 }
 
 impl Mnemonic {
-    pub fn from_str(s: &str) -> Option<Self> {
-        match s {
-            "addr" => Some(Mnemonic::Addr),
-            "addi" => Some(Mnemonic::Addi),
-            "mulr" => Some(Mnemonic::Mulr),
-            "muli" => Some(Mnemonic::Muli),
-            "banr" => Some(Mnemonic::Banr),
-            "bani" => Some(Mnemonic::Bani),
-            "borr" => Some(Mnemonic::Borr),
-            "bori" => Some(Mnemonic::Bori),
-            "setr" => Some(Mnemonic::Setr),
-            "seti" => Some(Mnemonic::Seti),
-            "gtir" => Some(Mnemonic::Gtir),
-            "gtri" => Some(Mnemonic::Gtri),
-            "gtrr" => Some(Mnemonic::Gtrr),
-            "eqir" => Some(Mnemonic::Eqir),
-            "eqri" => Some(Mnemonic::Eqri),
-            "eqrr" => Some(Mnemonic::Eqrr),
-            _ => None
+    // The base instructions in the order of `isa::BASE`
+    pub const ALL: [Mnemonic; 16] = {
+        let mut all = [Mnemonic::Addr; 16];
+        let mut i = 0;
+
+        while i < all.len() {
+            all[i] = isa::BASE[i].mnemonic;
+            i += 1;
+        }
+
+        all
+    };
+
+    // Row in `isa::BASE`, extensions come after all base instructions
+    const fn index(self) -> usize {
+        match self {
+            Mnemonic::Addr => 0,
+            Mnemonic::Addi => 1,
+            Mnemonic::Mulr => 2,
+            Mnemonic::Muli => 3,
+            Mnemonic::Banr => 4,
+            Mnemonic::Bani => 5,
+            Mnemonic::Borr => 6,
+            Mnemonic::Bori => 7,
+            Mnemonic::Setr => 8,
+            Mnemonic::Seti => 9,
+            Mnemonic::Gtir => 10,
+            Mnemonic::Gtri => 11,
+            Mnemonic::Gtrr => 12,
+            Mnemonic::Eqir => 13,
+            Mnemonic::Eqri => 14,
+            Mnemonic::Eqrr => 15,
+            Mnemonic::Ext(_) => isa::BASE.len()
+        }
+    }
+
+    // Entry in `isa::BASE`, extensions have none
+    pub fn definition(self) -> Option<&'static Definition> {
+        isa::BASE.get(self.index())
+    }
+
+    // Opcode number in the numbering of the day16 device
+    pub fn number(self) -> Option<Word> {
+        self.definition().map(|d| d.number)
+    }
+
+    pub fn from_number(number: Word) -> Option<Self> {
+        isa::BASE.iter().find(|d| d.number == number).map(|d| d.mnemonic)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Mnemonic::Ext(op) => op.name(),
+            base => base.definition().unwrap().name
+        }
+    }
+
+    pub fn shape(self) -> Shape {
+        match self {
+            Mnemonic::Ext(op) => op.shape(),
+            base => base.definition().unwrap().shape
+        }
+    }
+
+    pub fn summary(self) -> &'static str {
+        match self {
+            Mnemonic::Ext(op) => op.summary(),
+            base => base.definition().unwrap().summary
+        }
+    }
+
+    // Orders base instructions by their row, and extensions after them by name
+    fn key(self) -> (usize, &'static str) {
+        match self {
+            Mnemonic::Ext(op) => (self.index(), op.name()),
+            base => (base.index(), "")
         }
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+// Every row of `isa::BASE` has to be where `Mnemonic::index` looks for it
+const _: () = {
+    let mut i = 0;
+
+    while i < isa::BASE.len() {
+        assert!(isa::BASE[i].mnemonic.index() == i, "isa::BASE is out of order");
+        i += 1;
+    }
+};
+
+impl std::fmt::Display for Mnemonic {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        fmt.pad(self.name())
+    }
+}
+
+// A name that is not one of the base instructions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownMnemonic(pub String);
+
+impl std::fmt::Display for UnknownMnemonic {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(fmt, "unknown mnemonic '{}'", self.0)
+    }
+}
+
+impl std::error::Error for UnknownMnemonic { }
+
+// Only the base instructions, `InstructionSet::lookup` knows about extensions
+impl std::str::FromStr for Mnemonic {
+    type Err = UnknownMnemonic;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        isa::BASE.iter()
+            .find(|d| d.name == s)
+            .map(|d| d.mnemonic)
+            .ok_or_else(|| UnknownMnemonic(s.to_string()))
+    }
+}
+
+// The variant name, `Addr` for `addr`
+impl std::fmt::Debug for Mnemonic {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Mnemonic::Ext(op) => write!(fmt, "Ext({})", op.name()),
+            base => {
+                let name = base.name();
+
+                write!(fmt, "{}{}", name[..1].to_uppercase(), &name[1..])
+            }
+        }
+    }
+}
+
+// Extensions are told apart by name
+impl PartialEq for Mnemonic {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Mnemonic { }
+
+impl std::hash::Hash for Mnemonic {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.key().hash(state)
+    }
+}
+
+impl PartialOrd for Mnemonic {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Mnemonic {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 enum Slot {
     Reg(Register),
     Immediate(Word)
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct Opcode {
     mnemonic: Mnemonic,
 
@@ -60,6 +239,15 @@ pub struct Opcode {
     c: Slot
 }
 
+impl Slot {
+    fn value(self) -> Word {
+        match self {
+            Slot::Reg(r) => r as Word,
+            Slot::Immediate(i) => i
+        }
+    }
+}
+
 impl std::fmt::Display for Slot {
     fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
@@ -71,72 +259,68 @@ impl std::fmt::Display for Slot {
 
 impl std::fmt::Display for Opcode {
     fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let mnem = match self.mnemonic {
-            Mnemonic::Addr => "addr",
-            Mnemonic::Addi => "addi",
-            Mnemonic::Mulr => "mulr",
-            Mnemonic::Muli => "muli",
-            Mnemonic::Banr => "banr",
-            Mnemonic::Bani => "bani",
-            Mnemonic::Borr => "borr",
-            Mnemonic::Bori => "bori",
-            Mnemonic::Setr => "setr",
-            Mnemonic::Seti => "seti",
-            Mnemonic::Gtir => "gtir",
-            Mnemonic::Gtri => "gtri",
-            Mnemonic::Gtrr => "gtrr",
-            Mnemonic::Eqir => "eqir",
-            Mnemonic::Eqri => "eqri",
-            Mnemonic::Eqrr => "eqrr"
-        };
-
-        write!(fmt, "{} {} {} {}", mnem, self.a, self.b, self.c)
+        write!(fmt, "{} {} {} {}", self.mnemonic, self.a, self.b, self.c)
     }
 }
 
 impl Opcode {
+    // Operands are registers or immediates as the mnemonic's shape says, unused ones become register 0.
+    // Register operands that do not even fit a `Register` make it fail, ones the machine lacks fault later.
     pub fn build(mnemonic: Mnemonic, a: Word, b: Word, c: Word) -> Option<Self> {
-        Opcode::decode(&[mnemonic as Word, a, b, c])
+        let shape = mnemonic.shape();
+        let slot = |operand, value| match operand {
+            Operand::Register => Register::try_from(value).ok().map(Slot::Reg),
+            Operand::Immediate => Some(Slot::Immediate(value)),
+            Operand::Unused => Some(Slot::Reg(0))
+        };
+
+        Some(Opcode {
+            mnemonic,
+
+            a: slot(shape.a, a)?,
+            b: slot(shape.b, b)?,
+            c: slot(shape.c, c)?
+        })
     }
 
     pub fn decode(raw: &[Word]) -> Option<Self> {
         if raw.len() != 4 {
             None
         } else {
-            let make = |m, a, b, c| Some(Opcode { mnemonic: m, a, b, c });
-            let make_rr = |m, a, b, c| make(m, Slot::Reg(a), Slot::Reg(b), Slot::Reg(c));
-            let make_ri = |m, a, b, c| make(m, Slot::Reg(a), Slot::Immediate(b), Slot::Reg(c));
-            let make_ir = |m, a, b, c| make(m, Slot::Immediate(a), Slot::Reg(b), Slot::Reg(c));
-
-            let (a, b, c) = (raw[1], raw[2], raw[3]);
-
-            match raw[0] {
-                0 => make_ri(Mnemonic::Bori, a as Register, b as Word, c as Register),
-                1 => make_ri(Mnemonic::Muli, a as Register, b as Word, c as Register),
-                2 => make_rr(Mnemonic::Banr, a as Register, b as Register, c as Register),
-                3 => make_ri(Mnemonic::Bani, a as Register, b as Word, c as Register),
-                4 => make_ir(Mnemonic::Gtir, a as Word, b as Register, c as Register),
-                5 => make_rr(Mnemonic::Setr, a as Register, 0 as Register, c as Register),
-                6 => make_rr(Mnemonic::Addr, a as Register, b as Register, c as Register),
-                7 => make_ir(Mnemonic::Eqir, a as Word, b as Register, c as Register),
-                8 => make_ir(Mnemonic::Seti, a as Word, 0 as Register, c as Register),
-                9 => make_ri(Mnemonic::Addi, a as Register, b as Word, c as Register),
-                10 => make_rr(Mnemonic::Eqrr, a as Register, b as Register, c as Register),
-                11 => make_ri(Mnemonic::Eqri, a as Register, b as Word, c as Register),
-                12 => make_rr(Mnemonic::Borr, a as Register, b as Register, c as Register),
-                13 => make_rr(Mnemonic::Gtrr, a as Register, b as Register, c as Register),
-                14 => make_rr(Mnemonic::Mulr, a as Register, b as Register, c as Register),
-                15 => make_ri(Mnemonic::Gtri, a as Register, b as Word, c as Register),
-
-                _ => None
-            }
+            Opcode::build(Mnemonic::from_number(raw[0])?, raw[1], raw[2], raw[3])
+        }
+    }
+
+    pub fn mnemonic(&self) -> Mnemonic {
+        self.mnemonic
+    }
+
+    // Operand values, whether they are registers or immediates is up to the
+    // mnemonic's shape. Unused operands are 0.
+    pub fn a(&self) -> Word {
+        self.a.value()
+    }
+
+    pub fn b(&self) -> Word {
+        self.b.value()
+    }
+
+    pub fn c(&self) -> Word {
+        self.c.value()
+    }
+
+    // Register the result gets stored to
+    pub fn target(&self) -> Option<Register> {
+        match self.c {
+            Slot::Reg(r) if self.mnemonic.shape().c == Operand::Register => Some(r),
+            _ => None
         }
     }
 
     // Iterating through all opcodes, used for deciphering the instruction set in part 1
     pub fn try_all<'a>(raw: &'a [Word]) -> OpcodeIterator<'a> {
         OpcodeIterator {
-            raw: raw,
+            raw,
             state: 0
         }
     }
@@ -155,20 +339,94 @@ impl<'a> Iterator for OpcodeIterator<'a> {
     fn next(&mut self) -> Option<Self::Item> {
         let (a, b, c) = (self.raw[1], self.raw[2], self.raw[3]);
 
-        let next = Opcode::decode(&[self.state as Word, a, b, c]);
+        // Mnemonics the operands do not fit are skipped
+        loop {
+            let mnemonic = Mnemonic::from_number(self.state as Word)?;
 
-        if next.is_some() {
             self.state += 1;
-        }
 
-        next
+            if let Some(op) = Opcode::build(mnemonic, a, b, c) {
+                return Some(op);
+            }
+        }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum AluError {
-    InvalidRegister,
-    CannotStoreToImmediate
+    // Register index outside of the register file
+    InvalidRegister(Register),
+    CannotStoreToImmediate,
+
+    // Result does not fit into a `Word` in `ArithmeticMode::Checked`
+    Overflow,
+
+    DivisionByZero
+}
+
+impl std::fmt::Display for AluError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AluError::InvalidRegister(r) => write!(fmt, "invalid register {}", r),
+            AluError::CannotStoreToImmediate => write!(fmt, "cannot store to an immediate"),
+            AluError::Overflow => write!(fmt, "arithmetic overflow"),
+            AluError::DivisionByZero => write!(fmt, "division by zero")
+        }
+    }
+}
+
+impl std::error::Error for AluError { }
+
+// What arithmetic does when the result does not fit into a `Word`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ArithmeticMode {
+    // Fail with `AluError::Overflow`
+    #[default]
+    Checked,
+
+    // Two's complement wrap around
+    Wrapping,
+
+    // Clamp to `Word::MIN` or `Word::MAX`
+    Saturating
+}
+
+impl ArithmeticMode {
+    #[inline(always)]
+    pub fn add(self, a: Word, b: Word) -> Result<Word, AluError> {
+        match self {
+            ArithmeticMode::Checked => a.checked_add(b).ok_or(AluError::Overflow),
+            ArithmeticMode::Wrapping => Ok(a.wrapping_add(b)),
+            ArithmeticMode::Saturating => Ok(a.saturating_add(b))
+        }
+    }
+
+    #[inline(always)]
+    pub fn mul(self, a: Word, b: Word) -> Result<Word, AluError> {
+        match self {
+            ArithmeticMode::Checked => a.checked_mul(b).ok_or(AluError::Overflow),
+            ArithmeticMode::Wrapping => Ok(a.wrapping_mul(b)),
+            ArithmeticMode::Saturating => Ok(a.saturating_mul(b))
+        }
+    }
+
+    // Only `Word::MIN / -1` overflows
+    pub fn div(self, a: Word, b: Word) -> Result<Word, AluError> {
+        match self {
+            _ if b == 0 => Err(AluError::DivisionByZero),
+            ArithmeticMode::Checked => a.checked_div(b).ok_or(AluError::Overflow),
+            ArithmeticMode::Wrapping => Ok(a.wrapping_div(b)),
+            ArithmeticMode::Saturating => Ok(a.saturating_div(b))
+        }
+    }
+
+    pub fn rem(self, a: Word, b: Word) -> Result<Word, AluError> {
+        match self {
+            _ if b == 0 => Err(AluError::DivisionByZero),
+            ArithmeticMode::Checked => a.checked_rem(b).ok_or(AluError::Overflow),
+            ArithmeticMode::Wrapping | ArithmeticMode::Saturating => Ok(a.wrapping_rem(b))
+        }
+    }
 }
 
 // Helper macros to simply loading and storing registers
@@ -177,13 +435,10 @@ macro_rules! load {
     ($slot:expr, $regs:expr) => {
         match $slot {
             Slot::Immediate(i) => Ok(i),
-            Slot::Reg(r) => if r as usize > $regs.len() {
-                println!("AAAA, {} sux", r);
-                Err(AluError::InvalidRegister)
-            } else {
-                Ok($regs[r as usize])
+            Slot::Reg(r) => match $regs.get(r as usize) {
+                Some(v) if r >= 0 => Ok(*v),
+                _ => Err(AluError::InvalidRegister(r))
             }
-
         }
     }
 }
@@ -192,55 +447,123 @@ macro_rules! store {
     ($slot:expr, $val:expr, $regs:expr) => {
         match $slot {
             Slot::Immediate(_i) => Err(AluError::CannotStoreToImmediate),
-            Slot::Reg(r) => if r as usize > $regs.len() {
-                Err(AluError::InvalidRegister)
-            } else {
-                $regs[r as usize] = $val;
+            Slot::Reg(r) => match $regs.get_mut(r as usize) {
+                Some(v) if r >= 0 => {
+                    *v = $val;
 
-                Ok(())
-            }
+                    Ok(())
+                },
 
+                _ => Err(AluError::InvalidRegister(r))
+            }
         }
     }
 }
 
-pub struct Alu {
-    pub regs: RegisterState
+// Runs an operation from outside the base instruction set on the registers
+pub(crate) fn extension(regs: &mut [Word], op: &dyn Operation, opcode: &Opcode, mode: ArithmeticMode) -> Result<Effect, AluError> {
+    let shape = op.shape();
+    let operand = |operand, slot| match operand {
+        Operand::Unused => Ok(0),
+        _ => load!(slot, regs)
+    };
+
+    let effect = op.execute(operand(shape.a, opcode.a)?, operand(shape.b, opcode.b)?, mode)?;
+
+    if let Effect::Store(value) = effect {
+        store!(opcode.c, value, regs)?;
+    }
+
+    Ok(effect)
 }
 
-pub type AluFunc<'a> = &'a dyn Fn(Word, Word) -> Word;
+// Register file of `N` registers, day16 has 4 of them and the elfcode machines 6
+#[derive(Clone)]
+pub struct Alu<const N: usize = REGISTER_COUNT> {
+    pub regs: RegisterState<N>,
+
+    mode: ArithmeticMode
+}
+
+pub type AluFunc<'a> = &'a dyn Fn(Word, Word) -> Result<Word, AluError>;
 
 pub const REGISTER_COUNT: usize = 6;
-pub type RegisterState = [Word; REGISTER_COUNT];
+pub type RegisterState<const N: usize = REGISTER_COUNT> = [Word; N];
+
+// Whether every register with an expected value holds exactly that value,
+// registers beyond the end of `expected` or set to `None` are ignored
+pub fn registers_match(regs: &[Word], expected: &[Option<Word>]) -> bool {
+    expected.len() <= regs.len() && regs.iter().zip(expected).all(|(r, e)| e.map(|e| *r == e).unwrap_or(true))
+}
 
 impl Alu {
     pub fn new() -> Self {
+        Alu::with_registers([0; REGISTER_COUNT])
+    }
+}
+
+impl<const N: usize> Default for Alu<N> {
+    fn default() -> Self {
+        Alu::with_registers([0; N])
+    }
+}
+
+impl<const N: usize> Alu<N> {
+    pub fn with_registers(regs: RegisterState<N>) -> Self {
         Alu {
-            regs: [Default::default(); REGISTER_COUNT]
+            regs,
+            mode: ArithmeticMode::default()
         }
     }
 
+    pub fn mode(&self) -> ArithmeticMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: ArithmeticMode) {
+        self.mode = mode;
+    }
+
+    pub fn register_count(&self) -> usize {
+        N
+    }
+
     pub fn eval(&mut self, opcode: &Opcode) -> Result<(), AluError> {
+        self.execute(opcode).map(|_| ())
+    }
+
+    // Like `eval`, but also tells what the instruction did
+    pub fn execute(&mut self, opcode: &Opcode) -> Result<Effect, AluError> {
+        let mode = self.mode;
         let r = &mut self.regs;
         let f = match opcode.mnemonic {
-            Mnemonic::Addi | Mnemonic::Addr => &(|a, b| a + b) as AluFunc,
-            Mnemonic::Muli | Mnemonic::Mulr => &(|a, b| a * b) as AluFunc,
-            Mnemonic::Bani | Mnemonic::Banr => &(|a, b| a & b) as AluFunc,
-            Mnemonic::Bori | Mnemonic::Borr => &(|a, b| a | b) as AluFunc,
-            Mnemonic::Seti | Mnemonic::Setr => &(|a, _| a) as AluFunc,
-            Mnemonic::Gtrr | Mnemonic::Gtri | Mnemonic::Gtir => &(|a, b| (a > b) as Word) as AluFunc,
-            Mnemonic::Eqrr | Mnemonic::Eqri | Mnemonic::Eqir => &(|a, b| (a == b) as Word) as AluFunc,
+            Mnemonic::Addi | Mnemonic::Addr => &(|a, b| mode.add(a, b)) as AluFunc,
+            Mnemonic::Muli | Mnemonic::Mulr => &(|a, b| mode.mul(a, b)) as AluFunc,
+            Mnemonic::Bani | Mnemonic::Banr => &(|a, b| Ok(a & b)) as AluFunc,
+            Mnemonic::Bori | Mnemonic::Borr => &(|a, b| Ok(a | b)) as AluFunc,
+            Mnemonic::Seti | Mnemonic::Setr => &(|a, _| Ok(a)) as AluFunc,
+            Mnemonic::Gtrr | Mnemonic::Gtri | Mnemonic::Gtir => &(|a, b| Ok((a > b) as Word)) as AluFunc,
+            Mnemonic::Eqrr | Mnemonic::Eqri | Mnemonic::Eqir => &(|a, b| Ok((a == b) as Word)) as AluFunc,
+            Mnemonic::Ext(op) => return extension(r, op, opcode, mode)
         };
 
         Alu::exec(r, opcode.a, opcode.b, opcode.c, f)
     }
 
-    fn exec(regs: &mut RegisterState, a: Slot, b: Slot, c: Slot, op: AluFunc) -> Result<(), AluError> {
-        store!(c, op(load!(a, regs)?, load!(b, regs)?), regs)
+    fn exec(regs: &mut RegisterState<N>, a: Slot, b: Slot, c: Slot, op: AluFunc) -> Result<Effect, AluError> {
+        let value = op(load!(a, regs)?, load!(b, regs)?)?;
+
+        store!(c, value, regs)?;
+
+        Ok(Effect::Store(value))
     }
 
-    // Part 1 only, force register override
-    pub fn set_registers(&mut self, new: RegisterState) {
+    // Force register override
+    pub fn set_registers(&mut self, new: RegisterState<N>) {
         self.regs = new;
     }
-}
\ No newline at end of file
+
+    pub fn matches(&self, expected: &[Option<Word>]) -> bool {
+        registers_match(&self.regs, expected)
+    }
+}
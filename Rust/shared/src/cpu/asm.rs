@@ -0,0 +1,336 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use super::isa::{self, InstructionSet};
+use super::{Opcode, Register, Word};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    UnknownDirective(String),
+    DuplicateDirective(String),
+    UnknownMnemonic(String),
+    MissingOperand,
+    TooManyOperands,
+    InvalidOperand(String),
+    InvalidRegister(Word),
+    InvalidLabel(String),
+    DuplicateLabel(String),
+    UnknownLabel(String),
+
+    // Anything but exactly one instruction where only that is allowed
+    NotAnInstruction(String)
+}
+
+// Line numbers start at 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub kind: ParseErrorKind
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "line {}: ", self.line)?;
+
+        match &self.kind {
+            ParseErrorKind::UnknownDirective(d) => write!(fmt, "unknown directive '{}'", d),
+            ParseErrorKind::DuplicateDirective(d) => write!(fmt, "directive '{}' given more than once", d),
+            ParseErrorKind::UnknownMnemonic(m) => write!(fmt, "unknown mnemonic '{}'", m),
+            ParseErrorKind::MissingOperand => write!(fmt, "missing operand"),
+            ParseErrorKind::TooManyOperands => write!(fmt, "too many operands"),
+            ParseErrorKind::InvalidOperand(o) => write!(fmt, "invalid operand '{}'", o),
+            ParseErrorKind::InvalidRegister(r) => write!(fmt, "invalid register {}", r),
+            ParseErrorKind::InvalidLabel(l) => write!(fmt, "invalid label name '{}'", l),
+            ParseErrorKind::DuplicateLabel(l) => write!(fmt, "label '{}' defined more than once", l),
+            ParseErrorKind::UnknownLabel(l) => write!(fmt, "unknown label '{}'", l),
+            ParseErrorKind::NotAnInstruction(s) => write!(fmt, "'{}' is not a single instruction", s)
+        }
+    }
+}
+
+impl std::error::Error for ParseError { }
+
+// An elfcode program as written in the puzzle inputs, optionally with an
+// instruction pointer binding given by `#ip N`. On top of that, sources may
+// contain comments (`;` or `//` up to the end of the line) and labels
+// (`name:`), which can be used as operands in place of a number, optionally
+// with an offset such as `loop-1`.
+#[derive(Debug, Clone, Default)]
+pub struct Program {
+    ip_register: Option<Register>,
+    instructions: Vec<Opcode>,
+    labels: BTreeMap<String, usize>
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        _ => false
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    let end = [line.find(';'), line.find("//")].iter().flatten().cloned().min();
+
+    match end {
+        Some(end) => &line[..end],
+        None => line
+    }
+}
+
+// Operand that is either a number or a label reference, resolved once all labels are known
+enum Operand<'a> {
+    Value(Word),
+    Label(&'a str, Word)
+}
+
+fn parse_operand(s: &str) -> Result<Operand<'_>, ParseErrorKind> {
+    if let Ok(v) = s.parse() {
+        return Ok(Operand::Value(v));
+    }
+
+    let (name, offset) = match s.find(['+', '-']) {
+        Some(pos) => {
+            let offset: Word = s[pos + 1..].parse().map_err(|_| ParseErrorKind::InvalidOperand(s.to_string()))?;
+
+            let offset = if &s[pos..=pos] == "-" { offset.checked_neg() } else { Some(offset) };
+
+            (&s[..pos], offset.ok_or_else(|| ParseErrorKind::InvalidOperand(s.to_string()))?)
+        },
+
+        None => (s, 0)
+    };
+
+    if is_identifier(name) {
+        Ok(Operand::Label(name, offset))
+    } else {
+        Err(ParseErrorKind::InvalidOperand(s.to_string()))
+    }
+}
+
+impl Program {
+    pub fn new(ip_register: Option<Register>, instructions: Vec<Opcode>) -> Self {
+        Program {
+            ip_register,
+            instructions,
+            labels: BTreeMap::new()
+        }
+    }
+
+    pub fn parse(source: &str) -> Result<Self, ParseError> {
+        Program::parse_with(source, &InstructionSet::base())
+    }
+
+    // Parses a program that may use the operations registered with the instruction set.
+    // Trailing operands an operation does not use can be left out.
+    pub fn parse_with(source: &str, set: &InstructionSet) -> Result<Self, ParseError> {
+        let mut program = Program::default();
+        let mut pending = Vec::new();
+
+        for (n, line) in source.lines().enumerate() {
+            let error = |kind| ParseError { line: n + 1, kind };
+            let mut line = strip_comment(line).trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            if line.starts_with('#') {
+                let mut parts = line.split_whitespace();
+
+                match parts.next() {
+                    Some("#ip") => {
+                        if program.ip_register.is_some() {
+                            return Err(error(ParseErrorKind::DuplicateDirective("#ip".to_string())));
+                        }
+
+                        let reg = parts.next().ok_or_else(|| error(ParseErrorKind::MissingOperand))?;
+                        let reg: Word = reg.parse().map_err(|_| error(ParseErrorKind::InvalidOperand(reg.to_string())))?;
+
+                        if reg < 0 || reg > Register::MAX as Word {
+                            return Err(error(ParseErrorKind::InvalidRegister(reg)));
+                        }
+
+                        if parts.next().is_some() {
+                            return Err(error(ParseErrorKind::TooManyOperands));
+                        }
+
+                        program.ip_register = Some(reg as Register);
+                    },
+
+                    Some(other) => return Err(error(ParseErrorKind::UnknownDirective(other.to_string()))),
+                    None => unreachable!()
+                }
+
+                continue;
+            }
+
+            if let Some(colon) = line.find(':') {
+                let label = line[..colon].trim();
+
+                if !is_identifier(label) {
+                    return Err(error(ParseErrorKind::InvalidLabel(label.to_string())));
+                }
+
+                if program.labels.insert(label.to_string(), pending.len()).is_some() {
+                    return Err(error(ParseErrorKind::DuplicateLabel(label.to_string())));
+                }
+
+                line = line[colon + 1..].trim();
+
+                if line.is_empty() {
+                    continue;
+                }
+            }
+
+            let mut parts = line.split_whitespace();
+
+            let mnem = parts.next().unwrap();
+            let mnem = set.lookup(mnem).ok_or_else(|| error(ParseErrorKind::UnknownMnemonic(mnem.to_string())))?;
+            let shape = mnem.shape();
+
+            let mut operands = Vec::with_capacity(3);
+
+            for kind in [shape.a, shape.b, shape.c].iter() {
+                let op = match parts.next() {
+                    Some(op) => parse_operand(op).map_err(error)?,
+                    None if *kind == isa::Operand::Unused => Operand::Value(0),
+                    None => return Err(error(ParseErrorKind::MissingOperand))
+                };
+
+                operands.push(op);
+            }
+
+            if parts.next().is_some() {
+                return Err(error(ParseErrorKind::TooManyOperands));
+            }
+
+            pending.push((n + 1, mnem, operands));
+        }
+
+        for (line, mnem, operands) in pending {
+            let error = |kind| ParseError { line, kind };
+            let mut values = [0; 3];
+
+            for (value, operand) in values.iter_mut().zip(operands) {
+                *value = match operand {
+                    Operand::Value(v) => v,
+                    Operand::Label(name, offset) => match program.labels.get(name) {
+                        Some(addr) => (*addr as Word).checked_add(offset).ok_or_else(|| error(ParseErrorKind::InvalidOperand(format!("{}{:+}", name, offset))))?,
+                        None => return Err(error(ParseErrorKind::UnknownLabel(name.to_string())))
+                    }
+                };
+            }
+
+            // Register operands have to fit, immediates and unused operands can be anything
+            let shape = mnem.shape();
+
+            for (operand, value) in [shape.a, shape.b, shape.c].iter().zip(values.iter()) {
+                if *operand == isa::Operand::Register && (*value < 0 || *value > Register::MAX as Word) {
+                    return Err(error(ParseErrorKind::InvalidRegister(*value)));
+                }
+            }
+
+            program.instructions.push(Opcode::build(mnem, values[0], values[1], values[2]).unwrap());
+        }
+
+        Ok(program)
+    }
+
+    pub fn ip_register(&self) -> Option<Register> {
+        self.ip_register
+    }
+
+    pub fn instructions(&self) -> &[Opcode] {
+        &self.instructions
+    }
+
+    pub fn get(&self, ip: usize) -> Option<&Opcode> {
+        self.instructions.get(ip)
+    }
+
+    pub fn len(&self) -> usize {
+        self.instructions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instructions.is_empty()
+    }
+
+    pub fn label_address(&self, label: &str) -> Option<usize> {
+        self.labels.get(label).cloned()
+    }
+
+    // Labels pointing at the given address, in alphabetical order
+    pub fn labels_at(&self, ip: usize) -> impl Iterator<Item = &str> {
+        self.labels.iter().filter(move |(_, &addr)| addr == ip).map(|(l, _)| l.as_str())
+    }
+
+    // FNV-1a hash of the ip binding and instructions, ignoring labels and
+    // comments. Stays the same across builds, so it can be written to files.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        let mut feed = |s: &str| {
+            for byte in s.bytes().chain(Some(b'\n')) {
+                hash = (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+            }
+        };
+
+        if let Some(ip) = self.ip_register {
+            feed(&format!("#ip {}", ip));
+        }
+
+        for op in &self.instructions {
+            feed(&op.to_string());
+        }
+
+        hash
+    }
+}
+
+impl FromStr for Program {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Program::parse(s)
+    }
+}
+
+// A single base instruction as `Display` writes it, such as `addi 1 2 3`
+impl FromStr for Opcode {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let program = Program::parse(s)?;
+
+        match program.instructions() {
+            [op] if program.ip_register.is_none() && program.labels.is_empty() => Ok(*op),
+            _ => Err(ParseError { line: 1, kind: ParseErrorKind::NotAnInstruction(s.trim().to_string()) })
+        }
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ip) = self.ip_register {
+            writeln!(fmt, "#ip {}", ip)?;
+        }
+
+        for (ip, op) in self.instructions.iter().enumerate() {
+            for label in self.labels_at(ip) {
+                writeln!(fmt, "{}:", label)?;
+            }
+
+            writeln!(fmt, "{}", op)?;
+        }
+
+        for label in self.labels_at(self.instructions.len()) {
+            writeln!(fmt, "{}:", label)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,294 @@
+use std::collections::BTreeSet;
+use std::fmt;
+
+use super::{InstructionSet, Mnemonic, Opcode, OpcodeMap, Operand, Program, Register, Slot, Word};
+
+// Compiled programs, laid out as:
+//
+//   magic      "ELFC"
+//   version    1 byte, `VERSION`
+//   flags      1 byte, bit 0 set if the ip is bound
+//   ip         register the ip is bound to, only if flagged
+//   mapping    number of entries, then every opcode number followed by the
+//              length and bytes of its mnemonic's name
+//   code       number of instructions, then each one's opcode number followed
+//              by the operands its shape uses
+//   checksum   FNV-1a hash of all bytes before it, 8 bytes little endian
+//
+// All other numbers are zigzag encoded LEB128 varints, so small values of
+// either sign take a single byte. Labels are not kept.
+pub const MAGIC: &[u8; 4] = b"ELFC";
+pub const VERSION: u8 = 1;
+
+const IP_BOUND: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BytecodeErrorKind {
+    BadMagic,
+    UnsupportedVersion(u8),
+    UnknownFlags(u8),
+    Truncated,
+
+    // A varint that does not fit into a `Word`
+    Overlong,
+
+    UnknownMnemonic(String),
+    DuplicateMnemonic(String),
+    DuplicateOpcode(Word),
+    UnknownOpcode(Word),
+    InvalidRegister(Word),
+    ChecksumMismatch,
+    TrailingBytes
+}
+
+// Offsets count bytes from the start of the input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BytecodeError {
+    pub offset: usize,
+    pub kind: BytecodeErrorKind
+}
+
+impl fmt::Display for BytecodeError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "offset {}: ", self.offset)?;
+
+        match &self.kind {
+            BytecodeErrorKind::BadMagic => write!(fmt, "not elfcode bytecode"),
+            BytecodeErrorKind::UnsupportedVersion(v) => write!(fmt, "unsupported version {}", v),
+            BytecodeErrorKind::UnknownFlags(f) => write!(fmt, "unknown flags {:#04x}", f),
+            BytecodeErrorKind::Truncated => write!(fmt, "unexpected end of input"),
+            BytecodeErrorKind::Overlong => write!(fmt, "number too large"),
+            BytecodeErrorKind::UnknownMnemonic(m) => write!(fmt, "unknown mnemonic '{}'", m),
+            BytecodeErrorKind::DuplicateMnemonic(m) => write!(fmt, "mnemonic '{}' mapped more than once", m),
+            BytecodeErrorKind::DuplicateOpcode(n) => write!(fmt, "opcode {} mapped more than once", n),
+            BytecodeErrorKind::UnknownOpcode(n) => write!(fmt, "unknown opcode {}", n),
+            BytecodeErrorKind::InvalidRegister(r) => write!(fmt, "invalid register {}", r),
+            BytecodeErrorKind::ChecksumMismatch => write!(fmt, "checksum does not match the data"),
+            BytecodeErrorKind::TrailingBytes => write!(fmt, "unexpected data after the program")
+        }
+    }
+}
+
+impl std::error::Error for BytecodeError { }
+
+fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+fn write_varint(out: &mut Vec<u8>, value: Word) {
+    let mut zigzag = ((value << 1) ^ (value >> 63)) as u64;
+
+    loop {
+        let byte = (zigzag & 0x7f) as u8;
+
+        zigzag >>= 7;
+
+        if zigzag == 0 {
+            out.push(byte);
+            return;
+        }
+
+        out.push(byte | 0x80);
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize
+}
+
+impl<'a> Reader<'a> {
+    fn error(&self, kind: BytecodeErrorKind) -> BytecodeError {
+        BytecodeError { offset: self.offset, kind }
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], BytecodeError> {
+        let bytes = self.bytes.get(self.offset..self.offset + n).ok_or_else(|| self.error(BytecodeErrorKind::Truncated))?;
+
+        self.offset += n;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, BytecodeError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn varint(&mut self) -> Result<Word, BytecodeError> {
+        let start = self.offset;
+        let mut zigzag: u64 = 0;
+
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+
+            if shift == 63 && byte > 1 {
+                break;
+            }
+
+            zigzag |= ((byte & 0x7f) as u64) << shift;
+
+            if byte & 0x80 == 0 {
+                return Ok((zigzag >> 1) as Word ^ -((zigzag & 1) as Word));
+            }
+        }
+
+        Err(BytecodeError { offset: start, kind: BytecodeErrorKind::Overlong })
+    }
+
+    // A varint used as a count or length, which cannot be more than what is left
+    fn count(&mut self) -> Result<usize, BytecodeError> {
+        let start = self.offset;
+        let n = self.varint()?;
+
+        if n < 0 || n as usize > self.bytes.len() - self.offset {
+            Err(BytecodeError { offset: start, kind: BytecodeErrorKind::Truncated })
+        } else {
+            Ok(n as usize)
+        }
+    }
+}
+
+// Encodes the program with `map` numbering the instructions. Mnemonics the map
+// has no number for, like extensions, are numbered after its highest one.
+pub fn encode(program: &Program, map: &OpcodeMap) -> Vec<u8> {
+    let mut numbers = map.iter().collect::<Vec<_>>();
+
+    for op in program.instructions() {
+        if !numbers.iter().any(|(_, m)| *m == op.mnemonic) {
+            let next = numbers.iter().map(|(n, _)| n + 1).max().unwrap_or(0);
+
+            numbers.push((next, op.mnemonic));
+        }
+    }
+
+    let mut out = MAGIC.to_vec();
+
+    out.push(VERSION);
+
+    match program.ip_register() {
+        Some(ip) => {
+            out.push(IP_BOUND);
+            write_varint(&mut out, ip as Word);
+        },
+
+        None => out.push(0)
+    }
+
+    write_varint(&mut out, numbers.len() as Word);
+
+    for (number, mnemonic) in &numbers {
+        write_varint(&mut out, *number);
+        write_varint(&mut out, mnemonic.name().len() as Word);
+        out.extend_from_slice(mnemonic.name().as_bytes());
+    }
+
+    write_varint(&mut out, program.len() as Word);
+
+    for op in program.instructions() {
+        let shape = op.mnemonic.shape();
+        let number = numbers.iter().find(|(_, m)| *m == op.mnemonic).unwrap().0;
+
+        write_varint(&mut out, number);
+
+        for (operand, slot) in [(shape.a, op.a), (shape.b, op.b), (shape.c, op.c)].iter() {
+            match (operand, slot) {
+                (Operand::Unused, _) => { },
+                (_, Slot::Reg(r)) => write_varint(&mut out, *r as Word),
+                (_, Slot::Immediate(i)) => write_varint(&mut out, *i)
+            }
+        }
+    }
+
+    let sum = checksum(&out);
+
+    out.extend_from_slice(&sum.to_le_bytes());
+    out
+}
+
+// Reads back what `encode` wrote, looking up mnemonics beyond the base
+// instructions in `set`. Returns the numbering along with the program.
+pub fn decode(bytes: &[u8], set: &InstructionSet) -> Result<(Program, OpcodeMap), BytecodeError> {
+    let mut reader = Reader { bytes, offset: 0 };
+
+    if reader.bytes(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+        return Err(BytecodeError { offset: 0, kind: BytecodeErrorKind::BadMagic });
+    }
+
+    let version = reader.byte()?;
+
+    if version != VERSION {
+        return Err(BytecodeError { offset: reader.offset - 1, kind: BytecodeErrorKind::UnsupportedVersion(version) });
+    }
+
+    let flags = reader.byte()?;
+
+    if flags & !IP_BOUND != 0 {
+        return Err(BytecodeError { offset: reader.offset - 1, kind: BytecodeErrorKind::UnknownFlags(flags) });
+    }
+
+    let register = |reader: &mut Reader| -> Result<Register, BytecodeError> {
+        let start = reader.offset;
+        let r = reader.varint()?;
+
+        if r < 0 || r > Register::MAX as Word {
+            Err(BytecodeError { offset: start, kind: BytecodeErrorKind::InvalidRegister(r) })
+        } else {
+            Ok(r as Register)
+        }
+    };
+
+    let ip_register = if flags & IP_BOUND != 0 { Some(register(&mut reader)?) } else { None };
+
+    let mut numbers = Vec::new();
+    let mut seen = BTreeSet::new();
+
+    for _ in 0..reader.count()? {
+        let start = reader.offset;
+        let number = reader.varint()?;
+        let len = reader.count()?;
+        let name = String::from_utf8_lossy(reader.bytes(len)?);
+        let mnemonic = set.lookup(&name).ok_or_else(|| BytecodeError { offset: start, kind: BytecodeErrorKind::UnknownMnemonic(name.to_string()) })?;
+
+        if !seen.insert(number) {
+            return Err(BytecodeError { offset: start, kind: BytecodeErrorKind::DuplicateOpcode(number) });
+        }
+
+        if numbers.iter().any(|(_, m)| *m == mnemonic) {
+            return Err(BytecodeError { offset: start, kind: BytecodeErrorKind::DuplicateMnemonic(name.to_string()) });
+        }
+
+        numbers.push((number, mnemonic));
+    }
+
+    let map = numbers.into_iter().collect::<OpcodeMap>();
+    let mut instructions = Vec::new();
+
+    for _ in 0..reader.count()? {
+        let start = reader.offset;
+        let number = reader.varint()?;
+        let mnemonic: Mnemonic = map.get(number).ok_or(BytecodeError { offset: start, kind: BytecodeErrorKind::UnknownOpcode(number) })?;
+        let shape = mnemonic.shape();
+        let mut values = [0; 3];
+
+        for (value, operand) in values.iter_mut().zip([shape.a, shape.b, shape.c].iter()) {
+            *value = match operand {
+                Operand::Register => register(&mut reader)? as Word,
+                Operand::Immediate => reader.varint()?,
+                Operand::Unused => 0
+            };
+        }
+
+        instructions.push(Opcode::build(mnemonic, values[0], values[1], values[2]).unwrap());
+    }
+
+    let sum = checksum(&bytes[..reader.offset]);
+
+    if reader.bytes(8)? != sum.to_le_bytes() {
+        return Err(BytecodeError { offset: reader.offset - 8, kind: BytecodeErrorKind::ChecksumMismatch });
+    }
+
+    if reader.offset != bytes.len() {
+        return Err(reader.error(BytecodeErrorKind::TrailingBytes));
+    }
+
+    Ok((Program::new(ip_register, instructions), map))
+}
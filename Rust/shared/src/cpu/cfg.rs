@@ -0,0 +1,506 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use super::{Alu, Mnemonic, Opcode, Operand, Program, Register, Slot, Word};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Target {
+    Addr(usize),
+
+    // Anywhere outside of the program, which halts the machine
+    Exit
+}
+
+// How control leaves a single instruction
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Flow {
+    Next,
+    Jump(Target),
+
+    // `addr ip rX ip` on a comparison result: falls through, or skips the next instruction if rX is set
+    Branch { flag: Register, taken: Target },
+
+    // Stores something to the ip register that is only known at runtime
+    Computed
+}
+
+// Operands an instruction actually reads, set* ignore their B operand
+fn sources(op: &Opcode) -> Vec<Slot> {
+    let shape = op.mnemonic.shape();
+
+    [(shape.a, op.a), (shape.b, op.b)].iter().filter(|(operand, _)| *operand != Operand::Unused).map(|(_, slot)| *slot).collect()
+}
+
+pub(crate) fn is_comparison(mnemonic: Mnemonic) -> bool {
+    matches!(mnemonic,
+        Mnemonic::Gtir | Mnemonic::Gtri | Mnemonic::Gtrr |
+        Mnemonic::Eqir | Mnemonic::Eqri | Mnemonic::Eqrr)
+}
+
+// Value stored to the ip register if it does not depend on anything but the ip itself
+fn resolve(op: &Opcode, ip: usize, ip_register: Register) -> Option<Word> {
+    let is_static = sources(op).iter().all(|s| match s {
+        Slot::Reg(r) => *r == ip_register,
+        Slot::Immediate(_) => true
+    });
+
+    if !is_static {
+        return None;
+    }
+
+    let mut alu = Alu::new();
+
+    *alu.regs.get_mut(ip_register as usize)? = ip as Word;
+    alu.eval(op).ok()?;
+
+    Some(alu.regs[ip_register as usize])
+}
+
+// Register added to the ip by `addr ip rX ip` or `addr rX ip ip`
+pub(crate) fn offset_register(op: &Opcode, ip_register: Register) -> Option<Register> {
+    match (op.mnemonic, op.a, op.b) {
+        (Mnemonic::Addr, Slot::Reg(a), Slot::Reg(b)) if a == ip_register && b != ip_register => Some(b),
+        (Mnemonic::Addr, Slot::Reg(a), Slot::Reg(b)) if b == ip_register && a != ip_register => Some(a),
+        _ => None
+    }
+}
+
+// Classifies every instruction of a program by where it continues. Without an
+// ip binding, every instruction simply falls through to the next one.
+pub fn flow(program: &Program) -> Vec<Flow> {
+    let len = program.len();
+    let target = |addr: Word| if addr >= 0 && (addr as usize) < len { Target::Addr(addr as usize) } else { Target::Exit };
+
+    let ipr = match program.ip_register() {
+        Some(ipr) => ipr,
+        None => return vec![Flow::Next; len]
+    };
+
+    let ops = program.instructions();
+
+    let mut flows = ops.iter().enumerate().map(|(ip, op)| {
+        if op.target() != Some(ipr) {
+            Flow::Next
+        } else if let Some(to) = resolve(op, ip, ipr) {
+            // Past `Word::MAX` is outside of the program as well
+            Flow::Jump(to.checked_add(1).map(target).unwrap_or(Target::Exit))
+        } else {
+            Flow::Computed
+        }
+    }).collect::<Vec<_>>();
+
+    // Adding a register to the ip right after comparing into it is a conditional
+    // skip, unless something else jumps in between where the register may hold anything
+    let candidates = (1..len).filter_map(|ip| {
+        let flag = offset_register(&ops[ip], ipr)?;
+        let prev = &ops[ip - 1];
+
+        if flows[ip] == Flow::Computed && is_comparison(prev.mnemonic) && prev.target() == Some(flag) {
+            Some((ip, flag))
+        } else {
+            None
+        }
+    }).collect::<Vec<_>>();
+
+    let mut targets = flows.iter().filter_map(|f| match f {
+        Flow::Jump(Target::Addr(to)) => Some(*to),
+        _ => None
+    }).collect::<BTreeSet<_>>();
+
+    targets.extend(candidates.iter().map(|(ip, _)| ip + 2));
+
+    for (ip, flag) in candidates {
+        if !targets.contains(&ip) {
+            flows[ip] = Flow::Branch { flag, taken: target(ip as Word + 2) };
+        }
+    }
+
+    flows
+}
+
+#[derive(Debug, Clone)]
+pub struct BasicBlock {
+    // Instructions [start, end)
+    pub start: usize,
+    pub end: usize,
+
+    pub successors: Vec<Target>,
+    pub predecessors: Vec<usize>,
+
+    // Whether the block ends in a jump that could not be resolved
+    pub computed: bool
+}
+
+impl BasicBlock {
+    pub fn contains(&self, ip: usize) -> bool {
+        ip >= self.start && ip < self.end
+    }
+}
+
+// A natural loop, everything that reaches one of the back edges without passing the head
+#[derive(Debug, Clone)]
+pub struct Loop {
+    pub head: usize,
+    pub blocks: BTreeSet<usize>,
+
+    // Blocks jumping back to the head
+    pub latches: BTreeSet<usize>
+}
+
+// Control flow graph over basic blocks, block 0 being the entry. Blocks are
+// identified by their index, successors are the addresses they continue at.
+// Targets of unresolved computed jumps are unknown, so blocks only they can
+// reach are taken as further roots (see `roots`). Blocks that are reachable
+// otherwise may still be missing edges from them.
+#[derive(Debug, Clone)]
+pub struct Cfg {
+    flows: Vec<Flow>,
+    blocks: Vec<BasicBlock>,
+
+    // Block index of every instruction
+    block_of: Vec<usize>,
+
+    idom: Vec<Option<usize>>
+}
+
+impl Cfg {
+    pub fn new(program: &Program) -> Self {
+        let flows = flow(program);
+        let len = flows.len();
+
+        let mut leaders = BTreeSet::new();
+
+        if len > 0 {
+            leaders.insert(0);
+        }
+
+        for (ip, f) in flows.iter().enumerate() {
+            match *f {
+                Flow::Next => continue,
+                Flow::Jump(Target::Addr(to)) | Flow::Branch { taken: Target::Addr(to), .. } => {
+                    leaders.insert(to);
+                },
+
+                _ => { }
+            }
+
+            if ip + 1 < len {
+                leaders.insert(ip + 1);
+            }
+        }
+
+        let starts = leaders.into_iter().collect::<Vec<_>>();
+        let mut block_of = vec![0; len];
+
+        let mut blocks = starts.iter().enumerate().map(|(b, &start)| {
+            let end = starts.get(b + 1).cloned().unwrap_or(len);
+
+            for block in &mut block_of[start..end] {
+                *block = b;
+            }
+
+            let last = end - 1;
+            let next = if end < len { Target::Addr(end) } else { Target::Exit };
+
+            let successors = match flows[last] {
+                Flow::Next => vec![next],
+                Flow::Jump(to) => vec![to],
+                Flow::Branch { taken, .. } => vec![next, taken],
+                Flow::Computed => vec![]
+            };
+
+            BasicBlock {
+                start,
+                end,
+                successors,
+                predecessors: Vec::new(),
+                computed: flows[last] == Flow::Computed
+            }
+        }).collect::<Vec<_>>();
+
+        for b in 0..blocks.len() {
+            for s in blocks[b].successors.clone() {
+                if let Target::Addr(to) = s {
+                    let pred = &mut blocks[block_of[to]].predecessors;
+
+                    if !pred.contains(&b) {
+                        pred.push(b);
+                    }
+                }
+            }
+        }
+
+        let mut cfg = Cfg {
+            flows,
+            blocks,
+            block_of,
+            idom: Vec::new()
+        };
+
+        cfg.idom = cfg.compute_dominators();
+        cfg
+    }
+
+    pub fn flows(&self) -> &[Flow] {
+        &self.flows
+    }
+
+    pub fn blocks(&self) -> &[BasicBlock] {
+        &self.blocks
+    }
+
+    pub fn block_of(&self, ip: usize) -> Option<usize> {
+        self.block_of.get(ip).cloned()
+    }
+
+    // Block indices of the successors within the program
+    pub fn successor_blocks(&self, block: usize) -> impl Iterator<Item = usize> + '_ {
+        self.blocks[block].successors.iter().filter_map(move |s| match s {
+            Target::Addr(to) => Some(self.block_of[*to]),
+            Target::Exit => None
+        })
+    }
+
+    // Where execution may start: the entry, and if there are computed jumps every block
+    // not reachable otherwise, as any of them may be their target. Blocks nothing jumps
+    // to are picked before the ones they lead to, so those still get dominators.
+    pub fn roots(&self) -> Vec<usize> {
+        let mut roots = Vec::new();
+
+        if self.blocks.is_empty() {
+            return roots;
+        }
+
+        roots.push(0);
+
+        if !self.blocks.iter().any(|b| b.computed) {
+            return roots;
+        }
+
+        let mut reached = vec![false; self.blocks.len()];
+        let mut stack = vec![0];
+
+        loop {
+            while let Some(b) = stack.pop() {
+                if !reached[b] {
+                    reached[b] = true;
+                    stack.extend(self.successor_blocks(b));
+                }
+            }
+
+            let unreached = (0..self.blocks.len()).filter(|&b| !reached[b]).collect::<Vec<_>>();
+            let next = unreached.iter().find(|&&b| self.blocks[b].predecessors.is_empty()).or_else(|| unreached.first());
+
+            match next {
+                Some(&b) => {
+                    roots.push(b);
+                    stack.push(b);
+                },
+
+                None => return roots
+            }
+        }
+    }
+
+    // Blocks in reverse postorder from the roots, the entry first. Unreachable ones are left out.
+    pub fn reverse_postorder(&self) -> Vec<usize> {
+        let mut order = Vec::new();
+        let mut visited = vec![false; self.blocks.len()];
+
+        // The last root is on top, so the entry is finished last and comes out first
+        let mut stack = self.roots().into_iter().map(|r| (r, false)).collect::<Vec<_>>();
+
+        while let Some((b, done)) = stack.pop() {
+            if done {
+                order.push(b);
+                continue;
+            }
+
+            if visited[b] {
+                continue;
+            }
+
+            visited[b] = true;
+            stack.push((b, true));
+
+            for s in self.successor_blocks(b).collect::<Vec<_>>().into_iter().rev() {
+                if !visited[s] {
+                    stack.push((s, false));
+                }
+            }
+        }
+
+        order.reverse();
+        order
+    }
+
+    // Iterative dominators as described by Cooper, Harvey and Kennedy, with a virtual
+    // block above all roots. Blocks dominated by nothing but that one are their own.
+    fn compute_dominators(&self) -> Vec<Option<usize>> {
+        let len = self.blocks.len();
+        let virt = len;
+
+        let roots = self.roots();
+        let order = self.reverse_postorder();
+        let mut rank = vec![usize::MAX; len + 1];
+
+        rank[virt] = 0;
+
+        for (i, &b) in order.iter().enumerate() {
+            rank[b] = i + 1;
+        }
+
+        let mut idom = vec![None; len + 1];
+
+        idom[virt] = Some(virt);
+
+        for &r in &roots {
+            idom[r] = Some(virt);
+        }
+
+        let intersect = |idom: &[Option<usize>], mut a: usize, mut b: usize| {
+            while a != b {
+                while rank[a] > rank[b] {
+                    a = idom[a].unwrap();
+                }
+
+                while rank[b] > rank[a] {
+                    b = idom[b].unwrap();
+                }
+            }
+
+            a
+        };
+
+        let mut changed = true;
+
+        while changed {
+            changed = false;
+
+            for &b in order.iter().filter(|b| !roots.contains(b)) {
+                let new = self.blocks[b].predecessors
+                    .iter()
+                    .filter(|&&p| idom[p].is_some())
+                    .fold(None, |acc, &p| match acc {
+                        None => Some(p),
+                        Some(a) => Some(intersect(&idom, a, p))
+                    });
+
+                if new != idom[b] {
+                    idom[b] = new;
+                    changed = true;
+                }
+            }
+        }
+
+        idom.truncate(len);
+
+        for (b, d) in idom.iter_mut().enumerate() {
+            if *d == Some(virt) {
+                *d = Some(b);
+            }
+        }
+
+        idom
+    }
+
+    // Immediate dominator of a block, roots being their own and unreachable blocks having none
+    pub fn immediate_dominator(&self, block: usize) -> Option<usize> {
+        self.idom[block]
+    }
+
+    pub fn dominates(&self, a: usize, b: usize) -> bool {
+        let mut b = b;
+
+        loop {
+            if a == b {
+                return true;
+            }
+
+            match self.idom[b] {
+                Some(d) if d != b => b = d,
+                _ => return false
+            }
+        }
+    }
+
+    // Natural loops, one per head, ordered by their head
+    pub fn loops(&self) -> Vec<Loop> {
+        let mut loops: Vec<Loop> = Vec::new();
+
+        for b in 0..self.blocks.len() {
+            for h in self.successor_blocks(b).collect::<Vec<_>>() {
+                if self.idom[b].is_none() || !self.dominates(h, b) {
+                    continue;
+                }
+
+                let pos = match loops.iter().position(|l| l.head == h) {
+                    Some(pos) => pos,
+                    None => {
+                        loops.push(Loop { head: h, blocks: vec![h].into_iter().collect(), latches: BTreeSet::new() });
+                        loops.len() - 1
+                    }
+                };
+
+                let lp = &mut loops[pos];
+                let mut stack = vec![b];
+
+                lp.latches.insert(b);
+
+                while let Some(n) = stack.pop() {
+                    if lp.blocks.insert(n) {
+                        stack.extend(self.blocks[n].predecessors.iter().filter(|&&p| self.idom[p].is_some()));
+                    }
+                }
+            }
+        }
+
+        loops.sort_by_key(|l| l.head);
+        loops
+    }
+
+    // Graphviz rendering, one box per block listing its instructions
+    pub fn to_dot(&self, program: &Program) -> String {
+        let mut dot = String::new();
+
+        writeln!(dot, "digraph cfg {{").unwrap();
+        writeln!(dot, "    node [shape=box, fontname=monospace];").unwrap();
+
+        let mut exit = false;
+        let mut unknown = false;
+
+        for (b, block) in self.blocks.iter().enumerate() {
+            let body = (block.start..block.end)
+                .map(|ip| format!("{:>3}: {}\\l", ip, program.instructions()[ip]))
+                .collect::<String>();
+
+            writeln!(dot, "    b{} [label=\"{}\"];", b, body).unwrap();
+
+            for s in &block.successors {
+                match s {
+                    Target::Addr(to) => writeln!(dot, "    b{} -> b{};", b, self.block_of[*to]).unwrap(),
+                    Target::Exit => {
+                        exit = true;
+                        writeln!(dot, "    b{} -> exit;", b).unwrap();
+                    }
+                }
+            }
+
+            if block.computed {
+                unknown = true;
+                writeln!(dot, "    b{} -> unknown [style=dashed];", b).unwrap();
+            }
+        }
+
+        if exit {
+            writeln!(dot, "    exit [shape=doublecircle];").unwrap();
+        }
+
+        if unknown {
+            writeln!(dot, "    unknown [shape=circle, label=\"?\"];").unwrap();
+        }
+
+        writeln!(dot, "}}").unwrap();
+        dot
+    }
+}
@@ -0,0 +1,525 @@
+use super::optimize::{self, Macro};
+use super::{extension, Alu, AluError, ArithmeticMode, Effect, Fault, Halt, Machine, Mnemonic, Opcode, Program, Register, RegisterState, Slot, Snapshot, Word, REGISTER_COUNT};
+
+// An instruction with its operand kinds resolved and its registers validated
+// ahead of time, so executing it is a single match without any further checks
+#[derive(Debug, Copy, Clone)]
+enum Op {
+    AddRR(usize, usize, usize),
+    AddRI(usize, Word, usize),
+    MulRR(usize, usize, usize),
+    MulRI(usize, Word, usize),
+    BanRR(usize, usize, usize),
+    BanRI(usize, Word, usize),
+    BorRR(usize, usize, usize),
+    BorRI(usize, Word, usize),
+    SetR(usize, usize),
+    SetI(Word, usize),
+    GtIR(Word, usize, usize),
+    GtRI(usize, Word, usize),
+    GtRR(usize, usize, usize),
+    EqIR(Word, usize, usize),
+    EqRI(usize, Word, usize),
+    EqRR(usize, usize, usize),
+
+    // Would fail on the ALU every time it is executed
+    Fault(AluError),
+
+    // Index of a whole loop replaced by the optimizer
+    Macro(usize),
+
+    // Instruction at the given address evaluated the way `Alu::execute` does,
+    // along with the register it stores to. Used for operations from outside
+    // the base instruction set, and for instructions storing to an invalid
+    // register, which only fault once their value is computed.
+    Alu(usize, Option<usize>)
+}
+
+// Why an instruction did more than storing a value
+#[derive(Debug, Copy, Clone)]
+enum Trap {
+    Fault(AluError),
+    Output(Word),
+    Stop
+}
+
+impl From<AluError> for Trap {
+    fn from(e: AluError) -> Self {
+        Trap::Fault(e)
+    }
+}
+
+fn register(slot: Slot) -> Result<usize, AluError> {
+    match slot {
+        Slot::Reg(r) if r >= 0 && (r as usize) < REGISTER_COUNT => Ok(r as usize),
+        Slot::Reg(r) => Err(AluError::InvalidRegister(r)),
+        Slot::Immediate(_) => Err(AluError::CannotStoreToImmediate)
+    }
+}
+
+fn immediate(slot: Slot) -> Word {
+    match slot {
+        Slot::Immediate(i) => i,
+        Slot::Reg(r) => r as Word
+    }
+}
+
+fn compile(ip: usize, op: &Opcode) -> Op {
+    let compiled = || -> Result<Op, AluError> {
+        if let Mnemonic::Ext(_) = op.mnemonic {
+            return Ok(Op::Alu(ip, op.target().and_then(|_| register(op.c).ok())));
+        }
+
+        let c = match register(op.c) {
+            Ok(c) => c,
+            Err(_) => return Ok(Op::Alu(ip, None))
+        };
+
+        Ok(match op.mnemonic {
+            Mnemonic::Addr => Op::AddRR(register(op.a)?, register(op.b)?, c),
+            Mnemonic::Addi => Op::AddRI(register(op.a)?, immediate(op.b), c),
+            Mnemonic::Mulr => Op::MulRR(register(op.a)?, register(op.b)?, c),
+            Mnemonic::Muli => Op::MulRI(register(op.a)?, immediate(op.b), c),
+            Mnemonic::Banr => Op::BanRR(register(op.a)?, register(op.b)?, c),
+            Mnemonic::Bani => Op::BanRI(register(op.a)?, immediate(op.b), c),
+            Mnemonic::Borr => Op::BorRR(register(op.a)?, register(op.b)?, c),
+            Mnemonic::Bori => Op::BorRI(register(op.a)?, immediate(op.b), c),
+            Mnemonic::Setr => Op::SetR(register(op.a)?, c),
+            Mnemonic::Seti => Op::SetI(immediate(op.a), c),
+            Mnemonic::Gtir => Op::GtIR(immediate(op.a), register(op.b)?, c),
+            Mnemonic::Gtri => Op::GtRI(register(op.a)?, immediate(op.b), c),
+            Mnemonic::Gtrr => Op::GtRR(register(op.a)?, register(op.b)?, c),
+            Mnemonic::Eqir => Op::EqIR(immediate(op.a), register(op.b)?, c),
+            Mnemonic::Eqri => Op::EqRI(register(op.a)?, immediate(op.b), c),
+            Mnemonic::Eqrr => Op::EqRR(register(op.a)?, register(op.b)?, c),
+            Mnemonic::Ext(_) => unreachable!()
+        })
+    };
+
+    compiled().unwrap_or_else(Op::Fault)
+}
+
+// Register an instruction stores to, if it is a valid one
+fn target(op: Op) -> Option<usize> {
+    match op {
+        Op::AddRR(_, _, c) | Op::AddRI(_, _, c) | Op::MulRR(_, _, c) | Op::MulRI(_, _, c) |
+        Op::BanRR(_, _, c) | Op::BanRI(_, _, c) | Op::BorRR(_, _, c) | Op::BorRI(_, _, c) |
+        Op::SetR(_, c) | Op::SetI(_, c) |
+        Op::GtIR(_, _, c) | Op::GtRI(_, _, c) | Op::GtRR(_, _, c) |
+        Op::EqIR(_, _, c) | Op::EqRI(_, _, c) | Op::EqRR(_, _, c) => Some(c),
+        Op::Alu(_, c) => c,
+        Op::Fault(_) | Op::Macro(_) => None
+    }
+}
+
+// Runs the loop a macro replaced, or its first instruction if the macro cannot
+// tell what the loop would do
+#[cold]
+fn exec_macro(index: usize, r: &mut RegisterState, mode: ArithmeticMode, macros: &[(Macro, Op)], source: &[Opcode]) -> Result<usize, Trap> {
+    let (m, first) = macros[index];
+
+    match m.apply(r) {
+        Some(steps) => Ok(steps),
+        None => exec(first, r, mode, macros, source)
+    }
+}
+
+#[cold]
+fn exec_alu(ip: usize, r: &mut RegisterState, mode: ArithmeticMode, source: &[Opcode]) -> Result<(), Trap> {
+    let opcode = &source[ip];
+
+    let effect = match opcode.mnemonic {
+        Mnemonic::Ext(op) => extension(r, op, opcode, mode)?,
+        _ => {
+            let mut alu = Alu::with_registers(*r);
+
+            alu.set_mode(mode);
+
+            let effect = alu.execute(opcode);
+
+            *r = alu.regs;
+            effect?
+        }
+    };
+
+    match effect {
+        Effect::Output(value) => Err(Trap::Output(value)),
+        Effect::Halt => Err(Trap::Stop),
+        Effect::Store(_) | Effect::Continue => Ok(())
+    }
+}
+
+// Returns how many instructions were executed, more than one for a macro
+#[inline(always)]
+fn exec(op: Op, r: &mut RegisterState, mode: ArithmeticMode, macros: &[(Macro, Op)], source: &[Opcode]) -> Result<usize, Trap> {
+    match op {
+        Op::AddRR(a, b, c) => r[c] = mode.add(r[a], r[b])?,
+        Op::AddRI(a, b, c) => r[c] = mode.add(r[a], b)?,
+        Op::MulRR(a, b, c) => r[c] = mode.mul(r[a], r[b])?,
+        Op::MulRI(a, b, c) => r[c] = mode.mul(r[a], b)?,
+        Op::BanRR(a, b, c) => r[c] = r[a] & r[b],
+        Op::BanRI(a, b, c) => r[c] = r[a] & b,
+        Op::BorRR(a, b, c) => r[c] = r[a] | r[b],
+        Op::BorRI(a, b, c) => r[c] = r[a] | b,
+        Op::SetR(a, c) => r[c] = r[a],
+        Op::SetI(a, c) => r[c] = a,
+        Op::GtIR(a, b, c) => r[c] = (a > r[b]) as Word,
+        Op::GtRI(a, b, c) => r[c] = (r[a] > b) as Word,
+        Op::GtRR(a, b, c) => r[c] = (r[a] > r[b]) as Word,
+        Op::EqIR(a, b, c) => r[c] = (a == r[b]) as Word,
+        Op::EqRI(a, b, c) => r[c] = (r[a] == b) as Word,
+        Op::EqRR(a, b, c) => r[c] = (r[a] == r[b]) as Word,
+        Op::Fault(e) => return Err(Trap::Fault(e)),
+        Op::Macro(i) => return exec_macro(i, r, mode, macros, source),
+        Op::Alu(ip, _) => exec_alu(ip, r, mode, source)?
+    }
+
+    Ok(1)
+}
+
+// Same semantics as `Machine`, but runs on a pre-decoded copy of the program
+// instead of going through `Alu::eval` for every instruction
+#[derive(Clone)]
+pub struct CompiledMachine {
+    // Every instruction along with whether it stores to the ip register,
+    // the ip only has to be read back from the register after those
+    code: Vec<(Op, bool)>,
+
+    // The original instructions, to describe faults with
+    source: Vec<Opcode>,
+
+    // Loops replaced by `optimize`, along with the instruction each one starts with
+    macros: Vec<(Macro, Op)>,
+
+    // Validated ip binding, an invalid one faults on the first step
+    ip_register: Option<Result<usize, AluError>>,
+
+    // The binding as given, for snapshots
+    binding: Option<Register>,
+    fingerprint: u64,
+
+    ip: Word,
+    steps: usize,
+
+    regs: RegisterState,
+    mode: ArithmeticMode,
+
+    // Values produced by `out` instructions
+    output: Vec<Word>
+}
+
+impl CompiledMachine {
+    pub fn new(program: &Program) -> Self {
+        let mut compiled = CompiledMachine {
+            code: program.instructions().iter().enumerate().map(|(ip, op)| (compile(ip, op), false)).collect(),
+            source: program.instructions().to_vec(),
+            macros: Vec::new(),
+            ip_register: None,
+            binding: None,
+            fingerprint: program.fingerprint(),
+
+            ip: 0,
+            steps: 0,
+
+            regs: [0; REGISTER_COUNT],
+            mode: ArithmeticMode::default(),
+
+            output: Vec::new()
+        };
+
+        compiled.bind_ip(program.ip_register());
+        compiled
+    }
+
+    // Drops all macros, as they only work with the binding they were found for
+    pub fn bind_ip(&mut self, ip_register: Option<Register>) {
+        for (m, first) in self.macros.drain(..) {
+            self.code[m.start].0 = first;
+        }
+
+        self.binding = ip_register;
+        self.ip_register = ip_register.map(|r| register(Slot::Reg(r)));
+
+        let bound = match self.ip_register {
+            Some(Ok(r)) => Some(r),
+            _ => None
+        };
+
+        for (op, writes_ip) in self.code.iter_mut() {
+            *writes_ip = bound.is_some() && target(*op) == bound;
+        }
+    }
+
+    // Replaces known loop idioms with macros computing their result directly,
+    // returning what was replaced. Steps still count every instruction the loops
+    // would have executed.
+    pub fn optimize(&mut self) -> Vec<Macro> {
+        let ip = match self.ip_register {
+            Some(Ok(r)) => r as Register,
+            _ => return Vec::new()
+        };
+
+        let found = optimize::find(&self.source, ip)
+            .into_iter()
+            .filter(|m| !self.macros.iter().any(|(o, _)| o.start == m.start))
+            .collect::<Vec<_>>();
+
+        for m in &found {
+            let (first, _) = self.code[m.start];
+
+            // The macro leaves with the ip register set to where the loop exits
+            self.code[m.start] = (Op::Macro(self.macros.len()), true);
+            self.macros.push((*m, first));
+        }
+
+        found
+    }
+
+    // Compiles the machine's program, continuing from its current state
+    pub fn from_machine(machine: &Machine) -> Self {
+        let mut compiled = CompiledMachine::new(machine.program());
+
+        compiled.bind_ip(machine.ip_register());
+        compiled.ip = machine.ip();
+        compiled.steps = machine.steps();
+        compiled.regs = *machine.registers();
+        compiled.mode = machine.mode();
+
+        compiled
+    }
+
+    pub fn ip_register(&self) -> Option<Register> {
+        self.binding
+    }
+
+    pub fn ip(&self) -> Word {
+        self.ip
+    }
+
+    pub fn set_ip(&mut self, ip: Word) {
+        self.ip = ip;
+    }
+
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    pub fn mode(&self) -> ArithmeticMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: ArithmeticMode) {
+        self.mode = mode;
+    }
+
+    pub fn output(&self) -> &[Word] {
+        &self.output
+    }
+
+    pub fn take_output(&mut self) -> Vec<Word> {
+        std::mem::take(&mut self.output)
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            program: self.fingerprint,
+            ip_register: self.binding,
+
+            ip: self.ip,
+            steps: self.steps,
+
+            mode: self.mode,
+            registers: self.regs,
+
+            output: self.output.clone()
+        }
+    }
+
+    // Like `Machine::restore`. A different ip binding drops the macros, as `bind_ip` does.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> bool {
+        if snapshot.program != self.fingerprint {
+            return false;
+        }
+
+        if self.binding != snapshot.ip_register {
+            self.bind_ip(snapshot.ip_register);
+        }
+
+        self.ip = snapshot.ip;
+        self.steps = snapshot.steps;
+        self.mode = snapshot.mode;
+        self.regs = snapshot.registers;
+        self.output = snapshot.output.clone();
+
+        true
+    }
+
+    // The instruction at `ip`, unless `optimize` replaced it with a macro
+    pub fn instruction(&self, ip: usize) -> Option<Opcode> {
+        match self.code.get(ip)? {
+            (Op::Macro(_), _) => None,
+            _ => Some(self.source[ip])
+        }
+    }
+
+    fn fault(&self, ip: usize, error: AluError) -> Halt {
+        Halt::Fault(Fault {
+            ip,
+            opcode: self.source[ip],
+            error
+        })
+    }
+
+    pub fn registers(&self) -> &RegisterState {
+        &self.regs
+    }
+
+    pub fn registers_mut(&mut self) -> &mut RegisterState {
+        &mut self.regs
+    }
+
+    #[inline]
+    pub fn step(&mut self) -> Result<usize, Halt> {
+        let (op, writes_ip) = match self.code.get(self.ip as usize) {
+            Some(&(op, writes_ip)) if self.ip >= 0 => (op, writes_ip),
+            _ => return Err(Halt::IpOutOfRange(self.ip))
+        };
+
+        let ip = self.ip as usize;
+
+        let result = match self.ip_register {
+            None => exec(op, &mut self.regs, self.mode, &self.macros, &self.source),
+
+            Some(Ok(r)) => {
+                self.regs[r] = self.ip;
+
+                let result = exec(op, &mut self.regs, self.mode, &self.macros, &self.source);
+
+                if writes_ip {
+                    self.ip = self.regs[r];
+                }
+
+                result
+            },
+
+            Some(Err(e)) => Err(Trap::Fault(e))
+        };
+
+        let executed = match result {
+            Ok(n) => n,
+            Err(Trap::Output(value)) => {
+                self.output.push(value);
+                1
+            },
+
+            Err(Trap::Fault(e)) => return Err(self.fault(ip, e)),
+            Err(Trap::Stop) => return Err(Halt::Stopped(ip))
+        };
+
+        self.ip = self.ip.wrapping_add(1);
+        self.steps += executed;
+
+        Ok(ip)
+    }
+
+    // Runs at most `max` instructions with the ip and registers kept in locals,
+    // returning why it stopped early. A macro runs as a whole, even past `max`.
+    fn execute(&mut self, max: usize) -> Option<Halt> {
+        let code = &self.code;
+        let macros = &self.macros;
+        let source = &self.source;
+        let output = &mut self.output;
+        let regs = &mut self.regs;
+        let mode = self.mode;
+
+        let mut ip = self.ip;
+        let mut executed = 0;
+
+        // Separate loops so the ip binding is not looked at on every instruction
+        let halt = match self.ip_register {
+            None => loop {
+                if executed >= max {
+                    break None;
+                }
+
+                let op = match code.get(ip as usize) {
+                    Some((op, _)) if ip >= 0 => *op,
+                    _ => break Some(Ok(Halt::IpOutOfRange(ip)))
+                };
+
+                let n = match exec(op, regs, mode, macros, source) {
+                    Ok(n) => n,
+                    Err(Trap::Output(value)) => {
+                        output.push(value);
+                        1
+                    },
+
+                    Err(Trap::Fault(e)) => break Some(Err((ip as usize, e))),
+                    Err(Trap::Stop) => break Some(Ok(Halt::Stopped(ip as usize)))
+                };
+
+                ip += 1;
+                executed += n;
+            },
+
+            Some(Ok(r)) => loop {
+                if executed >= max {
+                    break None;
+                }
+
+                let (op, writes_ip) = match code.get(ip as usize) {
+                    Some(&(op, writes_ip)) if ip >= 0 => (op, writes_ip),
+                    _ => break Some(Ok(Halt::IpOutOfRange(ip)))
+                };
+
+                regs[r] = ip;
+
+                let n = match exec(op, regs, mode, macros, source) {
+                    Ok(n) => n,
+                    Err(Trap::Output(value)) => {
+                        output.push(value);
+                        1
+                    },
+
+                    Err(Trap::Fault(e)) => break Some(Err((ip as usize, e))),
+                    Err(Trap::Stop) => break Some(Ok(Halt::Stopped(ip as usize)))
+                };
+
+                if writes_ip {
+                    ip = regs[r];
+                }
+
+                ip = ip.wrapping_add(1);
+                executed += n;
+            },
+
+            Some(Err(e)) if max > 0 && code.get(ip as usize).is_some() && ip >= 0 => Some(Err((ip as usize, e))),
+            Some(Err(_)) if max > 0 => Some(Ok(Halt::IpOutOfRange(ip))),
+            Some(Err(_)) => None
+        };
+
+        self.ip = ip;
+        self.steps += executed;
+
+        // Faults are only described once the registers are no longer borrowed
+        halt.map(|halt| halt.unwrap_or_else(|(ip, e)| self.fault(ip, e)))
+    }
+
+    pub fn run(&mut self) -> Halt {
+        loop {
+            if let Some(halt) = self.execute(usize::MAX) {
+                return halt;
+            }
+        }
+    }
+
+    pub fn run_for(&mut self, n: usize) -> Halt {
+        match self.execute(n) {
+            Some(halt) => halt,
+            None => match self.code.get(self.ip as usize) {
+                Some(_) if self.ip >= 0 => Halt::StepLimit,
+                _ => Halt::IpOutOfRange(self.ip)
+            }
+        }
+    }
+}
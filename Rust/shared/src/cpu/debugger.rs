@@ -0,0 +1,400 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use super::{AluError, Halt, Machine, Profile, Register, Snapshot, Word};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Watch {
+    // Any instruction storing to the register, even if the value stays the same
+    Write(Register),
+
+    // The register being set to the given value when it held something else before
+    Value(Register, Word)
+}
+
+impl Watch {
+    pub fn register(&self) -> Register {
+        match *self {
+            Watch::Write(r) | Watch::Value(r, _) => r
+        }
+    }
+}
+
+// Why the debugger handed control back
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Stop {
+    // A single step finished, carrying the address that was executed
+    Stepped(usize),
+
+    // About to execute an instruction with a breakpoint on it
+    Breakpoint(usize),
+
+    // The instruction at `ip` triggered a watchpoint
+    Watchpoint { ip: usize, watch: Watch, old: Word, new: Word },
+
+    // About to execute `ip` for the requested number of times
+    HitCount(usize, usize),
+
+    Halted(Halt)
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Stop::Stepped(ip) => write!(fmt, "stepped over {}", ip),
+            Stop::Breakpoint(ip) => write!(fmt, "breakpoint at {}", ip),
+            Stop::Watchpoint { ip, watch, old, new } => {
+                write!(fmt, "watchpoint on r{} at {}: {} -> {}", watch.register(), ip, old, new)
+            },
+            Stop::HitCount(ip, times) => write!(fmt, "reached {} for the {}. time", ip, times),
+            Stop::Halted(halt) => write!(fmt, "halted: {}", halt)
+        }
+    }
+}
+
+// Wraps a machine with breakpoints and watchpoints. Running never stops on
+// the instruction it starts from, so continuing from a breakpoint moves on.
+pub struct Debugger {
+    machine: Machine,
+
+    breakpoints: BTreeSet<usize>,
+    watchpoints: Vec<Watch>,
+
+    // Times every address has been executed under the debugger
+    hits: BTreeMap<usize, usize>,
+
+    // State saved by the `save` command
+    checkpoint: Option<Snapshot>
+}
+
+impl Debugger {
+    pub fn new(machine: Machine) -> Self {
+        Debugger {
+            machine,
+
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+
+            hits: BTreeMap::new(),
+
+            checkpoint: None
+        }
+    }
+
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    pub fn machine_mut(&mut self) -> &mut Machine {
+        &mut self.machine
+    }
+
+    pub fn into_machine(self) -> Machine {
+        self.machine
+    }
+
+    pub fn add_breakpoint(&mut self, ip: usize) -> bool {
+        self.breakpoints.insert(ip)
+    }
+
+    pub fn remove_breakpoint(&mut self, ip: usize) -> bool {
+        self.breakpoints.remove(&ip)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.breakpoints.iter().cloned()
+    }
+
+    pub fn add_watchpoint(&mut self, watch: Watch) {
+        if !self.watchpoints.contains(&watch) {
+            self.watchpoints.push(watch);
+        }
+    }
+
+    // Removes every watchpoint on the given register
+    pub fn remove_watchpoints(&mut self, register: Register) -> bool {
+        let before = self.watchpoints.len();
+
+        self.watchpoints.retain(|w| w.register() != register);
+        self.watchpoints.len() != before
+    }
+
+    pub fn watchpoints(&self) -> &[Watch] {
+        &self.watchpoints
+    }
+
+    pub fn hits(&self, ip: usize) -> usize {
+        self.hits.get(&ip).cloned().unwrap_or(0)
+    }
+
+    pub fn register(&self, register: Register) -> Option<Word> {
+        self.machine.registers().get(register as usize).cloned()
+    }
+
+    pub fn set_register(&mut self, register: Register, value: Word) -> Result<(), AluError> {
+        let r = self.machine.registers_mut().get_mut(register as usize).ok_or(AluError::InvalidRegister(register))?;
+        *r = value;
+
+        Ok(())
+    }
+
+    // Executes one instruction, checking the register it stores to against the watchpoints
+    fn execute(&mut self) -> Result<(usize, Option<Stop>), Halt> {
+        // The ip register holds the instruction's own address while it executes
+        let target = match self.machine.current() {
+            Some((ip, op)) => op.target().and_then(|r| {
+                let old = self.register(r)?;
+
+                Some((r, if Some(r) == self.machine.ip_register() { ip as Word } else { old }))
+            }),
+
+            None => None
+        };
+
+        let ip = self.machine.step()?;
+
+        *self.hits.entry(ip).or_insert(0) += 1;
+
+        if let Some((r, old)) = target {
+            let new = self.machine.registers()[r as usize];
+
+            for &watch in &self.watchpoints {
+                let triggered = match watch {
+                    Watch::Write(w) => w == r,
+                    Watch::Value(w, v) => w == r && old != v && new == v
+                };
+
+                if triggered {
+                    return Ok((ip, Some(Stop::Watchpoint { ip, watch, old, new })));
+                }
+            }
+        }
+
+        Ok((ip, None))
+    }
+
+    pub fn step(&mut self) -> Stop {
+        match self.execute() {
+            Ok((_, Some(stop))) => stop,
+            Ok((ip, None)) => Stop::Stepped(ip),
+            Err(halt) => Stop::Halted(halt)
+        }
+    }
+
+    fn run_until(&mut self, target: Option<(usize, usize)>) -> Stop {
+        let mut reached = 0;
+
+        loop {
+            match self.execute() {
+                Ok((_, Some(stop))) => return stop,
+                Ok((_, None)) => { },
+                Err(halt) => return Stop::Halted(halt)
+            }
+
+            if let Some((next, _)) = self.machine.current() {
+                if let Some((ip, times)) = target {
+                    if next == ip {
+                        reached += 1;
+
+                        if reached >= times {
+                            return Stop::HitCount(ip, reached);
+                        }
+                    }
+                }
+
+                if self.breakpoints.contains(&next) {
+                    return Stop::Breakpoint(next);
+                }
+            }
+        }
+    }
+
+    // Runs until a breakpoint, a watchpoint or the machine halting
+    pub fn resume(&mut self) -> Stop {
+        self.run_until(None)
+    }
+
+    // Like `resume`, but also stops when about to execute `ip` for the `times`th time from here
+    pub fn run_until_hit(&mut self, ip: usize, times: usize) -> Stop {
+        self.run_until(Some((ip, times.max(1))))
+    }
+
+    // Machine state in the same form as the old println debugging in day19
+    pub fn status(&self) -> String {
+        match self.machine.current() {
+            Some((ip, op)) => format!("ip={} {:?} {}", ip, self.machine.registers(), op),
+            None => format!("ip={} {:?} <outside program>", self.machine.ip(), self.machine.registers())
+        }
+    }
+
+    // Program listing around the current instruction, marking breakpoints with `*`
+    pub fn listing(&self, context: usize) -> String {
+        let program = self.machine.program();
+        let ip = self.machine.ip().max(0) as usize;
+
+        let first = ip.saturating_sub(context);
+        let last = (ip + context + 1).min(program.len());
+
+        (first..last)
+            .map(|addr| format!(
+                "{}{}{:>4}  {}",
+                if addr == ip { '>' } else { ' ' },
+                if self.breakpoints.contains(&addr) { '*' } else { ' ' },
+                addr,
+                program.instructions()[addr]))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    // Executes a single textual debugger command, returning what should be shown to the user
+    pub fn command(&mut self, line: &str) -> Result<String, String> {
+        let parts = line.split_whitespace().collect::<Vec<_>>();
+
+        let number = |i: usize| -> Result<Word, String> {
+            let s = parts.get(i).ok_or_else(|| "missing argument".to_string())?;
+
+            s.parse().map_err(|_| format!("not a number: {}", s))
+        };
+
+        let address = |i: usize| -> Result<usize, String> {
+            let n = number(i)?;
+
+            if n < 0 { Err(format!("not an address: {}", n)) } else { Ok(n as usize) }
+        };
+
+        let count = self.machine.registers().len();
+
+        let register = |s: &str| -> Result<Register, String> {
+            let r: Register = s.strip_prefix('r').and_then(|n| n.parse().ok()).ok_or_else(|| format!("not a register: {}", s))?;
+
+            if r >= 0 && (r as usize) < count { Ok(r) } else { Err(format!("no such register: {}", s)) }
+        };
+
+        let stopped = |dbg: &Debugger, stop: Stop| format!("{}\n{}", stop, dbg.status());
+
+        match parts.first().cloned().unwrap_or("") {
+            "s" | "step" => {
+                let n = if parts.len() > 1 { address(1)?.max(1) } else { 1 };
+                let mut stop = Stop::Stepped(0);
+
+                for _ in 0..n {
+                    stop = self.step();
+
+                    if let Stop::Stepped(_) = stop { } else { break; }
+                }
+
+                Ok(stopped(self, stop))
+            },
+
+            "c" | "continue" => {
+                let stop = self.resume();
+
+                Ok(stopped(self, stop))
+            },
+
+            "u" | "until" => {
+                let ip = address(1)?;
+                let times = if parts.len() > 2 { address(2)? } else { 1 };
+                let stop = self.run_until_hit(ip, times);
+
+                Ok(stopped(self, stop))
+            },
+
+            "b" | "break" => {
+                let ip = address(1)?;
+
+                self.add_breakpoint(ip);
+                Ok(format!("breakpoint at {}", ip))
+            },
+
+            "d" | "delete" => {
+                let ip = address(1)?;
+
+                if self.remove_breakpoint(ip) {
+                    Ok(format!("removed breakpoint at {}", ip))
+                } else {
+                    Err(format!("no breakpoint at {}", ip))
+                }
+            },
+
+            "w" | "watch" => {
+                let arg = parts.get(1).ok_or_else(|| "missing argument".to_string())?;
+
+                let watch = match arg.find('=') {
+                    Some(eq) => {
+                        let value = arg[eq + 1..].parse().map_err(|_| format!("not a number: {}", &arg[eq + 1..]))?;
+
+                        Watch::Value(register(&arg[..eq])?, value)
+                    },
+
+                    None => Watch::Write(register(arg)?)
+                };
+
+                self.add_watchpoint(watch);
+                Ok(format!("watching {:?}", watch))
+            },
+
+            "uw" | "unwatch" => {
+                let r = register(parts.get(1).ok_or_else(|| "missing argument".to_string())?)?;
+
+                if self.remove_watchpoints(r) {
+                    Ok(format!("removed watchpoints on r{}", r))
+                } else {
+                    Err(format!("no watchpoints on r{}", r))
+                }
+            },
+
+            "set" => {
+                let target = parts.get(1).ok_or_else(|| "missing argument".to_string())?;
+                let value = number(2)?;
+
+                if *target == "ip" {
+                    self.machine.set_ip(value);
+                } else {
+                    self.set_register(register(target)?, value).map_err(|_| format!("no such register: {}", target))?;
+                }
+
+                Ok(self.status())
+            },
+
+            "r" | "regs" => Ok(self.status()),
+
+            "l" | "list" => Ok(self.listing(5)),
+
+            "p" | "profile" => {
+                let n = address(1)?;
+                let mut profile = Profile::new();
+                let halt = profile.record(&mut self.machine, Some(n));
+
+                Ok(format!("{}{}\n{}", profile.listing(self.machine.program()), halt, self.status()))
+            },
+
+            "save" => {
+                let snapshot = self.machine.snapshot();
+                let text = snapshot.to_string();
+
+                self.checkpoint = Some(snapshot);
+                Ok(text.trim_end().to_string())
+            },
+
+            "restore" => {
+                let checkpoint = self.checkpoint.as_ref().ok_or_else(|| "nothing saved".to_string())?;
+                let diff = self.machine.snapshot().diff(checkpoint);
+
+                self.machine.restore(checkpoint);
+
+                Ok(diff.iter().map(|d| format!("{}\n", d)).collect::<String>() + &self.status())
+            },
+
+            "i" | "info" => Ok(format!(
+                "breakpoints: {:?}\nwatchpoints: {:?}\nsteps: {}",
+                self.breakpoints().collect::<Vec<_>>(),
+                self.watchpoints,
+                self.machine.steps())),
+
+            "" => Err("empty command".to_string()),
+
+            other => Err(format!("unknown command: {}", other))
+        }
+    }
+}
@@ -0,0 +1,433 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use super::cfg::{self, is_comparison, Flow, Target};
+use super::{Mnemonic, Opcode, Operand, Operation, Program, Register, Slot};
+
+#[derive(Debug, Clone)]
+enum Node {
+    Stmt(String),
+    Goto(Target),
+
+    // Jumps if the flag register is non-zero, or if it is zero when negated
+    Branch { flag: Register, negated: bool, target: Target },
+
+    // Jump to an address only known at runtime, already including the ip increment
+    Computed(String)
+}
+
+#[derive(Debug, Clone)]
+struct Line {
+    // Instructions this line was made of, the first one being where it starts
+    addrs: Vec<usize>,
+    node: Node
+}
+
+impl Line {
+    fn addr(&self) -> usize {
+        self.addrs[0]
+    }
+}
+
+// Operand as an expression, reading the ip register always yields the current address
+fn operand(slot: Slot, ip: usize, ip_register: Option<Register>) -> String {
+    match slot {
+        Slot::Reg(r) if Some(r) == ip_register => ip.to_string(),
+        Slot::Reg(r) => format!("r{}", r),
+        Slot::Immediate(i) => i.to_string()
+    }
+}
+
+fn operator(mnemonic: Mnemonic) -> &'static str {
+    match mnemonic {
+        Mnemonic::Addr | Mnemonic::Addi => "+",
+        Mnemonic::Mulr | Mnemonic::Muli => "*",
+        Mnemonic::Banr | Mnemonic::Bani => "&",
+        Mnemonic::Borr | Mnemonic::Bori => "|",
+        Mnemonic::Gtir | Mnemonic::Gtri | Mnemonic::Gtrr => ">",
+        Mnemonic::Eqir | Mnemonic::Eqri | Mnemonic::Eqrr => "==",
+        Mnemonic::Setr | Mnemonic::Seti | Mnemonic::Ext(_) => ""
+    }
+}
+
+// Operations outside the base instruction set look like function calls
+fn call(ext: &dyn Operation, op: &Opcode, ip: usize, ip_register: Option<Register>) -> String {
+    let shape = ext.shape();
+    let args = [(shape.a, op.a), (shape.b, op.b)]
+        .iter()
+        .filter(|(operand, _)| *operand != Operand::Unused)
+        .map(|(_, slot)| operand(*slot, ip, ip_register))
+        .collect::<Vec<_>>();
+
+    format!("{}({})", ext.name(), args.join(", "))
+}
+
+// Value computed by an instruction, without storing it anywhere
+fn expression(op: &Opcode, ip: usize, ip_register: Option<Register>) -> String {
+    let a = operand(op.a, ip, ip_register);
+    let b = operand(op.b, ip, ip_register);
+
+    match op.mnemonic {
+        Mnemonic::Setr | Mnemonic::Seti => a,
+        Mnemonic::Ext(ext) => call(ext, op, ip, ip_register),
+        m => format!("{} {} {}", a, operator(m), b)
+    }
+}
+
+fn statement(op: &Opcode, ip: usize, ip_register: Option<Register>) -> String {
+    let a = operand(op.a, ip, ip_register);
+    let b = operand(op.b, ip, ip_register);
+    let c = operand(op.c, ip, ip_register);
+
+    match op.mnemonic {
+        Mnemonic::Setr | Mnemonic::Seti => format!("{} = {};", c, a),
+        Mnemonic::Ext(ext) if op.target().is_some() => format!("{} = {};", c, call(ext, op, ip, ip_register)),
+        Mnemonic::Ext(ext) => format!("{};", call(ext, op, ip, ip_register)),
+        m if is_comparison(m) => format!("{} = {} {} {};", c, a, operator(m), b),
+
+        // Arithmetic is commutative, so `rC = rX op rC` becomes `rC op= rX` as well
+        m if a == c => format!("{} {}= {};", c, operator(m), b),
+        m if b == c => format!("{} {}= {};", c, operator(m), a),
+        m => format!("{} = {} {} {};", c, a, operator(m), b)
+    }
+}
+
+struct Decompiler {
+    lines: Vec<Line>,
+    index: HashMap<usize, usize>,
+
+    // Sources of every jump to an address
+    incoming: BTreeMap<usize, Vec<usize>>,
+
+    labels: BTreeSet<usize>,
+    out: Vec<(usize, Output)>
+}
+
+#[derive(Debug, Clone)]
+enum Output {
+    Label(usize),
+    Code(String, Vec<usize>)
+}
+
+// Where `break` and `continue` lead inside of the innermost loop
+#[derive(Debug, Copy, Clone)]
+struct LoopContext {
+    head: Option<Target>,
+    exit: Target
+}
+
+impl Decompiler {
+    fn new(program: &Program) -> Self {
+        let flows = cfg::flow(program);
+
+        let lines = program.instructions().iter().zip(flows).enumerate().map(|(ip, (op, flow))| {
+            let ipr = program.ip_register();
+
+            let node = match flow {
+                Flow::Next => Node::Stmt(statement(op, ip, ipr)),
+                Flow::Jump(to) => Node::Goto(to),
+                Flow::Branch { flag, taken } => Node::Branch { flag, negated: false, target: taken },
+
+                Flow::Computed => match ipr.and_then(|ipr| cfg::offset_register(op, ipr)) {
+                    Some(offset) => Node::Computed(format!("r{} + {}", offset, ip + 1)),
+                    None => Node::Computed(format!("{} + 1", expression(op, ip, ipr)))
+                }
+            };
+
+            Line {
+                addrs: vec![ip],
+                node
+            }
+        }).collect();
+
+        let mut decompiler = Decompiler {
+            lines,
+            index: HashMap::new(),
+            incoming: BTreeMap::new(),
+            labels: BTreeSet::new(),
+            out: Vec::new()
+        };
+
+        decompiler.merge_branches();
+        decompiler
+    }
+
+    fn reindex(&mut self) {
+        self.index = self.lines.iter().enumerate().map(|(i, l)| (l.addr(), i)).collect();
+        self.incoming.clear();
+
+        for line in &self.lines {
+            match line.node {
+                Node::Goto(Target::Addr(to)) | Node::Branch { target: Target::Addr(to), .. } => {
+                    self.incoming.entry(to).or_default().push(line.addr());
+                },
+
+                _ => { }
+            }
+        }
+    }
+
+    // A branch over an unconditional jump is turned into a negated branch to the jump's target:
+    //
+    //   if r5 goto 7; goto 8; 7: ...   =>   if !r5 goto 8; 7: ...
+    fn merge_branches(&mut self) {
+        self.reindex();
+
+        let mut i = 0;
+
+        while i + 1 < self.lines.len() {
+            let next = self.lines[i + 1].addr();
+
+            let mergeable = match (&self.lines[i].node, &self.lines[i + 1].node) {
+                (Node::Branch { target: Target::Addr(to), .. }, Node::Goto(_)) => {
+                    self.lines.get(i + 2).map(|l| l.addr()) == Some(*to) && !self.incoming.contains_key(&next)
+                },
+
+                (Node::Branch { target: Target::Exit, .. }, Node::Goto(_)) => {
+                    i + 2 == self.lines.len() && !self.incoming.contains_key(&next)
+                },
+
+                _ => false
+            };
+
+            if mergeable {
+                let skipped = self.lines.remove(i + 1);
+
+                if let (Node::Branch { flag, negated, .. }, Node::Goto(to)) = (&self.lines[i].node, skipped.node) {
+                    self.lines[i].node = Node::Branch { flag: *flag, negated: !negated, target: to };
+                }
+
+                self.lines[i].addrs.extend(skipped.addrs);
+                self.reindex();
+            }
+
+            i += 1;
+        }
+    }
+
+    fn target_index(&self, target: Target) -> usize {
+        match target {
+            Target::Addr(addr) => self.index[&addr],
+            Target::Exit => self.lines.len()
+        }
+    }
+
+    fn target_at(&self, index: usize) -> Target {
+        self.lines.get(index).map(|l| Target::Addr(l.addr())).unwrap_or(Target::Exit)
+    }
+
+    // Whether the lines in [lo, hi) are only entered through `lo` from outside
+    fn closed(&self, lo: usize, hi: usize) -> bool {
+        (lo + 1..hi).all(|i| {
+            let sources = self.incoming.get(&self.lines[i].addr()).map(|s| s.as_slice()).unwrap_or(&[]);
+
+            sources.iter().all(|s| {
+                let s = self.index[s];
+
+                s >= lo && s < hi
+            })
+        })
+    }
+
+    fn emit(&mut self, depth: usize, text: String, addrs: Vec<usize>) {
+        self.out.push((depth, Output::Code(text, addrs)));
+    }
+
+    fn jump(&mut self, target: Target, ctx: Option<LoopContext>) -> String {
+        match ctx {
+            Some(LoopContext { head: Some(head), .. }) if head == target => "continue;".to_string(),
+            Some(LoopContext { exit, .. }) if exit == target => "break;".to_string(),
+
+            _ => match target {
+                Target::Exit => "halt;".to_string(),
+                Target::Addr(addr) => {
+                    self.labels.insert(addr);
+
+                    format!("goto L{};", addr)
+                }
+            }
+        }
+    }
+
+    fn condition(flag: Register, negated: bool) -> String {
+        format!("{}r{}", if negated { "!" } else { "" }, flag)
+    }
+
+    fn block(&mut self, lo: usize, hi: usize, depth: usize, ctx: Option<LoopContext>) {
+        let mut p = lo;
+
+        while p < hi {
+            let head = self.lines[p].addr();
+
+            self.out.push((depth, Output::Label(head)));
+
+            // The last jump back to this line within the block makes it a loop
+            let back = (p..hi).rev().find(|&q| match self.lines[q].node {
+                Node::Goto(Target::Addr(to)) | Node::Branch { target: Target::Addr(to), .. } => to == head,
+                _ => false
+            });
+
+            if let Some(q) = back {
+                if self.closed(p, q + 1) {
+                    let addrs = self.lines[q].addrs.clone();
+                    let exit = self.target_at(q + 1);
+
+                    match self.lines[q].node.clone() {
+                        Node::Branch { flag, negated, .. } => {
+                            let inner = LoopContext { head: None, exit };
+
+                            self.emit(depth, "do {".to_string(), vec![]);
+                            self.block(p, q, depth + 1, Some(inner));
+                            self.emit(depth, format!("}} while ({});", Decompiler::condition(flag, negated)), addrs);
+                        },
+
+                        _ => {
+                            let inner = LoopContext { head: Some(Target::Addr(head)), exit };
+
+                            self.emit(depth, "while (1) {".to_string(), vec![]);
+                            self.block(p, q, depth + 1, Some(inner));
+                            self.emit(depth, "}".to_string(), addrs);
+                        }
+                    }
+
+                    p = q + 1;
+                    continue;
+                }
+            }
+
+            let line = self.lines[p].clone();
+
+            match line.node {
+                Node::Stmt(text) => self.emit(depth, text, line.addrs),
+
+                Node::Branch { flag, negated, target } if self.is_forward(p, target, hi) => {
+                    let t = self.target_index(target);
+                    let cond = Decompiler::condition(flag, !negated);
+
+                    // `if (c) { ...; goto Y } T: ... Y:` is an if/else
+                    let els = match self.lines[t - 1].node {
+                        Node::Goto(to) if t - 1 > p + 1 => self.forward_index(to, hi).filter(|&u| {
+                            u > t && self.closed(t, u) && self.incoming_within(t, u, p)
+                        }),
+
+                        _ => None
+                    };
+
+                    self.emit(depth, format!("if ({}) {{", cond), line.addrs);
+
+                    match els {
+                        Some(u) => {
+                            let addrs = self.lines[t - 1].addrs.clone();
+
+                            self.block(p + 1, t - 1, depth + 1, ctx);
+                            self.emit(depth, "} else {".to_string(), addrs);
+                            self.block(t, u, depth + 1, ctx);
+                            self.emit(depth, "}".to_string(), vec![]);
+
+                            p = u;
+                        },
+
+                        None => {
+                            self.block(p + 1, t, depth + 1, ctx);
+                            self.emit(depth, "}".to_string(), vec![]);
+
+                            p = t;
+                        }
+                    }
+
+                    continue;
+                },
+
+                Node::Branch { flag, negated, target } => {
+                    let jump = self.jump(target, ctx);
+
+                    self.emit(depth, format!("if ({}) {}", Decompiler::condition(flag, negated), jump), line.addrs);
+                },
+
+                // Falling through to the next line needs no jump at all
+                Node::Goto(target) if target == self.target_at(p + 1) && p + 1 < hi => { },
+
+                Node::Goto(target) => {
+                    let jump = self.jump(target, ctx);
+
+                    self.emit(depth, jump, line.addrs);
+                },
+
+                Node::Computed(expr) => self.emit(depth, format!("jump({});", expr), line.addrs)
+            }
+
+            p += 1;
+        }
+    }
+
+    // Index of a jump target if it lies within a block ending at `hi`
+    fn forward_index(&self, target: Target, hi: usize) -> Option<usize> {
+        let t = match target {
+            Target::Addr(addr) => self.index[&addr],
+            Target::Exit if hi == self.lines.len() => hi,
+            Target::Exit => return None
+        };
+
+        if t <= hi { Some(t) } else { None }
+    }
+
+    // Whether a branch at `p` skips ahead to somewhere within the block, with nothing else jumping in between
+    fn is_forward(&self, p: usize, target: Target, hi: usize) -> bool {
+        match self.forward_index(target, hi) {
+            Some(t) => t > p + 1 && self.closed(p + 1, t) && self.incoming_within(p + 1, t, p),
+            None => false
+        }
+    }
+
+    // Whether the first line of [lo, hi) is only reached by falling through or from `from`
+    fn incoming_within(&self, lo: usize, hi: usize, from: usize) -> bool {
+        self.incoming.get(&self.lines[lo].addr()).map(|sources| sources.iter().all(|s| {
+            let s = self.index[s];
+
+            s == from || (s >= lo && s < hi)
+        })).unwrap_or(true)
+    }
+
+    fn render(mut self, program: &Program) -> String {
+        let len = self.lines.len();
+
+        self.block(0, len, 0, None);
+
+        let mut text = String::new();
+
+        if let Some(ipr) = program.ip_register() {
+            text.push_str(&format!("// #ip {}\n", ipr));
+        }
+
+        for (depth, out) in &self.out {
+            match out {
+                Output::Label(addr) if self.labels.contains(addr) => {
+                    text.push_str(&format!("{}L{}:\n", "    ".repeat(depth.saturating_sub(1)), addr));
+                },
+
+                Output::Label(_) => { },
+
+                Output::Code(code, addrs) => {
+                    let line = format!("{}{}", "    ".repeat(*depth), code);
+
+                    if addrs.is_empty() {
+                        text.push_str(&format!("{}\n", line));
+                    } else {
+                        let addrs = addrs.iter().map(|a| a.to_string()).collect::<Vec<_>>().join(", ");
+
+                        text.push_str(&format!("{:<40} // {}\n", line, addrs));
+                    }
+                }
+            }
+        }
+
+        text
+    }
+}
+
+// Turns a program into C-like pseudo-code. Writes to the bound ip register
+// become jumps and are rebuilt into loops and conditionals where possible,
+// everything else is left as labels and gotos. Every line is annotated with
+// the addresses of the instructions it came from.
+pub fn decompile(program: &Program) -> String {
+    Decompiler::new(program).render(program)
+}
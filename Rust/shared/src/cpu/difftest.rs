@@ -0,0 +1,261 @@
+use std::fmt;
+
+use super::{CompiledMachine, Executor, Halt, InstructionSet, Machine, Mnemonic, Opcode, Operand, Program, RegisterState, Word, REGISTER_COUNT};
+
+// A program along with the registers it starts with
+#[derive(Debug, Clone)]
+pub struct Case {
+    pub program: Program,
+    pub registers: RegisterState
+}
+
+// Where a run ended up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    pub halt: Halt,
+    pub ip: Word,
+    pub registers: RegisterState,
+    pub output: Vec<Word>
+}
+
+// A case the executor under test disagrees with the reference on
+#[derive(Debug, Clone)]
+pub struct Mismatch {
+    pub case: Case,
+    pub expected: Outcome,
+    pub actual: Outcome
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(fmt, "registers {:?}", self.case.registers)?;
+        write!(fmt, "{}", self.case.program)?;
+
+        for (name, outcome) in [("expected", &self.expected), ("actual", &self.actual)].iter() {
+            writeln!(fmt, "{}: {} at ip {} with {:?}, output {:?}", name, outcome.halt, outcome.ip, outcome.registers, outcome.output)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Random programs over an instruction set, the same ones for the same seed.
+// Operands are mostly small, with the odd invalid register or huge immediate
+// to get into faults and overflows.
+#[derive(Debug, Clone)]
+pub struct Generator {
+    state: u64,
+    mnemonics: Vec<Mnemonic>,
+
+    // Longest program to generate
+    pub length: usize
+}
+
+impl Generator {
+    pub fn new(seed: u64, set: &InstructionSet) -> Self {
+        Generator {
+            // xorshift gets stuck on 0
+            state: seed ^ 0x9e37_79b9_7f4a_7c15,
+            mnemonics: set.mnemonics().collect(),
+
+            length: 12
+        }
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn word(&mut self) -> Word {
+        match self.below(20) {
+            0 => Word::MAX,
+            1 => Word::MIN,
+            2 => self.next() as Word,
+            _ => self.below(24) as Word - 4
+        }
+    }
+
+    fn register(&mut self) -> Word {
+        match self.below(40) {
+            0 => REGISTER_COUNT as Word,
+            1 => -1,
+            _ => self.below(REGISTER_COUNT) as Word
+        }
+    }
+
+    pub fn registers(&mut self) -> RegisterState {
+        let mut registers = [0; REGISTER_COUNT];
+
+        for r in registers.iter_mut() {
+            *r = if self.below(2) == 0 { 0 } else { self.word() };
+        }
+
+        registers
+    }
+
+    pub fn program(&mut self) -> Program {
+        let length = 1 + self.below(self.length.max(1));
+        let ip_register = if self.below(2) == 0 { Some(self.below(REGISTER_COUNT) as i8) } else { None };
+
+        let instructions = (0..length).map(|_| {
+            let pick = self.below(self.mnemonics.len());
+            let mnemonic = self.mnemonics[pick];
+            let shape = mnemonic.shape();
+            let mut operand = |kind| match kind {
+                Operand::Register => self.register(),
+                Operand::Immediate => self.word(),
+                Operand::Unused => 0
+            };
+
+            let (a, b, c) = (operand(shape.a), operand(shape.b), operand(shape.c));
+
+            Opcode::build(mnemonic, a, b, c).unwrap()
+        }).collect();
+
+        Program::new(ip_register, instructions)
+    }
+
+    pub fn case(&mut self) -> Case {
+        Case {
+            program: self.program(),
+            registers: self.registers()
+        }
+    }
+}
+
+// The executors the reference is usually compared against
+pub fn compiled(program: &Program, registers: RegisterState) -> CompiledMachine {
+    let mut machine = CompiledMachine::new(program);
+
+    *machine.registers_mut() = registers;
+    machine
+}
+
+pub fn optimized(program: &Program, registers: RegisterState) -> CompiledMachine {
+    let mut machine = compiled(program, registers);
+
+    machine.optimize();
+    machine
+}
+
+pub fn outcome<E>(machine: &mut E, max_steps: usize) -> Outcome
+    where E: Executor<REGISTER_COUNT>
+{
+    let mut halt = Halt::StepLimit;
+
+    for _ in 0..max_steps {
+        if let Err(h) = machine.step() {
+            halt = h;
+            break;
+        }
+    }
+
+    Outcome {
+        halt,
+        ip: machine.ip(),
+        registers: *machine.registers(),
+        output: machine.output().to_vec()
+    }
+}
+
+// Runs the case on `Machine`, which executes every instruction on the `Alu`,
+// and on the executor `make` builds. Cases the reference does not finish within
+// `max_steps` are not compared, as executors may count steps differently.
+pub fn check<F, E>(case: &Case, max_steps: usize, make: &F) -> Result<(), Box<Mismatch>>
+    where F: Fn(&Program, RegisterState) -> E,
+          E: Executor<REGISTER_COUNT>
+{
+    let expected = outcome(&mut Machine::with_registers(case.program.clone(), case.registers), max_steps);
+
+    if expected.halt == Halt::StepLimit {
+        return Ok(());
+    }
+
+    let actual = outcome(&mut make(&case.program, case.registers), max_steps);
+
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(Box::new(Mismatch { case: case.clone(), expected, actual }))
+    }
+}
+
+// Smaller versions of a case: one instruction or the ip binding less, or a
+// register or operand set to 0
+fn reductions(case: &Case) -> Vec<Case> {
+    let ops = case.program.instructions();
+    let mut smaller = Vec::new();
+    let with = |ip_register, instructions, registers| Case { program: Program::new(ip_register, instructions), registers };
+
+    for i in 0..ops.len() {
+        let mut fewer = ops.to_vec();
+
+        fewer.remove(i);
+        smaller.push(with(case.program.ip_register(), fewer, case.registers));
+    }
+
+    if case.program.ip_register().is_some() {
+        smaller.push(with(None, ops.to_vec(), case.registers));
+    }
+
+    for r in 0..REGISTER_COUNT {
+        if case.registers[r] != 0 {
+            let mut registers = case.registers;
+
+            registers[r] = 0;
+            smaller.push(with(case.program.ip_register(), ops.to_vec(), registers));
+        }
+    }
+
+    for (i, op) in ops.iter().enumerate() {
+        let values = [op.a(), op.b(), op.c()];
+
+        for n in 0..3 {
+            if values[n] != 0 {
+                let mut zeroed = values;
+                let mut changed = ops.to_vec();
+
+                zeroed[n] = 0;
+                changed[i] = Opcode::build(op.mnemonic, zeroed[0], zeroed[1], zeroed[2]).unwrap();
+                smaller.push(with(case.program.ip_register(), changed, case.registers));
+            }
+        }
+    }
+
+    smaller
+}
+
+// Shrinks the case as long as it keeps failing
+pub fn minimize<F, E>(mismatch: Box<Mismatch>, max_steps: usize, make: &F) -> Box<Mismatch>
+    where F: Fn(&Program, RegisterState) -> E,
+          E: Executor<REGISTER_COUNT>
+{
+    let mut smallest = mismatch;
+
+    while let Some(smaller) = reductions(&smallest.case).iter().find_map(|case| check(case, max_steps, make).err()) {
+        smallest = smaller;
+    }
+
+    smallest
+}
+
+// Checks `cases` random cases, returning the first mismatch minimized
+pub fn fuzz<F, E>(generator: &mut Generator, cases: usize, max_steps: usize, make: &F) -> Result<(), Box<Mismatch>>
+    where F: Fn(&Program, RegisterState) -> E,
+          E: Executor<REGISTER_COUNT>
+{
+    for _ in 0..cases {
+        if let Err(mismatch) = check(&generator.case(), max_steps, make) {
+            return Err(minimize(mismatch, max_steps, make));
+        }
+    }
+
+    Ok(())
+}
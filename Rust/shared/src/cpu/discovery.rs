@@ -0,0 +1,335 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use super::{Alu, Mnemonic, Opcode, ParseError, ParseErrorKind, Program, Slot, Word, REGISTER_COUNT};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SampleErrorKind {
+    ExpectedBefore,
+    ExpectedInstruction,
+    ExpectedAfter,
+    InvalidNumber(String),
+
+    // Before and after states have a different number of registers
+    RegisterCountMismatch
+}
+
+// Line numbers start at 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SampleError {
+    pub line: usize,
+    pub kind: SampleErrorKind
+}
+
+impl fmt::Display for SampleError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "line {}: ", self.line)?;
+
+        match &self.kind {
+            SampleErrorKind::ExpectedBefore => write!(fmt, "expected 'Before: [...]'"),
+            SampleErrorKind::ExpectedInstruction => write!(fmt, "expected an instruction of four numbers"),
+            SampleErrorKind::ExpectedAfter => write!(fmt, "expected 'After: [...]'"),
+            SampleErrorKind::InvalidNumber(n) => write!(fmt, "invalid number '{}'", n),
+            SampleErrorKind::RegisterCountMismatch => write!(fmt, "before and after have different register counts")
+        }
+    }
+}
+
+impl std::error::Error for SampleError { }
+
+// An instruction in its numeric form along with the registers before and after executing it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sample {
+    pub before: Vec<Word>,
+    pub instruction: [Word; 4],
+    pub after: Vec<Word>
+}
+
+fn parse_state(line: &str, prefix: &str) -> Result<Vec<Word>, SampleErrorKind> {
+    let list = line
+        .strip_prefix(prefix)
+        .map(str::trim)
+        .and_then(|l| l.strip_prefix('['))
+        .and_then(|l| l.strip_suffix(']'))
+        .ok_or(if prefix == "Before:" { SampleErrorKind::ExpectedBefore } else { SampleErrorKind::ExpectedAfter })?;
+
+    list.split(',')
+        .map(|n| n.trim().parse().map_err(|_| SampleErrorKind::InvalidNumber(n.trim().to_string())))
+        .collect()
+}
+
+fn parse_instruction(line: &str) -> Result<[Word; 4], SampleErrorKind> {
+    let parts = line.split_whitespace().collect::<Vec<_>>();
+
+    if parts.len() != 4 {
+        return Err(SampleErrorKind::ExpectedInstruction);
+    }
+
+    let mut raw = [0; 4];
+
+    for (r, p) in raw.iter_mut().zip(parts) {
+        *r = p.parse().map_err(|_| SampleErrorKind::InvalidNumber(p.to_string()))?;
+    }
+
+    Ok(raw)
+}
+
+// Parses the samples at the start of the input, which may be followed by anything
+// else once a non-blank line does not start with `Before:`. Returns the samples
+// and the remaining input.
+pub fn parse_samples(input: &str) -> Result<(Vec<Sample>, &str), SampleError> {
+    let mut samples = Vec::new();
+    let mut lines = input.split_inclusive('\n').enumerate().peekable();
+    let mut offset = 0;
+
+    loop {
+        while let Some((_, line)) = lines.peek() {
+            if !line.trim().is_empty() {
+                break;
+            }
+
+            offset += line.len();
+            lines.next();
+        }
+
+        match lines.peek() {
+            Some((_, line)) if line.trim().starts_with("Before:") => { },
+            _ => return Ok((samples, &input[offset..]))
+        }
+
+        let mut next = |expected: SampleErrorKind| match lines.next() {
+            Some((n, line)) => {
+                offset += line.len();
+                Ok((n + 1, line.trim()))
+            },
+
+            None => Err(SampleError { line: input.lines().count() + 1, kind: expected })
+        };
+
+        let (n, before) = next(SampleErrorKind::ExpectedBefore)?;
+        let before = parse_state(before, "Before:").map_err(|kind| SampleError { line: n, kind })?;
+
+        let (n, instr) = next(SampleErrorKind::ExpectedInstruction)?;
+        let instruction = parse_instruction(instr).map_err(|kind| SampleError { line: n, kind })?;
+
+        let (n, after) = next(SampleErrorKind::ExpectedAfter)?;
+        let after = parse_state(after, "After:").map_err(|kind| SampleError { line: n, kind })?;
+
+        if before.len() != after.len() {
+            return Err(SampleError { line: n, kind: SampleErrorKind::RegisterCountMismatch });
+        }
+
+        samples.push(Sample { before, instruction, after });
+    }
+}
+
+impl Sample {
+    // Whether the instruction, read as the given mnemonic, turns `before` into `after`
+    pub fn matches(&self, mnemonic: Mnemonic) -> bool {
+        let [_, a, b, c] = self.instruction;
+        let count = self.before.len();
+
+        let op = match Opcode::build(mnemonic, a, b, c) {
+            Some(op) => op,
+            None => return false
+        };
+
+        // Registers beyond the sample's register file do not exist, and `b` is unused for set*
+        let slots = match mnemonic {
+            Mnemonic::Setr | Mnemonic::Seti => vec![(op.a, a), (op.c, c)],
+            _ => vec![(op.a, a), (op.b, b), (op.c, c)]
+        };
+
+        let registers = slots.iter().all(|(slot, raw)| match slot {
+            Slot::Reg(_) => *raw >= 0 && (*raw as usize) < count,
+            Slot::Immediate(_) => true
+        });
+
+        if !registers || count > REGISTER_COUNT {
+            return false;
+        }
+
+        let mut alu = Alu::new();
+
+        alu.regs[..count].copy_from_slice(&self.before);
+
+        alu.eval(&op).is_ok() && alu.regs[..count] == self.after[..]
+    }
+
+    // Every mnemonic the sample is consistent with
+    pub fn candidates(&self) -> Vec<Mnemonic> {
+        Mnemonic::ALL.iter().cloned().filter(|m| self.matches(*m)).collect()
+    }
+}
+
+// A runtime numbering of the instruction set
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OpcodeMap {
+    map: BTreeMap<Word, Mnemonic>
+}
+
+impl OpcodeMap {
+    // The numbering of the day16 device, see `Mnemonic::number`
+    pub fn builtin() -> Self {
+        OpcodeMap {
+            map: Mnemonic::ALL.iter().filter_map(|m| Some((m.number()?, *m))).collect()
+        }
+    }
+
+    pub fn get(&self, number: Word) -> Option<Mnemonic> {
+        self.map.get(&number).cloned()
+    }
+
+    pub fn number_of(&self, mnemonic: Mnemonic) -> Option<Word> {
+        self.map.iter().find(|(_, m)| **m == mnemonic).map(|(n, _)| *n)
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Word, Mnemonic)> + '_ {
+        self.map.iter().map(|(n, m)| (*n, *m))
+    }
+
+    pub fn decode(&self, raw: &[Word]) -> Option<Opcode> {
+        if raw.len() != 4 {
+            return None;
+        }
+
+        Opcode::build(self.get(raw[0])?, raw[1], raw[2], raw[3])
+    }
+
+    // The numeric form `decode` reads, if the mnemonic has a number
+    pub fn encode(&self, op: &Opcode) -> Option<[Word; 4]> {
+        let [a, b, c] = [op.a, op.b, op.c].map(|slot| match slot {
+            Slot::Reg(r) => r as Word,
+            Slot::Immediate(i) => i
+        });
+
+        Some([self.number_of(op.mnemonic)?, a, b, c])
+    }
+
+    // Decodes a program given as one numeric instruction per line, skipping blank lines
+    pub fn decode_program(&self, source: &str) -> Result<Program, ParseError> {
+        let mut instructions = Vec::new();
+
+        for (n, line) in source.lines().enumerate() {
+            let error = |kind| ParseError { line: n + 1, kind };
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let raw = parse_instruction(line).map_err(|_| error(ParseErrorKind::InvalidOperand(line.trim().to_string())))?;
+            let op = self.decode(&raw).ok_or_else(|| error(ParseErrorKind::UnknownMnemonic(raw[0].to_string())))?;
+
+            instructions.push(op);
+        }
+
+        Ok(Program::new(None, instructions))
+    }
+}
+
+// Later entries replace earlier ones with the same number or mnemonic
+impl std::iter::FromIterator<(Word, Mnemonic)> for OpcodeMap {
+    fn from_iter<I>(iter: I) -> Self
+        where I: IntoIterator<Item = (Word, Mnemonic)>
+    {
+        let mut map = BTreeMap::new();
+
+        for (number, mnemonic) in iter {
+            map.retain(|_, m| *m != mnemonic);
+            map.insert(number, mnemonic);
+        }
+
+        OpcodeMap { map }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SolveError {
+    // A sample no mnemonic is consistent with
+    InconsistentSample(usize),
+
+    // No mnemonic is left for an opcode number
+    Contradiction(Word),
+
+    // Propagation got stuck, with what was solved so far and the candidates left for the rest
+    Ambiguous(OpcodeMap, BTreeMap<Word, BTreeSet<Mnemonic>>)
+}
+
+impl fmt::Display for SolveError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SolveError::InconsistentSample(i) => write!(fmt, "sample {} matches no instruction", i),
+            SolveError::Contradiction(n) => write!(fmt, "no instruction left for opcode {}", n),
+            SolveError::Ambiguous(_, open) => write!(fmt, "ambiguous opcodes: {:?}", open)
+        }
+    }
+}
+
+impl std::error::Error for SolveError { }
+
+// Works out the opcode numbering from samples. Every number can only be one of
+// the mnemonics all of its samples agree with, and once a number is down to a
+// single mnemonic (or a mnemonic is only possible for a single number), that
+// pair is fixed and removed from everything else until nothing changes.
+pub fn solve(samples: &[Sample]) -> Result<OpcodeMap, SolveError> {
+    let mut open: BTreeMap<Word, BTreeSet<Mnemonic>> = BTreeMap::new();
+
+    for (i, sample) in samples.iter().enumerate() {
+        let candidates = sample.candidates().into_iter().collect::<BTreeSet<_>>();
+
+        if candidates.is_empty() {
+            return Err(SolveError::InconsistentSample(i));
+        }
+
+        let entry = open.entry(sample.instruction[0]).or_insert_with(|| candidates.clone());
+        *entry = entry.intersection(&candidates).cloned().collect();
+    }
+
+    let mut solved = OpcodeMap::default();
+
+    loop {
+        if let Some((&n, _)) = open.iter().find(|(_, c)| c.is_empty()) {
+            return Err(SolveError::Contradiction(n));
+        }
+
+        let single = open.iter().find(|(_, c)| c.len() == 1).map(|(n, c)| (*n, *c.iter().next().unwrap()));
+
+        // A mnemonic that only one of the numbers can still be, which only says anything
+        // when the samples cover every number so every mnemonic has to end up somewhere
+        let complete = open.len() + solved.len() == Mnemonic::ALL.len();
+        let hidden = || Mnemonic::ALL.iter().filter(|_| complete).find_map(|m| {
+            let mut holders = open.iter().filter(|(_, c)| c.contains(m));
+
+            match (holders.next(), holders.next()) {
+                (Some((n, c)), None) if c.len() > 1 => Some((*n, *m)),
+                _ => None
+            }
+        });
+
+        let (n, m) = match single.or_else(hidden) {
+            Some(pair) => pair,
+            None => break
+        };
+
+        open.remove(&n);
+        solved.map.insert(n, m);
+
+        for c in open.values_mut() {
+            c.remove(&m);
+        }
+    }
+
+    if open.is_empty() {
+        Ok(solved)
+    } else {
+        Err(SolveError::Ambiguous(solved, open))
+    }
+}
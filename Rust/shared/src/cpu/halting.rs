@@ -0,0 +1,201 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use super::cfg::{self, Flow, Target};
+use super::{Executor, Halt, Mnemonic, Operand, Program, Register, Slot, Word};
+
+// An `eqrr` comparing a register against r0 and leaving the program if they are equal
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ExitCheck {
+    pub ip: usize,
+    pub register: Register,
+
+    // Instructions executed from the check until the program is left, including the check itself
+    pub exit_steps: usize
+}
+
+// A value of r0 that makes the program halt
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    pub value: Word,
+
+    // The check that lets it halt
+    pub ip: usize,
+
+    // Instructions executed until the program halts, as counted by the machine
+    pub steps: usize
+}
+
+#[derive(Debug, Clone)]
+pub struct HaltingAnalysis {
+    pub checks: Vec<ExitCheck>,
+
+    // Every value halting the program, in the order they are first compared,
+    // so they take increasingly many instructions
+    pub candidates: Vec<Candidate>,
+
+    // None if the compared values started repeating, otherwise why the
+    // analysis stopped before all candidates were known
+    pub halt: Option<Halt>
+}
+
+impl HaltingAnalysis {
+    // Halts after the fewest instructions
+    pub fn first(&self) -> Option<&Candidate> {
+        self.candidates.first()
+    }
+
+    // Halts after the most instructions, only known once the values repeat
+    pub fn last(&self) -> Option<&Candidate> {
+        match self.halt {
+            None => self.candidates.last(),
+            Some(_) => None
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HaltingError {
+    // No `eqrr` against r0 leads out of the program
+    NoExitCheck,
+
+    // r0 is the ip register, so it cannot be an input
+    BoundToIp,
+
+    // The instruction at the address uses r0 other than in an exit check
+    InputUsed(usize)
+}
+
+impl fmt::Display for HaltingError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HaltingError::NoExitCheck => write!(fmt, "no comparison against r0 leaves the program"),
+            HaltingError::BoundToIp => write!(fmt, "r0 is bound to the ip"),
+            HaltingError::InputUsed(ip) => write!(fmt, "r0 is used at {} besides the exit checks", ip)
+        }
+    }
+}
+
+impl std::error::Error for HaltingError { }
+
+// Instructions executed from `target` on until the program is left, if it gets
+// there without any further conditions
+fn exit_distance(flows: &[Flow], target: Target) -> Option<usize> {
+    let mut at = target;
+    let mut steps = 0;
+
+    while let Target::Addr(ip) = at {
+        if steps > flows.len() {
+            return None;
+        }
+
+        at = match flows[ip] {
+            Flow::Next => Target::Addr(ip + 1),
+            Flow::Jump(to) => to,
+            Flow::Branch { .. } | Flow::Computed => return None
+        };
+
+        steps += 1;
+    }
+
+    Some(steps)
+}
+
+// The exit checks of a program meant to be analyzed, where r0 is an input
+// only read by the checks
+pub fn exit_checks(program: &Program) -> Result<Vec<ExitCheck>, HaltingError> {
+    if program.ip_register() == Some(0) {
+        return Err(HaltingError::BoundToIp);
+    }
+
+    let flows = cfg::flow(program);
+    let ops = program.instructions();
+    let mut checks = Vec::new();
+
+    for (ip, op) in ops.iter().enumerate() {
+        let register = match (op.mnemonic, op.a, op.b) {
+            (Mnemonic::Eqrr, Slot::Reg(0), Slot::Reg(r)) | (Mnemonic::Eqrr, Slot::Reg(r), Slot::Reg(0)) if r != 0 => Some(r),
+            _ => None
+        };
+
+        let exit_steps = match (register, flows.get(ip + 1)) {
+            (Some(_), Some(Flow::Branch { flag, taken })) if op.target() == Some(*flag) => exit_distance(&flows, *taken),
+            _ => None
+        };
+
+        match (register, exit_steps) {
+            (Some(register), Some(steps)) => checks.push(ExitCheck { ip, register, exit_steps: steps + 2 }),
+
+            // Anything else reading or writing r0 makes it more than a plain input
+            _ => {
+                let shape = op.mnemonic.shape();
+                let uses = [(shape.a, op.a), (shape.b, op.b), (shape.c, op.c)]
+                    .iter()
+                    .any(|(operand, slot)| *operand == Operand::Register && *slot == Slot::Reg(0));
+
+                if uses {
+                    return Err(HaltingError::InputUsed(ip));
+                }
+            }
+        }
+    }
+
+    if checks.is_empty() {
+        Err(HaltingError::NoExitCheck)
+    } else {
+        Ok(checks)
+    }
+}
+
+// Runs the program on the machine, which should have just been created for it,
+// and collects every value of r0 that would make it halt at an exit check. r0 is
+// set to -1 for the run, so a program computing -1 stops the analysis early.
+pub fn analyze<E, const N: usize>(program: &Program, machine: &mut E, max_steps: Option<usize>) -> Result<HaltingAnalysis, HaltingError>
+    where E: Executor<N>
+{
+    let checks = exit_checks(program)?;
+    let ip_register = machine.ip_register();
+
+    let mut seen_values = HashSet::new();
+    let mut seen_states = HashSet::new();
+    let mut candidates = Vec::new();
+
+    machine.registers_mut()[0] = -1;
+
+    let halt = loop {
+        let steps = machine.steps();
+
+        if max_steps.map(|max| steps >= max).unwrap_or(false) {
+            break Some(Halt::StepLimit);
+        }
+
+        let at = machine.ip();
+        let check = checks.iter().find(|c| c.ip as Word == at);
+
+        // A check on a register the machine does not have faults when stepping
+        if let Some((check, &value)) = check.and_then(|c| Some((c, machine.registers().get(c.register as usize)?))) {
+            if seen_values.insert(value) {
+                candidates.push(Candidate { value, ip: check.ip, steps: steps + check.exit_steps });
+            }
+
+            // Everything but the input and the ip is the same as before, so it all repeats from here
+            let mut state = *machine.registers();
+
+            for r in Some(0).into_iter().chain(ip_register) {
+                if let Some(r) = state.get_mut(r as usize) {
+                    *r = 0;
+                }
+            }
+
+            if !seen_states.insert((check.ip, state)) {
+                break None;
+            }
+        }
+
+        if let Err(halt) = machine.step() {
+            break Some(halt);
+        }
+    };
+
+    Ok(HaltingAnalysis { checks, candidates, halt })
+}
@@ -0,0 +1,222 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use super::{AluError, ArithmeticMode, Mnemonic, Word};
+
+// How an instruction uses one of its operands
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Operand {
+    Register,
+    Immediate,
+
+    // Ignored, and may be left out in assembly
+    Unused
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Shape {
+    pub a: Operand,
+    pub b: Operand,
+    pub c: Operand
+}
+
+impl Shape {
+    pub const fn new(a: Operand, b: Operand, c: Operand) -> Self {
+        Shape { a, b, c }
+    }
+}
+
+// Operands as the listing shows them, `rA` for a register, `A` for an immediate
+impl fmt::Display for Shape {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let operands = [(self.a, "A"), (self.b, "B"), (self.c, "C")]
+            .iter()
+            .map(|(operand, name)| match operand {
+                Operand::Register => format!("r{}", name),
+                Operand::Immediate => name.to_string(),
+                Operand::Unused => "-".to_string()
+            })
+            .collect::<Vec<_>>();
+
+        fmt.pad(&operands.join(" "))
+    }
+}
+
+// One of the 16 base instructions
+#[derive(Debug, Copy, Clone)]
+pub struct Definition {
+    pub mnemonic: Mnemonic,
+    pub name: &'static str,
+
+    // Opcode number in the numbering of the day16 device
+    pub number: Word,
+
+    pub shape: Shape,
+
+    // What it computes, in the notation of the listing
+    pub summary: &'static str
+}
+
+const fn define(mnemonic: Mnemonic, name: &'static str, number: Word, shape: Shape, summary: &'static str) -> Definition {
+    Definition { mnemonic, name, number, shape, summary }
+}
+
+const RR: Shape = Shape::new(Operand::Register, Operand::Register, Operand::Register);
+const RI: Shape = Shape::new(Operand::Register, Operand::Immediate, Operand::Register);
+const IR: Shape = Shape::new(Operand::Immediate, Operand::Register, Operand::Register);
+const RU: Shape = Shape::new(Operand::Register, Operand::Unused, Operand::Register);
+const IU: Shape = Shape::new(Operand::Immediate, Operand::Unused, Operand::Register);
+const R: Shape = Shape::new(Operand::Register, Operand::Unused, Operand::Unused);
+const NONE: Shape = Shape::new(Operand::Unused, Operand::Unused, Operand::Unused);
+
+// Names, numbers, shapes and summaries of the base instructions, which
+// `Mnemonic::ALL` is built from. Parsing, printing and decoding go by this
+// table, what the instructions compute is up to `Alu::execute` and the
+// compiled machine. Rows follow the order the variants are declared in.
+pub const BASE: [Definition; 16] = [
+    define(Mnemonic::Addr, "addr", 6, RR, "rC = rA + rB"),
+    define(Mnemonic::Addi, "addi", 9, RI, "rC = rA + B"),
+    define(Mnemonic::Mulr, "mulr", 14, RR, "rC = rA * rB"),
+    define(Mnemonic::Muli, "muli", 1, RI, "rC = rA * B"),
+    define(Mnemonic::Banr, "banr", 2, RR, "rC = rA & rB"),
+    define(Mnemonic::Bani, "bani", 3, RI, "rC = rA & B"),
+    define(Mnemonic::Borr, "borr", 12, RR, "rC = rA | rB"),
+    define(Mnemonic::Bori, "bori", 0, RI, "rC = rA | B"),
+    define(Mnemonic::Setr, "setr", 5, RU, "rC = rA"),
+    define(Mnemonic::Seti, "seti", 8, IU, "rC = A"),
+    define(Mnemonic::Gtir, "gtir", 4, IR, "rC = A > rB"),
+    define(Mnemonic::Gtri, "gtri", 15, RI, "rC = rA > B"),
+    define(Mnemonic::Gtrr, "gtrr", 13, RR, "rC = rA > rB"),
+    define(Mnemonic::Eqir, "eqir", 7, IR, "rC = A == rB"),
+    define(Mnemonic::Eqri, "eqri", 11, RI, "rC = rA == B"),
+    define(Mnemonic::Eqrr, "eqrr", 10, RR, "rC = rA == rB")
+];
+
+// What executing an instruction amounts to
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Effect {
+    // Stores the value to the C register
+    Store(Word),
+
+    // Does nothing
+    Continue,
+
+    // Hands the value to whoever runs the machine
+    Output(Word),
+
+    // Stops the machine
+    Halt
+}
+
+// An operation beyond the 16 base instructions. Operand values are loaded
+// according to the shape before `execute` gets them, unused ones are 0.
+pub trait Operation: Sync {
+    fn name(&self) -> &'static str;
+
+    fn shape(&self) -> Shape;
+
+    // What it does, for the listing
+    fn summary(&self) -> &'static str {
+        ""
+    }
+
+    fn execute(&self, a: Word, b: Word, mode: ArithmeticMode) -> Result<Effect, AluError>;
+}
+
+struct Extra {
+    name: &'static str,
+    shape: Shape,
+    summary: &'static str,
+    execute: fn(Word, Word, ArithmeticMode) -> Result<Effect, AluError>
+}
+
+impl Operation for Extra {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn shape(&self) -> Shape {
+        self.shape
+    }
+
+    fn summary(&self) -> &'static str {
+        self.summary
+    }
+
+    fn execute(&self, a: Word, b: Word, mode: ArithmeticMode) -> Result<Effect, AluError> {
+        (self.execute)(a, b, mode)
+    }
+}
+
+static DIVR: Extra = Extra { name: "divr", shape: RR, summary: "rC = rA / rB", execute: |a, b, mode| mode.div(a, b).map(Effect::Store) };
+static DIVI: Extra = Extra { name: "divi", shape: RI, summary: "rC = rA / B", execute: |a, b, mode| mode.div(a, b).map(Effect::Store) };
+static MODR: Extra = Extra { name: "modr", shape: RR, summary: "rC = rA % rB", execute: |a, b, mode| mode.rem(a, b).map(Effect::Store) };
+static MODI: Extra = Extra { name: "modi", shape: RI, summary: "rC = rA % B", execute: |a, b, mode| mode.rem(a, b).map(Effect::Store) };
+static NOP: Extra = Extra { name: "nop", shape: NONE, summary: "nothing", execute: |_, _, _| Ok(Effect::Continue) };
+static HALT: Extra = Extra { name: "halt", shape: NONE, summary: "stop the machine", execute: |_, _, _| Ok(Effect::Halt) };
+static OUT: Extra = Extra { name: "out", shape: R, summary: "output rA", execute: |a, _, _| Ok(Effect::Output(a)) };
+
+// Division, remainder, `nop`, `halt` and `out`, everything `InstructionSet::extended` adds
+pub fn extras() -> [&'static dyn Operation; 7] {
+    [&DIVR, &DIVI, &MODR, &MODI, &NOP, &HALT, &OUT]
+}
+
+// The base instructions along with any registered operations, used to assemble programs
+#[derive(Clone, Default)]
+pub struct InstructionSet {
+    operations: BTreeMap<&'static str, &'static dyn Operation>
+}
+
+impl InstructionSet {
+    // Only the 16 base instructions
+    pub fn base() -> Self {
+        InstructionSet::default()
+    }
+
+    pub fn extended() -> Self {
+        let mut set = InstructionSet::base();
+
+        for op in extras().iter() {
+            set.register(*op);
+        }
+
+        set
+    }
+
+    // Adds an operation, unless its name is already taken
+    pub fn register(&mut self, operation: &'static dyn Operation) -> bool {
+        let name = operation.name();
+
+        if name.parse::<Mnemonic>().is_ok() || self.operations.contains_key(name) {
+            return false;
+        }
+
+        self.operations.insert(name, operation);
+        true
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<Mnemonic> {
+        name.parse().ok().or_else(|| self.operations.get(name).map(|op| Mnemonic::Ext(*op)))
+    }
+
+    // Registered operations by name
+    pub fn operations(&self) -> impl Iterator<Item = &'static dyn Operation> + '_ {
+        self.operations.values().cloned()
+    }
+
+    // Base instructions followed by the registered operations
+    pub fn mnemonics(&self) -> impl Iterator<Item = Mnemonic> + '_ {
+        Mnemonic::ALL.iter().cloned().chain(self.operations().map(Mnemonic::Ext))
+    }
+}
+
+// Every instruction with its operands and what it does, one per line
+impl fmt::Display for InstructionSet {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        for mnemonic in self.mnemonics() {
+            writeln!(fmt, "{:<4}  {:<8}  {}", mnemonic, mnemonic.shape(), mnemonic.summary())?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,248 @@
+use std::fmt;
+use std::sync::Arc;
+
+use super::{Alu, AluError, ArithmeticMode, Effect, Opcode, Program, Register, RegisterState, Snapshot, Word, REGISTER_COUNT};
+
+// An instruction that could not be executed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Fault {
+    pub ip: usize,
+    pub opcode: Opcode,
+    pub error: AluError
+}
+
+impl Fault {
+    // Register index that caused the fault, if any
+    pub fn register(&self) -> Option<Register> {
+        match self.error {
+            AluError::InvalidRegister(r) => Some(r),
+            _ => None
+        }
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{} at {} ({})", self.error, self.ip, self.opcode)
+    }
+}
+
+impl std::error::Error for Fault { }
+
+// Why a machine stopped running
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Halt {
+    // The instruction pointer left the program, which is how programs end normally
+    IpOutOfRange(Word),
+
+    Fault(Fault),
+
+    // A `halt` instruction at the given address was executed
+    Stopped(usize),
+
+    // `run_for` used up its budget while the program was still running
+    StepLimit
+}
+
+impl fmt::Display for Halt {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Halt::IpOutOfRange(ip) => write!(fmt, "ip {} out of range", ip),
+            Halt::Fault(fault) => write!(fmt, "{}", fault),
+            Halt::Stopped(ip) => write!(fmt, "stopped at {}", ip),
+            Halt::StepLimit => write!(fmt, "step limit reached")
+        }
+    }
+}
+
+// Runs a program on an ALU. If the program binds the instruction pointer to a
+// register, the ip is written to that register before every instruction and
+// read back from it afterwards, before moving on to the next instruction.
+// Clones share the program, so cloning is about as cheap as a `Snapshot`.
+#[derive(Clone)]
+pub struct Machine<const N: usize = REGISTER_COUNT> {
+    program: Arc<Program>,
+    fingerprint: u64,
+    ip_register: Option<Register>,
+
+    ip: Word,
+    steps: usize,
+
+    alu: Alu<N>,
+
+    // Values produced by `out` instructions
+    output: Vec<Word>
+}
+
+impl Machine {
+    pub fn new(program: Program) -> Self {
+        Machine::with_registers(program, [0; REGISTER_COUNT])
+    }
+}
+
+impl<const N: usize> Machine<N> {
+    // Machine with `N` registers starting out with the given values
+    pub fn with_registers(program: Program, registers: RegisterState<N>) -> Self {
+        Machine {
+            ip_register: program.ip_register(),
+            fingerprint: program.fingerprint(),
+            program: Arc::new(program),
+
+            ip: 0,
+            steps: 0,
+
+            alu: Alu::with_registers(registers),
+            output: Vec::new()
+        }
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    pub fn ip_register(&self) -> Option<Register> {
+        self.ip_register
+    }
+
+    pub fn bind_ip(&mut self, ip_register: Option<Register>) {
+        self.ip_register = ip_register;
+    }
+
+    pub fn ip(&self) -> Word {
+        self.ip
+    }
+
+    pub fn set_ip(&mut self, ip: Word) {
+        self.ip = ip;
+    }
+
+    // Number of instructions executed so far
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    pub fn mode(&self) -> ArithmeticMode {
+        self.alu.mode()
+    }
+
+    pub fn set_mode(&mut self, mode: ArithmeticMode) {
+        self.alu.set_mode(mode);
+    }
+
+    pub fn registers(&self) -> &RegisterState<N> {
+        &self.alu.regs
+    }
+
+    pub fn registers_mut(&mut self) -> &mut RegisterState<N> {
+        &mut self.alu.regs
+    }
+
+    pub fn output(&self) -> &[Word] {
+        &self.output
+    }
+
+    pub fn take_output(&mut self) -> Vec<Word> {
+        std::mem::take(&mut self.output)
+    }
+
+    pub fn snapshot(&self) -> Snapshot<N> {
+        Snapshot {
+            program: self.fingerprint,
+            ip_register: self.ip_register,
+
+            ip: self.ip,
+            steps: self.steps,
+
+            mode: self.alu.mode(),
+            registers: self.alu.regs,
+
+            output: self.output.clone()
+        }
+    }
+
+    // Puts the machine back into the state of the snapshot. Does nothing and
+    // returns false if the snapshot was taken of a different program.
+    pub fn restore(&mut self, snapshot: &Snapshot<N>) -> bool {
+        if snapshot.program != self.fingerprint {
+            return false;
+        }
+
+        self.ip_register = snapshot.ip_register;
+        self.ip = snapshot.ip;
+        self.steps = snapshot.steps;
+        self.alu.set_mode(snapshot.mode);
+        self.alu.regs = snapshot.registers;
+        self.output = snapshot.output.clone();
+
+        true
+    }
+
+    // Instruction the next step would execute, if the ip is within the program
+    pub fn current(&self) -> Option<(usize, &Opcode)> {
+        if self.ip < 0 {
+            return None;
+        }
+
+        let ip = self.ip as usize;
+
+        self.program.get(ip).map(|op| (ip, op))
+    }
+
+    // Executes a single instruction, returning its address
+    pub fn step(&mut self) -> Result<usize, Halt> {
+        let (ip, op) = match self.current() {
+            Some((ip, op)) => (ip, *op),
+            None => return Err(Halt::IpOutOfRange(self.ip))
+        };
+
+        let fault = |error| Halt::Fault(Fault { ip, opcode: op, error });
+        let bound = match self.ip_register {
+            Some(r) if r < 0 || r as usize >= N => return Err(fault(AluError::InvalidRegister(r))),
+            Some(r) => Some(r as usize),
+            None => None
+        };
+
+        if let Some(r) = bound {
+            self.alu.regs[r] = self.ip;
+        }
+
+        match self.alu.execute(&op).map_err(fault)? {
+            Effect::Output(value) => self.output.push(value),
+            Effect::Halt => return Err(Halt::Stopped(ip)),
+            Effect::Store(_) | Effect::Continue => { }
+        }
+
+        if let Some(r) = bound {
+            self.ip = self.alu.regs[r];
+        }
+
+        // The ip register can hold any value, past `Word::MAX` is just as much
+        // out of range as anything negative
+        self.ip = self.ip.wrapping_add(1);
+        self.steps += 1;
+
+        Ok(ip)
+    }
+
+    pub fn run(&mut self) -> Halt {
+        loop {
+            if let Err(halt) = self.step() {
+                return halt;
+            }
+        }
+    }
+
+    // Like `run`, but stops with `Halt::StepLimit` after at most `n` instructions
+    pub fn run_for(&mut self, n: usize) -> Halt {
+        for _ in 0..n {
+            if let Err(halt) = self.step() {
+                return halt;
+            }
+        }
+
+        match self.current() {
+            Some(_) => Halt::StepLimit,
+            None => Halt::IpOutOfRange(self.ip)
+        }
+    }
+}
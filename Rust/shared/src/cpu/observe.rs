@@ -0,0 +1,272 @@
+use std::collections::BTreeMap;
+
+use super::cfg::is_comparison;
+use super::{CompiledMachine, Halt, Machine, Mnemonic, Opcode, Operand, Register, RegisterState, Slot, TraceWrite, Word, REGISTER_COUNT};
+
+// A machine observers can be attached to
+pub trait Executor<const N: usize> {
+    fn ip(&self) -> Word;
+
+    fn ip_register(&self) -> Option<Register>;
+
+    fn steps(&self) -> usize;
+
+    fn registers(&self) -> &RegisterState<N>;
+
+    fn registers_mut(&mut self) -> &mut RegisterState<N>;
+
+    // Values produced by `out` instructions
+    fn output(&self) -> &[Word];
+
+    // Instruction the machine executes at `ip`. None outside the program and
+    // where `CompiledMachine::optimize` replaced a whole loop with a macro.
+    fn instruction(&self, ip: usize) -> Option<Opcode>;
+
+    fn step(&mut self) -> Result<usize, Halt>;
+}
+
+impl<const N: usize> Executor<N> for Machine<N> {
+    fn ip(&self) -> Word {
+        Machine::ip(self)
+    }
+
+    fn ip_register(&self) -> Option<Register> {
+        Machine::ip_register(self)
+    }
+
+    fn steps(&self) -> usize {
+        Machine::steps(self)
+    }
+
+    fn registers(&self) -> &RegisterState<N> {
+        Machine::registers(self)
+    }
+
+    fn registers_mut(&mut self) -> &mut RegisterState<N> {
+        Machine::registers_mut(self)
+    }
+
+    fn output(&self) -> &[Word] {
+        Machine::output(self)
+    }
+
+    fn instruction(&self, ip: usize) -> Option<Opcode> {
+        self.program().get(ip).cloned()
+    }
+
+    fn step(&mut self) -> Result<usize, Halt> {
+        Machine::step(self)
+    }
+}
+
+impl Executor<REGISTER_COUNT> for CompiledMachine {
+    fn ip(&self) -> Word {
+        CompiledMachine::ip(self)
+    }
+
+    fn ip_register(&self) -> Option<Register> {
+        CompiledMachine::ip_register(self)
+    }
+
+    fn steps(&self) -> usize {
+        CompiledMachine::steps(self)
+    }
+
+    fn registers(&self) -> &RegisterState {
+        CompiledMachine::registers(self)
+    }
+
+    fn registers_mut(&mut self) -> &mut RegisterState {
+        CompiledMachine::registers_mut(self)
+    }
+
+    fn output(&self) -> &[Word] {
+        CompiledMachine::output(self)
+    }
+
+    fn instruction(&self, ip: usize) -> Option<Opcode> {
+        CompiledMachine::instruction(self, ip)
+    }
+
+    fn step(&mut self) -> Result<usize, Halt> {
+        CompiledMachine::step(self)
+    }
+}
+
+// A comparison instruction with the values it compared
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Comparison {
+    pub ip: usize,
+    pub mnemonic: Mnemonic,
+
+    pub a: Word,
+    pub b: Word,
+    pub result: bool
+}
+
+fn load(slot: Slot, operand: Operand, regs: &[Word]) -> Word {
+    match (slot, operand) {
+        (Slot::Reg(r), Operand::Register) => regs.get(r as usize).cloned().unwrap_or(0),
+        (Slot::Immediate(i), _) => i,
+        (Slot::Reg(r), _) => r as Word
+    }
+}
+
+type Hook<'a, T, const N: usize> = Box<dyn FnMut(&RegisterState<N>) -> Option<T> + 'a>;
+type WriteHook<'a, T> = Box<dyn FnMut(usize, TraceWrite) -> Option<T> + 'a>;
+type StepHook<'a, T, const N: usize> = Box<dyn FnMut(usize, &RegisterState<N>) -> Option<T> + 'a>;
+
+// Callbacks run alongside a machine, any of which can stop it by returning a value:
+//
+//   let result = Observers::new()
+//       .at(28, |regs| if regs[1] == 0 { Some(regs[1]) } else { None })
+//       .run(&mut machine);
+//
+// Write and comparison hooks never see the instructions inside a macro.
+pub struct Observers<'a, T, const N: usize = REGISTER_COUNT> {
+    at: BTreeMap<usize, Vec<Hook<'a, T, N>>>,
+    writes: Vec<(Register, WriteHook<'a, T>)>,
+    comparisons: Vec<Box<dyn FnMut(Comparison) -> Option<T> + 'a>>,
+    every: Vec<(usize, StepHook<'a, T, N>)>,
+
+    // Step count the last run stopped at in an `at` hook, which is not run
+    // again when continuing from there
+    stopped_at: Option<usize>
+}
+
+impl<'a, T, const N: usize> Default for Observers<'a, T, N> {
+    fn default() -> Self {
+        Observers {
+            at: BTreeMap::new(),
+            writes: Vec::new(),
+            comparisons: Vec::new(),
+            every: Vec::new(),
+
+            stopped_at: None
+        }
+    }
+}
+
+impl<'a, T, const N: usize> Observers<'a, T, N> {
+    pub fn new() -> Self {
+        Observers::default()
+    }
+
+    // Called with the registers when about to execute the instruction at `ip`.
+    // The ip register does not hold `ip` yet at that point.
+    pub fn at<F>(mut self, ip: usize, hook: F) -> Self
+        where F: FnMut(&RegisterState<N>) -> Option<T> + 'a
+    {
+        self.at.entry(ip).or_default().push(Box::new(hook));
+        self
+    }
+
+    // Called with the address of every instruction storing to the register
+    pub fn write<F>(mut self, register: Register, hook: F) -> Self
+        where F: FnMut(usize, TraceWrite) -> Option<T> + 'a
+    {
+        self.writes.push((register, Box::new(hook)));
+        self
+    }
+
+    // Called after every `gt*` and `eq*` instruction
+    pub fn comparison<F>(mut self, hook: F) -> Self
+        where F: FnMut(Comparison) -> Option<T> + 'a
+    {
+        self.comparisons.push(Box::new(hook));
+        self
+    }
+
+    // Called with the step count and the registers whenever the machine has
+    // executed a multiple of `n` instructions, or gone past one in a macro
+    pub fn every<F>(mut self, n: usize, hook: F) -> Self
+        where F: FnMut(usize, &RegisterState<N>) -> Option<T> + 'a
+    {
+        self.every.push((n.max(1), Box::new(hook)));
+        self
+    }
+
+    // Runs the machine until a hook returns a value or the machine halts
+    pub fn run<E>(&mut self, machine: &mut E) -> Result<T, Halt>
+        where E: Executor<N>
+    {
+        let watching = !self.writes.is_empty() || !self.comparisons.is_empty();
+
+        loop {
+            let ip = machine.ip();
+
+            if ip >= 0 && self.stopped_at.take() != Some(machine.steps()) {
+                for hook in self.at.get_mut(&(ip as usize)).into_iter().flatten() {
+                    if let Some(value) = hook(machine.registers()) {
+                        self.stopped_at = Some(machine.steps());
+                        return Ok(value);
+                    }
+                }
+            }
+
+            // Registers as the instruction sees them, with the ip written to its register
+            let before = if watching {
+                let mut regs = *machine.registers();
+
+                if let Some(r) = machine.ip_register().and_then(|r| regs.get_mut(r as usize)) {
+                    *r = ip;
+                }
+
+                Some(regs)
+            } else {
+                None
+            };
+
+            let previous = machine.steps();
+            let ip = machine.step()?;
+
+            if let (Some(before), Some(op)) = (before, machine.instruction(ip)) {
+                if let Some(value) = self.observe(ip, &op, &before, machine.registers()) {
+                    return Ok(value);
+                }
+            }
+
+            let steps = machine.steps();
+
+            for (n, hook) in self.every.iter_mut() {
+                if steps / *n != previous / *n {
+                    if let Some(value) = hook(steps, machine.registers()) {
+                        return Ok(value);
+                    }
+                }
+            }
+        }
+    }
+
+    fn observe(&mut self, ip: usize, op: &Opcode, before: &[Word], after: &[Word]) -> Option<T> {
+        let target = op.target().and_then(|r| Some((r, *before.get(r as usize)?, *after.get(r as usize)?)));
+
+        if let Some((register, old, new)) = target {
+            for (_, hook) in self.writes.iter_mut().filter(|(r, _)| *r == register) {
+                if let Some(value) = hook(ip, TraceWrite { register, old, new }) {
+                    return Some(value);
+                }
+            }
+        }
+
+        if is_comparison(op.mnemonic) && !self.comparisons.is_empty() {
+            let shape = op.mnemonic.shape();
+            let comparison = Comparison {
+                ip,
+                mnemonic: op.mnemonic,
+
+                a: load(op.a, shape.a, before),
+                b: load(op.b, shape.b, before),
+                result: target.map(|(_, _, new)| new == 1).unwrap_or(false)
+            };
+
+            for hook in self.comparisons.iter_mut() {
+                if let Some(value) = hook(comparison) {
+                    return Some(value);
+                }
+            }
+        }
+
+        None
+    }
+}
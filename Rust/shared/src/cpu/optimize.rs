@@ -0,0 +1,282 @@
+use super::cfg::offset_register;
+use super::{Mnemonic, Opcode, Program, Register, Slot, Word};
+
+// A loop the optimizer knows how to compute in one go
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Idiom {
+    // Adds every divisor of `n` to `sum` by trying all products `outer * inner`
+    // for `outer` and `inner` counting up from 1 to `n` (day19)
+    DivisorSum {
+        n: Register,
+        sum: Register,
+        outer: Register,
+        inner: Register,
+        flag: Register
+    },
+
+    // Divides `dividend` by `divisor` by counting up `quotient` until
+    // `(quotient + 1) * divisor` exceeds the dividend (day21)
+    Divide {
+        dividend: Register,
+        divisor: Word,
+        quotient: Register,
+        scratch: Register
+    }
+}
+
+// An idiom found in a program, spanning the instructions from `start` up to but
+// not including `exit`, which is where it always leaves the loop
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Macro {
+    pub start: usize,
+    pub exit: usize,
+    pub ip_register: Register,
+    pub idiom: Idiom
+}
+
+fn reg(slot: Slot) -> Option<Register> {
+    match slot {
+        Slot::Reg(r) => Some(r),
+        Slot::Immediate(_) => None
+    }
+}
+
+fn imm(slot: Slot) -> Option<Word> {
+    match slot {
+        Slot::Immediate(i) => Some(i),
+        Slot::Reg(_) => None
+    }
+}
+
+fn distinct(regs: &[Register]) -> bool {
+    regs.iter().enumerate().all(|(i, r)| !regs[i + 1..].contains(r))
+}
+
+// Operands of a commutative instruction in either order, starting with `first`
+fn commuted(op: &Opcode, mnemonic: Mnemonic, first: Register) -> Option<Register> {
+    match (reg(op.a)?, reg(op.b)?) {
+        _ if op.mnemonic != mnemonic => None,
+        (a, b) if a == first => Some(b),
+        (a, b) if b == first => Some(a),
+        _ => None
+    }
+}
+
+// `seti` storing its constant into `c`
+fn set(op: &Opcode, c: Register) -> Option<Word> {
+    match op.mnemonic {
+        Mnemonic::Seti if reg(op.c) == Some(c) => imm(op.a),
+        _ => None
+    }
+}
+
+// `addi a 1 a`
+fn increments(op: &Opcode, a: Register) -> bool {
+    op.mnemonic == Mnemonic::Addi && reg(op.a) == Some(a) && imm(op.b) == Some(1) && reg(op.c) == Some(a)
+}
+
+// `seti <target - 1> _ ip`, a jump to `target`
+fn jumps_to(op: &Opcode, ip: Register, target: usize) -> bool {
+    set(op, ip) == Some(target as Word - 1)
+}
+
+// `addr flag ip ip` followed by `addi ip 1 ip`, skipping over the jump after them if `flag` is set
+fn skips_unless(ops: &[Opcode], ip: Register, flag: Register) -> bool {
+    offset_register(&ops[0], ip) == Some(flag) && reg(ops[0].c) == Some(ip) &&
+        increments(&ops[1], ip)
+}
+
+//   seti 1 _ outer
+//   seti 1 _ inner
+//   mulr outer inner flag
+//   eqrr flag n flag
+//   addr flag ip ip
+//   addi ip 1 ip
+//   addr outer sum sum
+//   addi inner 1 inner
+//   gtrr inner n flag
+//   addr ip flag ip
+//   seti <start + 1> _ ip
+//   addi outer 1 outer
+//   gtrr outer n flag
+//   addr flag ip ip
+//   seti <start> _ ip
+fn divisor_sum(ops: &[Opcode], start: usize, ip: Register) -> Option<Macro> {
+    let ops = ops.get(start..start + 15)?;
+
+    let outer = reg(ops[0].c)?;
+    let inner = reg(ops[1].c)?;
+
+    if set(&ops[0], outer)? != 1 || set(&ops[1], inner)? != 1 {
+        return None;
+    }
+
+    let flag = reg(ops[2].c)?;
+    let n = commuted(&ops[3], Mnemonic::Eqrr, flag)?;
+    let sum = commuted(&ops[6], Mnemonic::Addr, outer)?;
+
+    let matches =
+        commuted(&ops[2], Mnemonic::Mulr, outer)? == inner &&
+        reg(ops[3].c)? == flag &&
+        skips_unless(&ops[4..], ip, flag) &&
+        reg(ops[6].c)? == sum &&
+        increments(&ops[7], inner) &&
+        ops[8].mnemonic == Mnemonic::Gtrr && (reg(ops[8].a), reg(ops[8].b), reg(ops[8].c)) == (Some(inner), Some(n), Some(flag)) &&
+        offset_register(&ops[9], ip) == Some(flag) && reg(ops[9].c) == Some(ip) &&
+        jumps_to(&ops[10], ip, start + 2) &&
+        increments(&ops[11], outer) &&
+        ops[12].mnemonic == Mnemonic::Gtrr && (reg(ops[12].a), reg(ops[12].b), reg(ops[12].c)) == (Some(outer), Some(n), Some(flag)) &&
+        offset_register(&ops[13], ip) == Some(flag) && reg(ops[13].c) == Some(ip) &&
+        jumps_to(&ops[14], ip, start + 1) &&
+        distinct(&[n, sum, outer, inner, flag, ip]);
+
+    if !matches {
+        return None;
+    }
+
+    Some(Macro {
+        start,
+        exit: start + 15,
+        ip_register: ip,
+        idiom: Idiom::DivisorSum { n, sum, outer, inner, flag }
+    })
+}
+
+//   seti 0 _ quotient
+//   addi quotient 1 scratch
+//   muli scratch <divisor> scratch
+//   gtrr scratch dividend scratch
+//   addr scratch ip ip
+//   addi ip 1 ip
+//   seti <start + 8> _ ip
+//   addi quotient 1 quotient
+//   seti <start> _ ip
+fn divide(ops: &[Opcode], start: usize, ip: Register) -> Option<Macro> {
+    let ops = ops.get(start..start + 9)?;
+
+    let quotient = reg(ops[0].c)?;
+    let scratch = reg(ops[1].c)?;
+    let dividend = reg(ops[3].b)?;
+    let divisor = imm(ops[2].b)?;
+
+    let matches =
+        set(&ops[0], quotient)? == 0 &&
+        ops[1].mnemonic == Mnemonic::Addi && reg(ops[1].a) == Some(quotient) && imm(ops[1].b) == Some(1) &&
+        ops[2].mnemonic == Mnemonic::Muli && reg(ops[2].a) == Some(scratch) && reg(ops[2].c) == Some(scratch) &&
+        ops[3].mnemonic == Mnemonic::Gtrr && reg(ops[3].a) == Some(scratch) && reg(ops[3].c) == Some(scratch) &&
+        skips_unless(&ops[4..], ip, scratch) &&
+        jumps_to(&ops[6], ip, start + 9) &&
+        increments(&ops[7], quotient) &&
+        jumps_to(&ops[8], ip, start + 1) &&
+        divisor > 0 &&
+        distinct(&[dividend, quotient, scratch, ip]);
+
+    if !matches {
+        return None;
+    }
+
+    Some(Macro {
+        start,
+        exit: start + 9,
+        ip_register: ip,
+        idiom: Idiom::Divide { dividend, divisor, quotient, scratch }
+    })
+}
+
+// Every known idiom in the program. They all need the ip to be bound, as that
+// is how the loops jump around.
+pub fn find_macros(program: &Program) -> Vec<Macro> {
+    match program.ip_register() {
+        Some(ip) => find(program.instructions(), ip),
+        None => Vec::new()
+    }
+}
+
+pub(super) fn find(ops: &[Opcode], ip: Register) -> Vec<Macro> {
+    (0..ops.len())
+        .filter_map(|start| divisor_sum(ops, start, ip).or_else(|| divide(ops, start, ip)))
+        .collect()
+}
+
+fn sum_of_divisors(n: Word) -> Option<Word> {
+    let mut sum: Word = 0;
+    let mut d = 1;
+
+    while d * d <= n {
+        if n % d == 0 {
+            sum = sum.checked_add(d)?;
+
+            if d * d != n {
+                sum = sum.checked_add(n / d)?;
+            }
+        }
+
+        d += 1;
+    }
+
+    Some(sum)
+}
+
+impl Macro {
+    // Leaves the registers exactly like running the loop from `start` would when
+    // it gets to `exit`, including the ip register, and returns how many
+    // instructions that took. Does nothing and returns None if the loop would
+    // overflow at some point, or a register is out of range.
+    pub fn apply(&self, regs: &mut [Word]) -> Option<usize> {
+        let fits = |r: Register| r >= 0 && (r as usize) < regs.len();
+
+        match self.idiom {
+            Idiom::DivisorSum { n, sum, outer, inner, flag } => {
+                if ![n, sum, outer, inner, flag, self.ip_register].iter().all(|r| fits(*r)) {
+                    return None;
+                }
+
+                // Both loops run at least once
+                let limit = regs[n as usize].max(1);
+
+                limit.checked_mul(limit)?;
+
+                // Every inner iteration takes 7 instructions and 1 more to jump back, the
+                // outer ones 1 to start the inner loop, 3 to test and 1 to jump back. The
+                // last jump back of both is left out, the first `seti` is added.
+                let l = limit as usize;
+                let steps = l.checked_mul(l)?.checked_mul(8)?.checked_add(l.checked_mul(4)?)?;
+                let total = sum_of_divisors(regs[n as usize]).and_then(|s| regs[sum as usize].checked_add(s))?;
+
+                regs[sum as usize] = total;
+                regs[outer as usize] = limit + 1;
+                regs[inner as usize] = limit + 1;
+                regs[flag as usize] = 1;
+
+                self.leave(regs);
+                Some(steps)
+            },
+
+            Idiom::Divide { dividend, divisor, quotient, scratch } => {
+                if ![dividend, quotient, scratch, self.ip_register].iter().all(|r| fits(*r)) {
+                    return None;
+                }
+
+                let x = regs[dividend as usize].max(0);
+
+                x.checked_add(divisor)?;
+
+                // The first `seti`, then 7 instructions for every increment of the
+                // quotient and 5 for the last comparison jumping out
+                let q = x / divisor;
+                let steps = (q as usize).checked_mul(7)?.checked_add(6)?;
+
+                regs[quotient as usize] = q;
+                regs[scratch as usize] = 1;
+
+                self.leave(regs);
+                Some(steps)
+            }
+        }
+    }
+
+    fn leave(&self, regs: &mut [Word]) {
+        // Last written by the jump out of the loop
+        regs[self.ip_register as usize] = self.exit as Word - 1;
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use super::{Cfg, Halt, Machine, Program, Word};
+
+// Smallest and largest value a register held
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ValueRange {
+    pub min: Word,
+    pub max: Word
+}
+
+impl ValueRange {
+    fn extend(range: &mut Option<ValueRange>, value: Word) {
+        *range = Some(match *range {
+            Some(r) => ValueRange { min: r.min.min(value), max: r.max.max(value) },
+            None => ValueRange { min: value, max: value }
+        });
+    }
+}
+
+// A loop of the program along with how much of the execution it accounts for
+#[derive(Debug, Clone)]
+pub struct HotLoop {
+    // Address of the first instruction of the loop head
+    pub head: usize,
+    pub addresses: BTreeSet<usize>,
+
+    // Instructions executed inside the loop, including nested loops
+    pub count: usize
+}
+
+// Execution counts and register ranges for every address of a program,
+// accumulated over all runs recorded into it
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    counts: Vec<usize>,
+
+    // Per address, the values each register held right before the instruction executed
+    ranges: Vec<Vec<Option<ValueRange>>>,
+
+    total: usize
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Profile::default()
+    }
+
+    // Runs the machine until it halts or `max_steps` instructions have been executed
+    pub fn record<const N: usize>(&mut self, machine: &mut Machine<N>, max_steps: Option<usize>) -> Halt {
+        let len = machine.program().len();
+
+        if self.counts.len() < len {
+            self.counts.resize(len, 0);
+            self.ranges.resize(len, vec![None; N]);
+        }
+
+        let mut executed = 0;
+
+        loop {
+            if max_steps.map(|max| executed >= max).unwrap_or(false) {
+                return Halt::StepLimit;
+            }
+
+            // The ip register holds the address once the instruction executes
+            let mut before = *machine.registers();
+
+            if let Some(r) = machine.ip_register().and_then(|r| before.get_mut(r as usize)) {
+                *r = machine.ip();
+            }
+
+            let ip = match machine.step() {
+                Ok(ip) => ip,
+                Err(halt) => return halt
+            };
+
+            executed += 1;
+
+            self.counts[ip] += 1;
+            self.total += 1;
+
+            let ranges = &mut self.ranges[ip];
+
+            if ranges.len() < N {
+                ranges.resize(N, None);
+            }
+
+            for (range, value) in ranges.iter_mut().zip(before.iter()) {
+                ValueRange::extend(range, *value);
+            }
+        }
+    }
+
+    // Instructions executed over all recorded runs
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    pub fn count(&self, ip: usize) -> usize {
+        self.counts.get(ip).cloned().unwrap_or(0)
+    }
+
+    // Share of all executed instructions in percent
+    pub fn percentage(&self, ip: usize) -> f64 {
+        if self.total == 0 {
+            0.0
+        } else {
+            self.count(ip) as f64 * 100.0 / self.total as f64
+        }
+    }
+
+    // Range of the register's values whenever the instruction at `ip` executed
+    pub fn range(&self, ip: usize, register: usize) -> Option<ValueRange> {
+        *self.ranges.get(ip)?.get(register)?
+    }
+
+    // Addresses ordered by how often they were executed, most frequent first
+    pub fn hottest(&self) -> Vec<(usize, usize)> {
+        let mut hot = self.counts.iter().cloned().enumerate().filter(|(_, c)| *c > 0).collect::<Vec<_>>();
+
+        hot.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        hot
+    }
+
+    // Loops of the program that were executed, most expensive first
+    pub fn hot_loops(&self, program: &Program) -> Vec<HotLoop> {
+        let cfg = Cfg::new(program);
+
+        let mut loops = cfg.loops().into_iter().map(|l| {
+            let addresses = l.blocks
+                .iter()
+                .flat_map(|b| cfg.blocks()[*b].start..cfg.blocks()[*b].end)
+                .collect::<BTreeSet<_>>();
+
+            HotLoop {
+                head: cfg.blocks()[l.head].start,
+                count: addresses.iter().map(|ip| self.count(*ip)).sum(),
+                addresses
+            }
+        }).filter(|l| l.count > 0).collect::<Vec<_>>();
+
+        loops.sort_by(|a, b| b.count.cmp(&a.count).then(a.head.cmp(&b.head)));
+        loops
+    }
+
+    // The program with every instruction's execution count and share. Loop heads
+    // are marked with the loop's rank from `hot_loops` and its share.
+    pub fn listing(&self, program: &Program) -> String {
+        let loops = self.hot_loops(program);
+        let mut out = String::new();
+
+        writeln!(out, "{:>12} {:>7}   ip  instruction", "count", "%").unwrap();
+
+        for (ip, op) in program.instructions().iter().enumerate() {
+            let mark = match loops.iter().position(|l| l.head == ip) {
+                Some(n) => format!("  <- loop {} ({:.2}%)", n, loops[n].count as f64 * 100.0 / self.total.max(1) as f64),
+                None => String::new()
+            };
+
+            writeln!(out, "{:>12} {:>6.2}% {:>4}: {}{}", self.count(ip), self.percentage(ip), ip, op, mark).unwrap();
+        }
+
+        writeln!(out, "{:>12} total", self.total).unwrap();
+        out
+    }
+}
@@ -0,0 +1,241 @@
+use std::convert::TryInto;
+use std::fmt;
+use std::str::FromStr;
+
+use super::{ArithmeticMode, Register, RegisterState, Word, REGISTER_COUNT};
+
+// Everything needed to continue a machine from where it was, short of the
+// program itself, which is only identified by its `Program::fingerprint`.
+// Written out as one `key value` line per field:
+//
+//   program 8c1a6bb5e7f3c2d4
+//   ip-register 5
+//   ip 28
+//   steps 1848
+//   mode checked
+//   registers 0 1 0 0 28 28
+//   output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot<const N: usize = REGISTER_COUNT> {
+    pub program: u64,
+    pub ip_register: Option<Register>,
+
+    pub ip: Word,
+    pub steps: usize,
+
+    pub mode: ArithmeticMode,
+    pub registers: RegisterState<N>,
+
+    pub output: Vec<Word>
+}
+
+// A field that differs between two snapshots, with its old and new value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    Program(u64, u64),
+    IpRegister(Option<Register>, Option<Register>),
+    Ip(Word, Word),
+    Steps(usize, usize),
+    Mode(ArithmeticMode, ArithmeticMode),
+    Register(usize, Word, Word),
+    Output(Vec<Word>, Vec<Word>)
+}
+
+fn binding(ip_register: Option<Register>) -> String {
+    match ip_register {
+        Some(r) => r.to_string(),
+        None => "none".to_string()
+    }
+}
+
+fn mode_name(mode: ArithmeticMode) -> &'static str {
+    match mode {
+        ArithmeticMode::Checked => "checked",
+        ArithmeticMode::Wrapping => "wrapping",
+        ArithmeticMode::Saturating => "saturating"
+    }
+}
+
+fn words(values: &[Word]) -> String {
+    values.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(" ")
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Difference::Program(old, new) => write!(fmt, "program: {:016x} -> {:016x}", old, new),
+            Difference::IpRegister(old, new) => write!(fmt, "ip-register: {} -> {}", binding(*old), binding(*new)),
+            Difference::Ip(old, new) => write!(fmt, "ip: {} -> {}", old, new),
+            Difference::Steps(old, new) => write!(fmt, "steps: {} -> {}", old, new),
+            Difference::Mode(old, new) => write!(fmt, "mode: {} -> {}", mode_name(*old), mode_name(*new)),
+            Difference::Register(r, old, new) => write!(fmt, "r{}: {} -> {}", r, old, new),
+            Difference::Output(old, new) => write!(fmt, "output: [{}] -> [{}]", words(old), words(new))
+        }
+    }
+}
+
+impl<const N: usize> Snapshot<N> {
+    // What changed going from this snapshot to `other`, in the order of the text format
+    pub fn diff(&self, other: &Snapshot<N>) -> Vec<Difference> {
+        let mut diff = Vec::new();
+
+        if self.program != other.program {
+            diff.push(Difference::Program(self.program, other.program));
+        }
+
+        if self.ip_register != other.ip_register {
+            diff.push(Difference::IpRegister(self.ip_register, other.ip_register));
+        }
+
+        if self.ip != other.ip {
+            diff.push(Difference::Ip(self.ip, other.ip));
+        }
+
+        if self.steps != other.steps {
+            diff.push(Difference::Steps(self.steps, other.steps));
+        }
+
+        if self.mode != other.mode {
+            diff.push(Difference::Mode(self.mode, other.mode));
+        }
+
+        for (r, (old, new)) in self.registers.iter().zip(other.registers.iter()).enumerate() {
+            if old != new {
+                diff.push(Difference::Register(r, *old, *new));
+            }
+        }
+
+        if self.output != other.output {
+            diff.push(Difference::Output(self.output.clone(), other.output.clone()));
+        }
+
+        diff
+    }
+}
+
+impl<const N: usize> fmt::Display for Snapshot<N> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(fmt, "program {:016x}", self.program)?;
+        writeln!(fmt, "ip-register {}", binding(self.ip_register))?;
+        writeln!(fmt, "ip {}", self.ip)?;
+        writeln!(fmt, "steps {}", self.steps)?;
+        writeln!(fmt, "mode {}", mode_name(self.mode))?;
+        writeln!(fmt, "registers {}", words(&self.registers))?;
+        writeln!(fmt, "output {}", words(&self.output))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotErrorKind {
+    UnknownKey(String),
+    DuplicateKey(String),
+    MissingKey(&'static str),
+    InvalidValue(String),
+
+    // Number of registers given, when the snapshot is for a different count
+    RegisterCount(usize)
+}
+
+// Line numbers start at 1, missing keys are reported on the line after the last one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotError {
+    pub line: usize,
+    pub kind: SnapshotErrorKind
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "line {}: ", self.line)?;
+
+        match &self.kind {
+            SnapshotErrorKind::UnknownKey(k) => write!(fmt, "unknown key '{}'", k),
+            SnapshotErrorKind::DuplicateKey(k) => write!(fmt, "key '{}' given more than once", k),
+            SnapshotErrorKind::MissingKey(k) => write!(fmt, "missing key '{}'", k),
+            SnapshotErrorKind::InvalidValue(v) => write!(fmt, "invalid value '{}'", v),
+            SnapshotErrorKind::RegisterCount(n) => write!(fmt, "{} registers given", n)
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError { }
+
+const KEYS: [&str; 7] = ["program", "ip-register", "ip", "steps", "mode", "registers", "output"];
+
+impl<const N: usize> FromStr for Snapshot<N> {
+    type Err = SnapshotError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut values: [Option<(usize, &str)>; 7] = [None; 7];
+        let mut lines = 0;
+
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            let error = |kind| SnapshotError { line: i + 1, kind };
+
+            lines = i + 1;
+
+            if line.is_empty() {
+                continue;
+            }
+
+            let (key, value) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let slot = KEYS.iter().position(|k| *k == key).ok_or_else(|| error(SnapshotErrorKind::UnknownKey(key.to_string())))?;
+
+            if values[slot].is_some() {
+                return Err(error(SnapshotErrorKind::DuplicateKey(key.to_string())));
+            }
+
+            values[slot] = Some((i + 1, value.trim()));
+        }
+
+        let field = |slot: usize| values[slot].ok_or(SnapshotError { line: lines + 1, kind: SnapshotErrorKind::MissingKey(KEYS[slot]) });
+        let invalid = |(line, value): (usize, &str)| SnapshotError { line, kind: SnapshotErrorKind::InvalidValue(value.to_string()) };
+
+        let number = |slot: usize| -> Result<Word, SnapshotError> {
+            let (line, value) = field(slot)?;
+
+            value.parse().map_err(|_| invalid((line, value)))
+        };
+
+        let list = |slot: usize| -> Result<Vec<Word>, SnapshotError> {
+            let (line, value) = field(slot)?;
+
+            value.split_whitespace().map(|v| v.parse().map_err(|_| invalid((line, v)))).collect()
+        };
+
+        let program = field(0).and_then(|f| u64::from_str_radix(f.1, 16).map_err(|_| invalid(f)))?;
+
+        let ip_register = match field(1)? {
+            (_, "none") => None,
+            f => Some(f.1.parse().map_err(|_| invalid(f))?)
+        };
+
+        let steps = field(3).and_then(|f| f.1.parse().map_err(|_| invalid(f)))?;
+
+        let mode = match field(4)? {
+            (_, "checked") => ArithmeticMode::Checked,
+            (_, "wrapping") => ArithmeticMode::Wrapping,
+            (_, "saturating") => ArithmeticMode::Saturating,
+            f => return Err(invalid(f))
+        };
+
+        let given = list(5)?;
+        let registers = given.as_slice().try_into().map_err(|_| SnapshotError {
+            line: field(5).map(|f| f.0).unwrap_or(0),
+            kind: SnapshotErrorKind::RegisterCount(given.len())
+        })?;
+
+        Ok(Snapshot {
+            program,
+            ip_register,
+
+            ip: number(2)?,
+            steps,
+
+            mode,
+            registers,
+
+            output: list(6)?
+        })
+    }
+}
@@ -0,0 +1,595 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::rc::Rc;
+
+use super::{AluError, ArithmeticMode, Effect, Fault, Mnemonic, Opcode, Operand, Program, Register, Slot, Word, REGISTER_COUNT};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Mul,
+    And,
+    Or,
+
+    // Comparisons are 1 if they hold and 0 otherwise
+    Gt,
+    Eq
+}
+
+// A register's value in terms of the registers' values at the start
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Const(Word),
+
+    // Unknown initial value of a register
+    Input(Register),
+
+    Binary(BinOp, Rc<Expr>, Rc<Expr>),
+
+    // An extension instruction on operands not known yet
+    Call(Mnemonic, Rc<Expr>, Rc<Expr>)
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Const(c) => write!(fmt, "{}", c),
+            Expr::Input(r) => write!(fmt, "r{}", r),
+            Expr::Binary(op, a, b) => {
+                let op = match op {
+                    BinOp::Add => "+",
+                    BinOp::Mul => "*",
+                    BinOp::And => "&",
+                    BinOp::Or => "|",
+                    BinOp::Gt => ">",
+                    BinOp::Eq => "=="
+                };
+
+                write!(fmt, "({} {} {})", a, op, b)
+            },
+            Expr::Call(m, a, b) => write!(fmt, "{}({}, {})", m.name(), a, b)
+        }
+    }
+}
+
+impl Expr {
+    pub fn constant(&self) -> Option<Word> {
+        match self {
+            Expr::Const(c) => Some(*c),
+            _ => None
+        }
+    }
+
+    // Inputs the expression depends on
+    pub fn inputs(&self) -> BTreeSet<Register> {
+        let mut inputs = BTreeSet::new();
+
+        self.collect_inputs(&mut inputs);
+        inputs
+    }
+
+    fn collect_inputs(&self, inputs: &mut BTreeSet<Register>) {
+        match self {
+            Expr::Const(_) => { },
+            Expr::Input(r) => { inputs.insert(*r); },
+            Expr::Binary(_, a, b) | Expr::Call(_, a, b) => {
+                a.collect_inputs(inputs);
+                b.collect_inputs(inputs);
+            }
+        }
+    }
+
+    // First comparison found going depth first, which execution can fork on
+    fn comparison(self: &Rc<Self>) -> Option<Rc<Expr>> {
+        match &**self {
+            Expr::Binary(BinOp::Gt, ..) | Expr::Binary(BinOp::Eq, ..) => Some(self.clone()),
+            Expr::Binary(_, a, b) | Expr::Call(_, a, b) => a.comparison().or_else(|| b.comparison()),
+            _ => None
+        }
+    }
+
+    // Replaces every occurrence of `atom`, simplifying what can be simplified again
+    fn substitute(self: &Rc<Self>, atom: &Expr, value: Word) -> Rc<Expr> {
+        if **self == *atom {
+            return Rc::new(Expr::Const(value));
+        }
+
+        match &**self {
+            Expr::Binary(op, a, b) => {
+                let (a, b) = (a.substitute(atom, value), b.substitute(atom, value));
+
+                binary(*op, a.clone(), b.clone()).unwrap_or_else(|_| Rc::new(Expr::Binary(*op, a, b)))
+            },
+            Expr::Call(m, a, b) => {
+                let (a, b) = (a.substitute(atom, value), b.substitute(atom, value));
+
+                call(*m, a.clone(), b.clone()).unwrap_or_else(|_| Rc::new(Expr::Call(*m, a, b)))
+            },
+            _ => self.clone()
+        }
+    }
+}
+
+// Builds `a op b`, folding constants with checked arithmetic and dropping neutral operands
+fn binary(op: BinOp, a: Rc<Expr>, b: Rc<Expr>) -> Result<Rc<Expr>, AluError> {
+    let mode = ArithmeticMode::Checked;
+    let value = |v: Word| Ok(Rc::new(Expr::Const(v)));
+
+    if let (Some(x), Some(y)) = (a.constant(), b.constant()) {
+        return value(match op {
+            BinOp::Add => mode.add(x, y)?,
+            BinOp::Mul => mode.mul(x, y)?,
+            BinOp::And => x & y,
+            BinOp::Or => x | y,
+            BinOp::Gt => (x > y) as Word,
+            BinOp::Eq => (x == y) as Word
+        });
+    }
+
+    // Constants go to the right, so `(x + 1) + 2` can become `x + 3`
+    let (a, b) = match (op, a.constant()) {
+        (BinOp::Add, Some(_)) | (BinOp::Mul, Some(_)) | (BinOp::And, Some(_)) | (BinOp::Or, Some(_)) | (BinOp::Eq, Some(_)) => (b, a),
+        _ => (a, b)
+    };
+
+    match (op, &*a, b.constant()) {
+        (BinOp::Add, _, Some(0)) | (BinOp::Mul, _, Some(1)) | (BinOp::Or, _, Some(0)) => return Ok(a),
+        (BinOp::Mul, _, Some(0)) | (BinOp::And, _, Some(0)) => return value(0),
+        (BinOp::Add, Expr::Binary(BinOp::Add, x, y), Some(c)) if y.constant().is_some() => {
+            return binary(BinOp::Add, x.clone(), Rc::new(Expr::Const(mode.add(y.constant().unwrap(), c)?)));
+        },
+        (BinOp::Eq, _, _) if a == b => return value(1),
+        _ => { }
+    }
+
+    Ok(Rc::new(Expr::Binary(op, a, b)))
+}
+
+fn call(m: Mnemonic, a: Rc<Expr>, b: Rc<Expr>) -> Result<Rc<Expr>, AluError> {
+    match (m, a.constant(), b.constant()) {
+        (Mnemonic::Ext(op), Some(x), Some(y)) => match op.execute(x, y, ArithmeticMode::Checked)? {
+            Effect::Store(v) => Ok(Rc::new(Expr::Const(v))),
+            _ => Ok(Rc::new(Expr::Const(0)))
+        },
+        _ => Ok(Rc::new(Expr::Call(m, a, b)))
+    }
+}
+
+// A comparison, or any other expression, that has to be nonzero (or zero) on a path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    pub expr: Rc<Expr>,
+    pub holds: bool
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        if self.holds {
+            write!(fmt, "{}", self.expr)
+        } else {
+            write!(fmt, "!{}", self.expr)
+        }
+    }
+}
+
+// How a path ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum End {
+    // The ip left the program, `Path::last` is where it jumped from
+    Exit(Word),
+
+    // A `halt` instruction at the given address was executed
+    Halted(usize),
+
+    Fault(Fault),
+
+    // The instruction at `from` set the ip to something that is not just a comparison away from a constant
+    UnknownJump { from: usize, target: Rc<Expr> },
+
+    StepLimit
+}
+
+#[derive(Debug, Clone)]
+pub struct Path {
+    pub conditions: Vec<Condition>,
+    pub registers: Vec<Rc<Expr>>,
+    pub steps: usize,
+
+    // Address of the last executed instruction
+    pub last: Option<usize>,
+
+    pub end: End
+}
+
+impl Path {
+    pub fn solve(&self) -> Option<Solution> {
+        solve(&self.conditions)
+    }
+}
+
+// Values an input can take, as far as the solver could tell
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Domain {
+    pub min: Option<Word>,
+    pub max: Option<Word>,
+    pub excluded: BTreeSet<Word>
+}
+
+impl Domain {
+    // The only possible value, if there is one
+    pub fn value(&self) -> Option<Word> {
+        match (self.min, self.max) {
+            (Some(min), Some(max)) if min == max && !self.excluded.contains(&min) => Some(min),
+            _ => None
+        }
+    }
+
+    pub fn contains(&self, value: Word) -> bool {
+        self.min.map(|min| value >= min).unwrap_or(true) &&
+            self.max.map(|max| value <= max).unwrap_or(true) &&
+            !self.excluded.contains(&value)
+    }
+
+    fn is_empty(&self) -> bool {
+        match (self.min, self.max) {
+            (Some(min), Some(max)) => min > max || (min == max && self.excluded.contains(&min)),
+            _ => false
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Solution {
+    pub inputs: BTreeMap<Register, Domain>,
+
+    // Conditions the solver could not turn into bounds, left for the caller
+    pub unsolved: Vec<Condition>
+}
+
+// `a * input + c`, or just `c` without an input
+#[derive(Debug, Copy, Clone)]
+struct Linear {
+    input: Option<Register>,
+    a: Word,
+    c: Word
+}
+
+fn linear(expr: &Expr) -> Option<Linear> {
+    match expr {
+        Expr::Const(c) => Some(Linear { input: None, a: 0, c: *c }),
+        Expr::Input(r) => Some(Linear { input: Some(*r), a: 1, c: 0 }),
+        Expr::Binary(BinOp::Add, x, y) => {
+            let (x, y) = (linear(x)?, linear(y)?);
+            let input = match (x.input, y.input) {
+                (Some(p), Some(q)) if p != q => return None,
+                (p, q) => p.or(q)
+            };
+
+            Some(Linear { input, a: x.a.checked_add(y.a)?, c: x.c.checked_add(y.c)? })
+        },
+        Expr::Binary(BinOp::Mul, x, y) => {
+            let (x, y) = (linear(x)?, linear(y)?);
+            let (l, k) = match (x.input, y.input) {
+                (_, None) => (x, y.c),
+                (None, _) => (y, x.c),
+                _ => return None
+            };
+
+            Some(Linear { input: l.input, a: l.a.checked_mul(k)?, c: l.c.checked_mul(k)? })
+        },
+        _ => None
+    }
+}
+
+// `x - y` for two linear forms
+fn difference(x: Linear, y: Linear) -> Option<Linear> {
+    let input = match (x.input, y.input) {
+        (Some(p), Some(q)) if p != q => return None,
+        (p, q) => p.or(q)
+    };
+
+    Some(Linear { input, a: x.a.checked_sub(y.a)?, c: x.c.checked_sub(y.c)? })
+}
+
+enum Bound {
+    // The condition always or never holds
+    Always(bool),
+
+    Exactly(Register, Option<Word>),
+    Not(Register, Word),
+    AtLeast(Register, Word),
+    AtMost(Register, Word)
+}
+
+// `a * x + c` compared to 0 with `==` or `>`, as a bound on x
+fn bound(l: Linear, eq: bool, holds: bool) -> Option<Bound> {
+    let x = match l.input {
+        Some(x) if l.a != 0 => x,
+        _ => return Some(Bound::Always((if eq { l.c == 0 } else { l.c > 0 }) == holds))
+    };
+
+    // a * x == v, or a * x > v
+    let v = l.c.checked_neg()?;
+    let exact = if v % l.a == 0 { Some(v / l.a) } else { None };
+
+    Some(match (eq, holds) {
+        (true, true) => Bound::Exactly(x, exact),
+        (true, false) => exact.map(|e| Bound::Not(x, e)).unwrap_or(Bound::Always(true)),
+
+        (false, true) if l.a > 0 => Bound::AtLeast(x, v.div_euclid(l.a).checked_add(1)?),
+        (false, true) => Bound::AtMost(x, (l.c.checked_sub(1)?).div_euclid(l.a.checked_neg()?)),
+
+        // a * x + c <= 0 is -a * x + 1 - c > 0
+        (false, false) => return bound(Linear { input: l.input, a: l.a.checked_neg()?, c: 1i64.checked_sub(l.c)? }, false, true)
+    })
+}
+
+// Turns conditions on a single input that are linear in it into bounds, and
+// leaves everything else in `unsolved`. None if the conditions contradict each other.
+pub fn solve(conditions: &[Condition]) -> Option<Solution> {
+    let mut solution = Solution::default();
+
+    for condition in conditions {
+        let bound = match &*condition.expr {
+            Expr::Binary(op @ BinOp::Eq, x, y) | Expr::Binary(op @ BinOp::Gt, x, y) => {
+                linear(x).zip(linear(y)).and_then(|(x, y)| difference(x, y)).and_then(|d| bound(d, *op == BinOp::Eq, condition.holds))
+            },
+            expr => linear(expr).and_then(|l| bound(l, true, !condition.holds))
+        };
+
+        match bound {
+            None => solution.unsolved.push(condition.clone()),
+            Some(Bound::Always(true)) => { },
+            Some(Bound::Always(false)) | Some(Bound::Exactly(_, None)) => return None,
+
+            Some(Bound::Exactly(x, Some(v))) => {
+                let d = solution.inputs.entry(x).or_default();
+
+                d.min = Some(d.min.map(|m| m.max(v)).unwrap_or(v));
+                d.max = Some(d.max.map(|m| m.min(v)).unwrap_or(v));
+            },
+
+            Some(Bound::Not(x, v)) => { solution.inputs.entry(x).or_default().excluded.insert(v); },
+
+            Some(Bound::AtLeast(x, v)) => {
+                let d = solution.inputs.entry(x).or_default();
+
+                d.min = Some(d.min.map(|m| m.max(v)).unwrap_or(v));
+            },
+
+            Some(Bound::AtMost(x, v)) => {
+                let d = solution.inputs.entry(x).or_default();
+
+                d.max = Some(d.max.map(|m| m.min(v)).unwrap_or(v));
+            }
+        }
+    }
+
+    if solution.inputs.values().any(|d| d.is_empty()) {
+        None
+    } else {
+        Some(solution)
+    }
+}
+
+// Runs a program on symbolic registers, following both ways wherever a jump
+// depends on an input:
+//
+//   let paths = Explorer::new(&program).input(0).explore(100_000, 16);
+//
+// Registers that are not inputs start out as constants, 0 unless `set`.
+#[derive(Debug, Clone)]
+pub struct Explorer<'a> {
+    program: &'a Program,
+    registers: Vec<Rc<Expr>>
+}
+
+// A path still being explored
+#[derive(Debug, Clone)]
+struct State {
+    ip: Word,
+
+    // What the last instruction left in the ip register, if it is bound
+    jump: Option<Rc<Expr>>,
+
+    registers: Vec<Rc<Expr>>,
+    conditions: Vec<Condition>,
+
+    steps: usize,
+    last: Option<usize>
+}
+
+impl State {
+    fn end(self, end: End) -> Path {
+        Path {
+            conditions: self.conditions,
+            registers: self.registers,
+            steps: self.steps,
+            last: self.last,
+            end
+        }
+    }
+
+    // The same state once `atom` is known to hold or not
+    fn assume(&self, atom: &Rc<Expr>, holds: bool) -> State {
+        let value = holds as Word;
+        let mut state = self.clone();
+
+        for r in state.registers.iter_mut().chain(state.jump.as_mut()) {
+            *r = r.substitute(atom, value);
+        }
+
+        state.conditions.push(Condition { expr: atom.clone(), holds });
+        state
+    }
+}
+
+impl<'a> Explorer<'a> {
+    pub fn new(program: &'a Program) -> Self {
+        Explorer {
+            program,
+            registers: Vec::new()
+        }.registers(REGISTER_COUNT)
+    }
+
+    // Number of registers, all starting out as 0
+    pub fn registers(mut self, count: usize) -> Self {
+        self.registers = (0..count).map(|_| Rc::new(Expr::Const(0))).collect();
+        self
+    }
+
+    pub fn input(mut self, register: Register) -> Self {
+        if let Some(r) = self.registers.get_mut(register as usize) {
+            *r = Rc::new(Expr::Input(register));
+        }
+
+        self
+    }
+
+    pub fn set(mut self, register: Register, value: Word) -> Self {
+        if let Some(r) = self.registers.get_mut(register as usize) {
+            *r = Rc::new(Expr::Const(value));
+        }
+
+        self
+    }
+
+    // Follows every path for up to `max_steps` instructions each, until `max_paths`
+    // of them have ended. Paths whose conditions contradict each other are dropped.
+    pub fn explore(&self, max_steps: usize, max_paths: usize) -> Vec<Path> {
+        let mut paths = Vec::new();
+        let mut pending = vec![State {
+            ip: 0,
+            jump: None,
+
+            registers: self.registers.clone(),
+            conditions: Vec::new(),
+
+            steps: 0,
+            last: None
+        }];
+
+        while paths.len() < max_paths {
+            let mut state = match pending.pop() {
+                Some(state) => state,
+                None => break
+            };
+
+            match self.run(&mut state, max_steps) {
+                Ok(end) => paths.push(state.end(end)),
+
+                // Explores the path where the comparison holds first, which usually
+                // leaves a loop, so paths out of the program come up early
+                Err(atom) => {
+                    for holds in [false, true].iter() {
+                        let next = state.assume(&atom, *holds);
+
+                        if solve(&next.conditions).is_some() {
+                            pending.push(next);
+                        }
+                    }
+                }
+            }
+        }
+
+        paths
+    }
+
+    // Runs until the path ends, or returns the comparison it has to fork on
+    fn run(&self, state: &mut State, max_steps: usize) -> Result<End, Rc<Expr>> {
+        let ip_register = self.program.ip_register();
+
+        loop {
+            if let Some(jump) = state.jump.take() {
+                match (jump.constant(), jump.comparison()) {
+                    (Some(ip), _) => match ip.checked_add(1) {
+                        Some(next) => state.ip = next,
+
+                        // Past `Word::MAX`, where the machine wraps around to `Word::MIN`
+                        None => return Ok(End::Exit(Word::MIN))
+                    },
+                    (None, Some(atom)) => {
+                        state.jump = Some(jump);
+                        return Err(atom);
+                    },
+                    (None, None) => return Ok(End::UnknownJump { from: state.last.unwrap_or(0), target: jump })
+                }
+            }
+
+            let (ip, op) = match self.program.get(state.ip as usize) {
+                Some(op) if state.ip >= 0 => (state.ip as usize, *op),
+                _ => return Ok(End::Exit(state.ip))
+            };
+
+            if state.steps >= max_steps {
+                return Ok(End::StepLimit);
+            }
+
+            let fault = |error| End::Fault(Fault { ip, opcode: op, error });
+            let bound = match ip_register {
+                Some(r) if r < 0 || r as usize >= state.registers.len() => return Ok(fault(AluError::InvalidRegister(r))),
+                Some(r) => Some(r as usize),
+                None => None
+            };
+
+            if let Some(r) = bound {
+                state.registers[r] = Rc::new(Expr::Const(ip as Word));
+            }
+
+            let halted = execute(&op, &mut state.registers).map_err(fault);
+
+            state.steps += 1;
+            state.last = Some(ip);
+
+            match halted {
+                Ok(true) => return Ok(End::Halted(ip)),
+                Ok(false) => { },
+                Err(end) => return Ok(end)
+            }
+
+            match bound {
+                Some(r) => state.jump = Some(state.registers[r].clone()),
+                None => state.ip += 1
+            }
+        }
+    }
+}
+
+fn operand(slot: Slot, kind: Operand, registers: &[Rc<Expr>]) -> Result<Rc<Expr>, AluError> {
+    match (slot, kind) {
+        (Slot::Reg(r), Operand::Register) => registers.get(r as usize).cloned().filter(|_| r >= 0).ok_or(AluError::InvalidRegister(r)),
+        (Slot::Immediate(i), Operand::Immediate) => Ok(Rc::new(Expr::Const(i))),
+        _ => Ok(Rc::new(Expr::Const(0)))
+    }
+}
+
+// Executes an instruction on symbolic registers, returning whether it halts the machine
+fn execute(op: &Opcode, registers: &mut [Rc<Expr>]) -> Result<bool, AluError> {
+    let shape = op.mnemonic.shape();
+    let a = operand(op.a, shape.a, registers)?;
+    let b = operand(op.b, shape.b, registers)?;
+
+    let value = match op.mnemonic {
+        Mnemonic::Addr | Mnemonic::Addi => binary(BinOp::Add, a, b)?,
+        Mnemonic::Mulr | Mnemonic::Muli => binary(BinOp::Mul, a, b)?,
+        Mnemonic::Banr | Mnemonic::Bani => binary(BinOp::And, a, b)?,
+        Mnemonic::Borr | Mnemonic::Bori => binary(BinOp::Or, a, b)?,
+        Mnemonic::Setr | Mnemonic::Seti => a,
+        Mnemonic::Gtir | Mnemonic::Gtri | Mnemonic::Gtrr => binary(BinOp::Gt, a, b)?,
+        Mnemonic::Eqir | Mnemonic::Eqri | Mnemonic::Eqrr => binary(BinOp::Eq, a, b)?,
+
+        // Operations not storing anything run right away, whatever their operands
+        Mnemonic::Ext(ext) if shape.c != Operand::Register => {
+            return Ok(ext.execute(a.constant().unwrap_or(0), b.constant().unwrap_or(0), ArithmeticMode::Checked)? == Effect::Halt);
+        },
+        Mnemonic::Ext(_) => call(op.mnemonic, a, b)?
+    };
+
+    match op.c {
+        Slot::Reg(r) if r >= 0 && (r as usize) < registers.len() => registers[r as usize] = value,
+        Slot::Reg(r) => return Err(AluError::InvalidRegister(r)),
+        Slot::Immediate(_) => return Err(AluError::CannotStoreToImmediate)
+    }
+
+    Ok(false)
+}
@@ -0,0 +1,454 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::io::{self, BufRead, Write};
+
+use super::{Halt, InstructionSet, Machine, Opcode, Register, RegisterState, Word, REGISTER_COUNT};
+
+// A register store done by an instruction
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TraceWrite {
+    pub register: Register,
+    pub old: Word,
+    pub new: Word
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct TraceEntry {
+    // Number of instructions the machine executed before this one
+    pub step: usize,
+
+    pub ip: usize,
+    pub opcode: Opcode,
+    pub write: Option<TraceWrite>
+}
+
+// Decides which executed instructions end up in a trace. An empty filter keeps everything.
+#[derive(Debug, Clone, Default)]
+pub struct TraceFilter {
+    ips: Option<BTreeSet<usize>>,
+    registers: Option<BTreeSet<Register>>
+}
+
+impl TraceFilter {
+    pub fn new() -> Self {
+        TraceFilter::default()
+    }
+
+    // Only keep instructions at these addresses
+    pub fn ips<I>(mut self, ips: I) -> Self
+        where I: IntoIterator<Item = usize>
+    {
+        self.ips.get_or_insert_with(BTreeSet::new).extend(ips);
+        self
+    }
+
+    // Only keep instructions storing to one of these registers
+    pub fn registers<I>(mut self, registers: I) -> Self
+        where I: IntoIterator<Item = Register>
+    {
+        self.registers.get_or_insert_with(BTreeSet::new).extend(registers);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ips.is_none() && self.registers.is_none()
+    }
+
+    pub fn matches(&self, entry: &TraceEntry) -> bool {
+        let ip = self.ips.as_ref().map(|ips| ips.contains(&entry.ip)).unwrap_or(true);
+        let reg = match (&self.registers, entry.write) {
+            (None, _) => true,
+            (Some(regs), Some(w)) => regs.contains(&w.register),
+            (Some(_), None) => false
+        };
+
+        ip && reg
+    }
+}
+
+// Executes a single instruction and describes what it did
+pub fn trace_step(machine: &mut Machine) -> Result<TraceEntry, Halt> {
+    let step = machine.steps();
+
+    // The ip register holds the address once the instruction executes
+    let mut before = *machine.registers();
+
+    if let Some(r) = machine.ip_register().and_then(|r| before.get_mut(r as usize)) {
+        *r = machine.ip();
+    }
+
+    let (target, opcode) = match machine.current() {
+        Some((_, op)) => (op.target().and_then(|r| Some((r, *before.get(r as usize)?))), *op),
+        None => return Err(Halt::IpOutOfRange(machine.ip()))
+    };
+
+    let ip = machine.step()?;
+
+    Ok(TraceEntry {
+        step,
+        ip,
+        opcode,
+        write: target.map(|(register, old)| TraceWrite {
+            register,
+            old,
+            new: machine.registers()[register as usize]
+        })
+    })
+}
+
+// Somewhere to put trace entries, in memory or on disk
+pub trait TraceSink {
+    fn filter(&self) -> &TraceFilter;
+
+    fn push(&mut self, entry: TraceEntry) -> io::Result<()>;
+}
+
+// Runs the machine until it halts or `max_steps` instructions have been executed,
+// handing every entry passing the filter to the sink
+pub fn record<S>(machine: &mut Machine, sink: &mut S, max_steps: Option<usize>) -> io::Result<Halt>
+    where S: TraceSink
+{
+    let mut executed = 0;
+
+    loop {
+        if max_steps.map(|max| executed >= max).unwrap_or(false) {
+            return Ok(Halt::StepLimit);
+        }
+
+        let entry = match trace_step(machine) {
+            Ok(entry) => entry,
+            Err(halt) => return Ok(halt)
+        };
+
+        executed += 1;
+
+        if sink.filter().matches(&entry) {
+            sink.push(entry)?;
+        }
+    }
+}
+
+fn is_register(r: Register) -> bool {
+    r >= 0 && (r as usize) < REGISTER_COUNT
+}
+
+// What a trace starts from. Written as the first line of a saved trace:
+//
+//   registers 0 0 0 0 0 0 [ip <register>] [filtered]
+#[derive(Debug, Copy, Clone)]
+struct Header {
+    registers: RegisterState,
+    ip_register: Option<Register>,
+
+    // Whether every executed instruction was recorded
+    complete: bool
+}
+
+impl Header {
+    fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write!(out, "registers")?;
+
+        for r in self.registers.iter() {
+            write!(out, " {}", r)?;
+        }
+
+        if let Some(ip) = self.ip_register {
+            write!(out, " ip {}", ip)?;
+        }
+
+        writeln!(out, "{}", if self.complete { "" } else { " filtered" })
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        let mut parts = line.split_whitespace().peekable();
+
+        if parts.next() != Some("registers") {
+            return None;
+        }
+
+        let mut registers = [0; REGISTER_COUNT];
+
+        for r in registers.iter_mut() {
+            *r = parts.next()?.parse().ok()?;
+        }
+
+        let ip_register = if parts.peek() == Some(&"ip") {
+            parts.next();
+
+            Some(parts.next()?.parse().ok().filter(|r| is_register(*r))?)
+        } else {
+            None
+        };
+
+        let complete = match parts.next() {
+            None => true,
+            Some("filtered") => false,
+            Some(_) => return None
+        };
+
+        Some(Header {
+            registers,
+            ip_register,
+            complete
+        })
+    }
+}
+
+// In-memory trace, optionally a ring buffer only keeping the last `limit` entries
+#[derive(Debug, Clone)]
+pub struct Trace {
+    filter: TraceFilter,
+    limit: Option<usize>,
+
+    entries: VecDeque<TraceEntry>,
+    dropped: usize,
+
+    // Registers before the oldest kept entry, only meaningful for complete traces
+    base: Header,
+
+    // Per-ip counts over every entry ever recorded, including dropped ones
+    counts: BTreeMap<usize, usize>
+}
+
+impl Trace {
+    // Starts a trace from the machine's current state
+    pub fn new(machine: &Machine, filter: TraceFilter, limit: Option<usize>) -> Self {
+        let base = Header {
+            registers: *machine.registers(),
+            ip_register: machine.ip_register(),
+            complete: filter.is_empty()
+        };
+
+        Trace::with_header(base, filter, limit)
+    }
+
+    fn with_header(base: Header, filter: TraceFilter, limit: Option<usize>) -> Self {
+        Trace {
+            filter,
+            limit,
+
+            entries: VecDeque::new(),
+            dropped: 0,
+
+            base,
+            counts: BTreeMap::new()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Entries that were pushed out of the ring buffer
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    pub fn entries(&self) -> impl DoubleEndedIterator<Item = &TraceEntry> {
+        self.entries.iter()
+    }
+
+    pub fn counts(&self) -> &BTreeMap<usize, usize> {
+        &self.counts
+    }
+
+    pub fn count(&self, ip: usize) -> usize {
+        self.counts.get(&ip).cloned().unwrap_or(0)
+    }
+
+    // Most recent instruction that stored a different value to the register
+    pub fn last_change(&self, register: Register) -> Option<&TraceEntry> {
+        self.entries.iter().rev().find(|e| match e.write {
+            Some(w) => w.register == register && w.old != w.new,
+            None => false
+        })
+    }
+
+    // Every value stored to the register, oldest first
+    pub fn writes(&self, register: Register) -> impl Iterator<Item = (&TraceEntry, Word)> {
+        self.entries
+            .iter()
+            .filter_map(move |e| e.write.filter(|w| w.register == register).map(|w| (e, w.new)))
+    }
+
+    // Registers as every kept instruction saw them, oldest first. Only possible
+    // when nothing was filtered out, as skipped stores are unknown.
+    pub fn states(&self) -> Option<impl Iterator<Item = (&TraceEntry, RegisterState)>> {
+        if !self.base.complete {
+            return None;
+        }
+
+        let mut regs = self.base.registers;
+        let ip_register = self.base.ip_register;
+
+        Some(self.entries.iter().map(move |e| {
+            if let Some(r) = ip_register.and_then(|r| regs.get_mut(r as usize)) {
+                *r = e.ip as Word;
+            }
+
+            let before = regs;
+
+            if let Some((r, w)) = e.write.and_then(|w| Some((regs.get_mut(w.register as usize)?, w))) {
+                *r = w.new;
+            }
+
+            (e, before)
+        }))
+    }
+
+    // All values the register held whenever the instruction at `ip` was about
+    // to execute. None for an incomplete trace or a register the machine lacks.
+    pub fn values_at(&self, ip: usize, register: Register) -> Option<Vec<Word>> {
+        if !is_register(register) {
+            return None;
+        }
+
+        let states = self.states()?;
+
+        Some(states.filter(|(e, _)| e.ip == ip).map(|(_, regs)| regs[register as usize]).collect())
+    }
+
+    // Writes the trace in the line based format that `load` reads back
+    pub fn save<W>(&self, out: W) -> io::Result<()>
+        where W: Write
+    {
+        let mut writer = TraceWriter::with_header(out, self.base, self.filter.clone())?;
+
+        for e in &self.entries {
+            writer.push(*e)?;
+        }
+
+        Ok(())
+    }
+
+    // Reads back what `save` wrote, looking up mnemonics beyond the base
+    // instructions in `set`
+    pub fn load<R>(input: R, set: &InstructionSet, limit: Option<usize>) -> io::Result<Self>
+        where R: BufRead
+    {
+        let invalid = |line: &str| io::Error::new(io::ErrorKind::InvalidData, format!("invalid trace line: {}", line));
+
+        let mut lines = input.lines();
+
+        let header = lines.next().ok_or_else(|| invalid(""))??;
+        let header = Header::parse(&header).ok_or_else(|| invalid(&header))?;
+
+        // The exact filter is not stored, only whether entries may be missing
+        let mut trace = Trace::with_header(header, TraceFilter::new(), limit);
+
+        for line in lines {
+            let line = line?;
+            let entry = parse_entry(&line, set).ok_or_else(|| invalid(&line))?;
+
+            trace.push(entry)?;
+        }
+
+        Ok(trace)
+    }
+}
+
+impl TraceSink for Trace {
+    fn filter(&self) -> &TraceFilter {
+        &self.filter
+    }
+
+    fn push(&mut self, entry: TraceEntry) -> io::Result<()> {
+        *self.counts.entry(entry.ip).or_insert(0) += 1;
+
+        self.entries.push_back(entry);
+
+        if self.limit.map(|limit| self.entries.len() > limit).unwrap_or(false) {
+            if let Some(evicted) = self.entries.pop_front() {
+                if let Some((r, w)) = evicted.write.and_then(|w| Some((self.base.registers.get_mut(w.register as usize)?, w))) {
+                    *r = w.new;
+                }
+
+                self.dropped += 1;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Streams a trace to disk. After the header there is one line per entry:
+//
+//   <step> <ip> <mnemonic> <a> <b> <c> [r<register> <old> <new>]
+pub struct TraceWriter<W> {
+    out: W,
+    filter: TraceFilter
+}
+
+impl<W> TraceWriter<W> where W: Write {
+    // Starts a trace from the machine's current state
+    pub fn new(out: W, machine: &Machine, filter: TraceFilter) -> io::Result<Self> {
+        let header = Header {
+            registers: *machine.registers(),
+            ip_register: machine.ip_register(),
+            complete: filter.is_empty()
+        };
+
+        TraceWriter::with_header(out, header, filter)
+    }
+
+    fn with_header(mut out: W, header: Header, filter: TraceFilter) -> io::Result<Self> {
+        header.write(&mut out)?;
+
+        Ok(TraceWriter {
+            out,
+            filter
+        })
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl<W> TraceSink for TraceWriter<W> where W: Write {
+    fn filter(&self) -> &TraceFilter {
+        &self.filter
+    }
+
+    fn push(&mut self, entry: TraceEntry) -> io::Result<()> {
+        write!(self.out, "{} {} {}", entry.step, entry.ip, entry.opcode)?;
+
+        if let Some(w) = entry.write {
+            write!(self.out, " r{} {} {}", w.register, w.old, w.new)?;
+        }
+
+        writeln!(self.out)
+    }
+}
+
+fn parse_entry(line: &str, set: &InstructionSet) -> Option<TraceEntry> {
+    let parts = line.split_whitespace().collect::<Vec<_>>();
+
+    if parts.len() != 6 && parts.len() != 9 {
+        return None;
+    }
+
+    let num = |i: usize| parts[i].parse::<Word>().ok();
+
+    let opcode = Opcode::build(set.lookup(parts[2])?, num(3)?, num(4)?, num(5)?)?;
+
+    let write = if parts.len() == 9 {
+        Some(TraceWrite {
+            register: parts[6].strip_prefix('r')?.parse().ok().filter(|r| is_register(*r))?,
+            old: num(7)?,
+            new: num(8)?
+        })
+    } else {
+        None
+    };
+
+    Some(TraceEntry {
+        step: parts[0].parse().ok()?,
+        ip: parts[1].parse().ok()?,
+        opcode,
+        write
+    })
+}
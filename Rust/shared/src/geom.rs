@@ -0,0 +1,318 @@
+use std::iter::{FromIterator, Step};
+use std::ops::Range;
+
+use crate::grid::Numeric;
+
+// Half-open interval [start, end). Use `Interval::inclusive` for ranges that
+// are given by their first and last value.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Interval<T> {
+    pub start: T,
+    pub end: T
+}
+
+impl<T> Interval<T> where T: Numeric + Step + Default {
+    pub fn new(start: T, end: T) -> Self {
+        Interval { start, end }
+    }
+
+    pub fn inclusive(first: T, last: T) -> Self {
+        Interval::new(first, Step::forward(last, 1))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start >= self.end
+    }
+
+    pub fn len(&self) -> T {
+        if self.is_empty() {
+            T::default()
+        } else {
+            self.end - self.start
+        }
+    }
+
+    // Last value contained in the interval, if any
+    pub fn last(&self) -> Option<T> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(Step::backward(self.end, 1))
+        }
+    }
+
+    pub fn contains(&self, value: T) -> bool {
+        value >= self.start && value < self.end
+    }
+
+    pub fn contains_interval(&self, other: &Self) -> bool {
+        other.is_empty() || (other.start >= self.start && other.end <= self.end)
+    }
+
+    pub fn overlaps(&self, other: &Self) -> bool {
+        self.intersection(other).is_some()
+    }
+
+    pub fn intersection(&self, other: &Self) -> Option<Self> {
+        let i = Interval::new(self.start.max(other.start), self.end.min(other.end));
+
+        if i.is_empty() { None } else { Some(i) }
+    }
+
+    // Smallest interval covering both
+    pub fn union(&self, other: &Self) -> Self {
+        if self.is_empty() {
+            *other
+        } else if other.is_empty() {
+            *self
+        } else {
+            Interval::new(self.start.min(other.start), self.end.max(other.end))
+        }
+    }
+
+    pub fn iter(&self) -> Range<T> {
+        self.start .. self.end
+    }
+}
+
+// A set of values stored as sorted, disjoint and non-adjacent intervals
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct IntervalSet<T> {
+    intervals: Vec<Interval<T>>
+}
+
+impl<T> IntervalSet<T> where T: Numeric + Step + Default {
+    pub fn new() -> Self {
+        IntervalSet {
+            intervals: Vec::new()
+        }
+    }
+
+    pub fn insert(&mut self, interval: Interval<T>) {
+        if interval.is_empty() {
+            return;
+        }
+
+        let mut merged = interval;
+        let mut kept = Vec::with_capacity(self.intervals.len() + 1);
+
+        for i in self.intervals.drain(..) {
+            // Touching intervals are merged as well, [1, 3) and [3, 5) become [1, 5)
+            if i.end < merged.start || i.start > merged.end {
+                kept.push(i);
+            } else {
+                merged = merged.union(&i);
+            }
+        }
+
+        let pos = kept.iter().position(|i| i.start > merged.start).unwrap_or(kept.len());
+        kept.insert(pos, merged);
+
+        self.intervals = kept;
+    }
+
+    pub fn contains(&self, value: T) -> bool {
+        self.intervals.iter().any(|i| i.contains(value))
+    }
+
+    pub fn intervals(&self) -> &[Interval<T>] {
+        &self.intervals
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.intervals.is_empty()
+    }
+
+    // Number of values covered by the set
+    pub fn len(&self) -> T {
+        self.intervals.iter().fold(T::default(), |acc, i| acc + i.len())
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        let mut set = self.clone();
+
+        for i in &other.intervals {
+            set.insert(*i);
+        }
+
+        set
+    }
+
+    pub fn intersection(&self, other: &Self) -> Self {
+        let mut set = IntervalSet::new();
+
+        for a in &self.intervals {
+            for b in &other.intervals {
+                if let Some(i) = a.intersection(b) {
+                    set.insert(i);
+                }
+            }
+        }
+
+        set
+    }
+}
+
+impl<T> FromIterator<Interval<T>> for IntervalSet<T> where T: Numeric + Step + Default {
+    fn from_iter<I: IntoIterator<Item = Interval<T>>>(iter: I) -> Self {
+        let mut set = IntervalSet::new();
+
+        for i in iter {
+            set.insert(i);
+        }
+
+        set
+    }
+}
+
+// Axis-aligned rectangle, half-open on both axes. Points are given as (x, y).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Rect<T> {
+    pub x: Interval<T>,
+    pub y: Interval<T>
+}
+
+impl<T> Rect<T> where T: Numeric + Step + Default {
+    pub fn new(x: Interval<T>, y: Interval<T>) -> Self {
+        Rect { x, y }
+    }
+
+    // From a top left corner and a bottom right corner that is excluded
+    pub fn exclusive((x0, y0): (T, T), (x1, y1): (T, T)) -> Self {
+        Rect::new(Interval::new(x0, x1), Interval::new(y0, y1))
+    }
+
+    // From a top left corner and a bottom right corner that is included
+    pub fn inclusive((x0, y0): (T, T), (x1, y1): (T, T)) -> Self {
+        Rect::new(Interval::inclusive(x0, x1), Interval::inclusive(y0, y1))
+    }
+
+    pub fn from_size((x, y): (T, T), width: T, height: T) -> Self {
+        Rect::exclusive((x, y), (x + width, y + height))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.x.is_empty() || self.y.is_empty()
+    }
+
+    pub fn width(&self) -> T {
+        self.x.len()
+    }
+
+    pub fn height(&self) -> T {
+        self.y.len()
+    }
+
+    pub fn area(&self) -> T {
+        if self.is_empty() {
+            T::default()
+        } else {
+            self.width() * self.height()
+        }
+    }
+
+    // Top left and bottom right corners, both included
+    pub fn corners(&self) -> Option<((T, T), (T, T))> {
+        Some(((self.x.start, self.y.start), (self.x.last()?, self.y.last()?)))
+    }
+
+    pub fn contains(&self, (x, y): (T, T)) -> bool {
+        self.x.contains(x) && self.y.contains(y)
+    }
+
+    pub fn contains_rect(&self, other: &Self) -> bool {
+        other.is_empty() || (self.x.contains_interval(&other.x) && self.y.contains_interval(&other.y))
+    }
+
+    pub fn overlaps(&self, other: &Self) -> bool {
+        self.intersection(other).is_some()
+    }
+
+    pub fn intersection(&self, other: &Self) -> Option<Self> {
+        Some(Rect::new(self.x.intersection(&other.x)?, self.y.intersection(&other.y)?))
+    }
+
+    // Smallest rectangle covering both
+    pub fn union(&self, other: &Self) -> Self {
+        if self.is_empty() {
+            *other
+        } else if other.is_empty() {
+            *self
+        } else {
+            Rect::new(self.x.union(&other.x), self.y.union(&other.y))
+        }
+    }
+
+    // All points in reading order, row by row
+    pub fn points(&self) -> impl Iterator<Item = (T, T)> {
+        let xs = self.x;
+
+        self.y.iter().flat_map(move |y| xs.iter().map(move |x| (x, y)))
+    }
+}
+
+// Accumulates the smallest rectangle containing every point added to it
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct BoundingBox<T> {
+    corners: Option<((T, T), (T, T))>
+}
+
+impl<T> BoundingBox<T> where T: Numeric + Step + Default {
+    pub fn new() -> Self {
+        BoundingBox {
+            corners: None
+        }
+    }
+
+    pub fn add(&mut self, (x, y): (T, T)) {
+        self.corners = Some(match self.corners {
+            None => ((x, y), (x, y)),
+            Some(((x0, y0), (x1, y1))) => ((x0.min(x), y0.min(y)), (x1.max(x), y1.max(y)))
+        });
+    }
+
+    pub fn add_rect(&mut self, rect: &Rect<T>) {
+        if let Some((min, max)) = rect.corners() {
+            self.add(min);
+            self.add(max);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.corners.is_none()
+    }
+
+    // Top left and bottom right corners, both included
+    pub fn corners(&self) -> Option<((T, T), (T, T))> {
+        self.corners
+    }
+
+    pub fn min(&self) -> Option<(T, T)> {
+        self.corners.map(|(min, _)| min)
+    }
+
+    pub fn max(&self) -> Option<(T, T)> {
+        self.corners.map(|(_, max)| max)
+    }
+
+    pub fn rect(&self) -> Option<Rect<T>> {
+        self.corners.map(|(min, max)| Rect::inclusive(min, max))
+    }
+}
+
+impl<T> Extend<(T, T)> for BoundingBox<T> where T: Numeric + Step + Default {
+    fn extend<I: IntoIterator<Item = (T, T)>>(&mut self, iter: I) {
+        for p in iter {
+            self.add(p);
+        }
+    }
+}
+
+impl<T> FromIterator<(T, T)> for BoundingBox<T> where T: Numeric + Step + Default {
+    fn from_iter<I: IntoIterator<Item = (T, T)>>(iter: I) -> Self {
+        let mut bb = BoundingBox::new();
+        bb.extend(iter);
+
+        bb
+    }
+}
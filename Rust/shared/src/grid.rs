@@ -1,5 +1,16 @@
 use std::ops::{Add, Mul, Sub};
 use std::cmp::Ordering;
+use std::iter::Step;
+
+pub mod distance;
+pub mod regions;
+pub mod summed_area;
+pub mod voronoi;
+
+pub use self::distance::{distances, multi_source_distances, summed_distances, DistanceMap, Graph, GridGraph};
+pub use self::regions::{flood_fill, label_components, Components, Region};
+pub use self::summed_area::SummedAreaTable;
+pub use self::voronoi::{Metric, Nearest, Voronoi};
 
 pub trait Numeric:
     Copy + PartialEq + Ord
@@ -12,11 +23,13 @@ pub trait Numeric:
 impl Numeric for i8 { }
 impl Numeric for i16 { }
 impl Numeric for i32 { }
+impl Numeric for i64 { }
 impl Numeric for isize { }
 
 impl Numeric for u8 { }
 impl Numeric for u16 { }
 impl Numeric for u32 { }
+impl Numeric for u64 { }
 impl Numeric for usize { }
 
 pub trait Coordinate: Copy + PartialEq + Ord {
@@ -34,6 +47,36 @@ pub trait Coordinate: Copy + PartialEq + Ord {
     fn numeric_limits() -> (Self::Component, Self::Component);
 }
 
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Connectivity {
+    Four, Eight
+}
+
+// Neighbors of a coordinate in reading order, optionally including the diagonals
+pub fn adjacent<C: Coordinate>(coord: &C, connectivity: Connectivity) -> Vec<C> {
+    match connectivity {
+        Connectivity::Four => coord.neighbors().collect(),
+        Connectivity::Eight => {
+            let (y, x) = (coord.y(), coord.x());
+
+            let rows = [Step::backward_checked(y, 1), Some(y), Step::forward_checked(y, 1)];
+            let cols = [Step::backward_checked(x, 1), Some(x), Step::forward_checked(x, 1)];
+
+            let mut adjacent = Vec::with_capacity(8);
+
+            for ny in rows.iter().flatten() {
+                for nx in cols.iter().flatten() {
+                    if *ny != y || *nx != x {
+                        adjacent.push(C::new(*ny, *nx));
+                    }
+                }
+            }
+
+            adjacent
+        }
+    }
+}
+
 #[derive(Copy, Clone)]
 pub enum Color {
     Black = 0, Red, Green, Yellow, Blue, Magenta, Cyan, White
@@ -88,7 +131,36 @@ pub trait Grid {
 
     fn bounds(&self) -> (Self::Coord, Self::Coord);
     fn tile_at(&self, coord: &Self::Coord) -> &Self::Tile;
-    
+
+    fn contains(&self, coord: &Self::Coord) -> bool {
+        let (lower, upper) = self.bounds();
+
+        coord.y() >= lower.y() && coord.y() < upper.y()
+            && coord.x() >= lower.x() && coord.x() < upper.x()
+    }
+
+    // Whether the coordinate lies in the outermost row or column of the grid
+    fn is_border(&self, coord: &Self::Coord) -> bool {
+        let (lower, upper) = self.bounds();
+
+        self.contains(coord) && (
+            coord.y() == lower.y() || Step::forward(coord.y(), 1) == upper.y()
+                || coord.x() == lower.x() || Step::forward(coord.x(), 1) == upper.x())
+    }
+
+    fn coords(&self) -> Vec<Self::Coord> {
+        let (lower, upper) = self.bounds();
+        let mut coords = Vec::new();
+
+        for y in lower.y() .. upper.y() {
+            for x in lower.x() .. upper.x() {
+                coords.push(<Self::Coord as Coordinate>::new(y, x));
+            }
+        }
+
+        coords
+    }
+
     fn draw(&self) {
         let (lower, upper) = self.bounds();
 
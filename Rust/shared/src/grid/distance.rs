@@ -0,0 +1,156 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::hash::Hash;
+
+use super::{Coordinate, MovementCost, NavigatableGrid};
+
+pub trait Graph {
+    type Node: Copy + Ord;
+
+    fn edges(&self, node: &Self::Node) -> Vec<(Self::Node, isize)>;
+}
+
+// Walks a grid between in-bounds neighbors that are passable
+pub struct GridGraph<'a, G>(pub &'a G);
+
+impl<'a, G> Graph for GridGraph<'a, G> where G: NavigatableGrid {
+    type Node = G::Coord;
+
+    fn edges(&self, node: &Self::Node) -> Vec<(Self::Node, isize)> {
+        node.neighbors()
+            .filter(|n| self.0.contains(n))
+            .filter_map(|n| match self.0.movement_cost(node, &n) {
+                MovementCost::Passable(cost) => Some((n, cost)),
+                MovementCost::Impassable => None
+            })
+            .collect()
+    }
+}
+
+// Adjacency lists, every edge costs one step
+impl<N> Graph for BTreeMap<N, Vec<N>> where N: Copy + Ord {
+    type Node = N;
+
+    fn edges(&self, node: &N) -> Vec<(N, isize)> {
+        self.get(node).map(|ns| ns.iter().map(|n| (*n, 1)).collect()).unwrap_or_default()
+    }
+}
+
+impl<N> Graph for HashMap<N, Vec<N>> where N: Copy + Ord + Hash {
+    type Node = N;
+
+    fn edges(&self, node: &N) -> Vec<(N, isize)> {
+        self.get(node).map(|ns| ns.iter().map(|n| (*n, 1)).collect()).unwrap_or_default()
+    }
+}
+
+// Shortest distance to every node reachable from a set of sources
+#[derive(Debug, Clone)]
+pub struct DistanceMap<N> {
+    distances: BTreeMap<N, isize>
+}
+
+pub fn distances<G>(graph: &G, source: G::Node) -> DistanceMap<G::Node>
+    where G: Graph
+{
+    multi_source_distances(graph, std::iter::once(source))
+}
+
+pub fn multi_source_distances<G, I>(graph: &G, sources: I) -> DistanceMap<G::Node>
+    where G: Graph, I: IntoIterator<Item = G::Node>
+{
+    let mut distances = BTreeMap::new();
+    let mut queue = BinaryHeap::new();
+
+    for source in sources {
+        queue.push(Reverse((0, source)));
+    }
+
+    while let Some(Reverse((dist, node))) = queue.pop() {
+        if distances.contains_key(&node) {
+            continue;
+        }
+
+        distances.insert(node, dist);
+
+        for (neighbor, cost) in graph.edges(&node) {
+            if !distances.contains_key(&neighbor) {
+                queue.push(Reverse((dist + cost, neighbor)));
+            }
+        }
+    }
+
+    DistanceMap {
+        distances
+    }
+}
+
+// Total distance from all maps, for nodes every map has reached
+pub fn summed_distances<N>(maps: &[DistanceMap<N>]) -> DistanceMap<N>
+    where N: Copy + Ord
+{
+    let mut distances = match maps.first() {
+        Some(first) => first.distances.clone(),
+        None => BTreeMap::new()
+    };
+
+    for map in maps.iter().skip(1) {
+        distances = distances
+            .into_iter()
+            .filter_map(|(n, d)| Some((n, d + map.get(&n)?)))
+            .collect();
+    }
+
+    DistanceMap {
+        distances
+    }
+}
+
+impl<N> DistanceMap<N> where N: Copy + Ord {
+    pub fn get(&self, node: &N) -> Option<isize> {
+        self.distances.get(node).cloned()
+    }
+
+    pub fn contains(&self, node: &N) -> bool {
+        self.distances.contains_key(node)
+    }
+
+    pub fn len(&self) -> usize {
+        self.distances.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.distances.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (N, isize)> + '_ {
+        self.distances.iter().map(|(n, d)| (*n, *d))
+    }
+
+    // Farthest node, the first one in order if several are equally far
+    pub fn max(&self) -> Option<(N, isize)> {
+        self.iter().fold(None, |best, (n, d)| match best {
+            Some((_, bd)) if bd >= d => best,
+            _ => Some((n, d))
+        })
+    }
+
+    pub fn count_at_least(&self, k: isize) -> usize {
+        self.distances.values().filter(|&&d| d >= k).count()
+    }
+
+    pub fn count_below(&self, k: isize) -> usize {
+        self.distances.values().filter(|&&d| d < k).count()
+    }
+
+    // Closest of the given nodes, ties broken by node order, which is reading
+    // order for grid coordinates. Unreachable nodes are skipped.
+    pub fn nearest<I>(&self, nodes: I) -> Option<(N, isize)>
+        where I: IntoIterator<Item = N>
+    {
+        nodes
+            .into_iter()
+            .filter_map(|n| Some((n, self.get(&n)?)))
+            .min_by(|(n1, d1), (n2, d2)| d1.cmp(d2).then(n1.cmp(n2)))
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::iter::Step;
+
+use super::{adjacent, Connectivity, Coordinate, Grid};
+
+#[derive(Debug, Clone)]
+pub struct Region<C: Coordinate> {
+    pub cells: BTreeSet<C>,
+
+    // Number of cell edges facing a tile outside of the region, counted the
+    // same way regardless of the connectivity used to build the region
+    pub perimeter: usize,
+
+    // Bounding box of the region, exclusive upper bound like `Grid::bounds`
+    pub bounds: (C, C),
+
+    pub touches_border: bool
+}
+
+impl<C: Coordinate> Region<C> {
+    fn from_cells<G>(grid: &G, cells: BTreeSet<C>) -> Self
+        where G: Grid<Coord = C>
+    {
+        let (cmin, cmax) = C::numeric_limits();
+
+        let (mut min_y, mut min_x) = (cmax, cmax);
+        let (mut max_y, mut max_x) = (cmin, cmin);
+
+        let mut perimeter = 0;
+        let mut touches_border = false;
+
+        for cell in &cells {
+            if cell.y() < min_y { min_y = cell.y(); }
+            if cell.x() < min_x { min_x = cell.x(); }
+            if cell.y() > max_y { max_y = cell.y(); }
+            if cell.x() > max_x { max_x = cell.x(); }
+
+            perimeter += cell.neighbors().filter(|n| !cells.contains(n)).count();
+            touches_border |= grid.is_border(cell);
+        }
+
+        let bounds = if cells.is_empty() {
+            (C::new(cmin, cmin), C::new(cmin, cmin))
+        } else {
+            (C::new(min_y, min_x), C::new(Step::forward(max_y, 1), Step::forward(max_x, 1)))
+        };
+
+        Region {
+            cells,
+            perimeter,
+            bounds,
+            touches_border
+        }
+    }
+
+    pub fn area(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn contains(&self, coord: &C) -> bool {
+        self.cells.contains(coord)
+    }
+}
+
+// Breadth first fill from `start` over every in-bounds coordinate accepted by `include`
+fn fill<G, F>(grid: &G, start: G::Coord, connectivity: Connectivity, mut include: F) -> BTreeSet<G::Coord>
+    where G: Grid, F: FnMut(&G::Coord) -> bool
+{
+    let mut cells = BTreeSet::new();
+    let mut queue = VecDeque::new();
+
+    if !grid.contains(&start) || !include(&start) {
+        return cells;
+    }
+
+    cells.insert(start);
+    queue.push_back(start);
+
+    while let Some(c) = queue.pop_front() {
+        for neighbor in adjacent(&c, connectivity) {
+            if grid.contains(&neighbor) && !cells.contains(&neighbor) && include(&neighbor) {
+                cells.insert(neighbor);
+                queue.push_back(neighbor);
+            }
+        }
+    }
+
+    cells
+}
+
+// All tiles reachable from `start` through tiles matching the predicate. The
+// region is empty if the starting tile itself doesn't match.
+pub fn flood_fill<G, F>(grid: &G, start: G::Coord, connectivity: Connectivity, predicate: F) -> Region<G::Coord>
+    where G: Grid, F: Fn(&G::Tile) -> bool
+{
+    let cells = fill(grid, start, connectivity, |c| predicate(grid.tile_at(c)));
+
+    Region::from_cells(grid, cells)
+}
+
+#[derive(Debug, Clone)]
+pub struct Components<C: Coordinate> {
+    labels: BTreeMap<C, usize>,
+    regions: Vec<Region<C>>
+}
+
+impl<C: Coordinate> Components<C> {
+    pub fn label(&self, coord: &C) -> Option<usize> {
+        self.labels.get(coord).cloned()
+    }
+
+    pub fn region_of(&self, coord: &C) -> Option<&Region<C>> {
+        self.label(coord).map(|l| &self.regions[l])
+    }
+
+    pub fn regions(&self) -> &[Region<C>] {
+        &self.regions
+    }
+
+    pub fn len(&self) -> usize {
+        self.regions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+}
+
+// Label connected components of tiles sharing the same key. Tiles keyed to
+// `None` are background and left unlabeled. Labels are handed out in reading
+// order of each component's first tile.
+pub fn label_components<G, K, F>(grid: &G, connectivity: Connectivity, key: F) -> Components<G::Coord>
+    where G: Grid, K: PartialEq, F: Fn(&G::Tile) -> Option<K>
+{
+    let mut labels = BTreeMap::new();
+    let mut regions = Vec::new();
+
+    for coord in grid.coords() {
+        if labels.contains_key(&coord) {
+            continue;
+        }
+
+        let k = match key(grid.tile_at(&coord)) {
+            Some(k) => k,
+            None => continue
+        };
+
+        let cells = fill(grid, coord, connectivity, |c| key(grid.tile_at(c)).as_ref() == Some(&k));
+
+        for cell in &cells {
+            labels.insert(*cell, regions.len());
+        }
+
+        regions.push(Region::from_cells(grid, cells));
+    }
+
+    Components {
+        labels,
+        regions
+    }
+}
@@ -0,0 +1,76 @@
+use super::{Coordinate, Grid, Numeric};
+
+// Two dimensional prefix sums over a rectangular area. Every entry holds the sum
+// of all values above and to the left of it, so any rectangle can be summed up
+// from four lookups. Indices are relative to the top left corner of the source.
+#[derive(Debug, Clone)]
+pub struct SummedAreaTable<T> {
+    height: usize,
+    width: usize,
+
+    // (height + 1) x (width + 1), the first row and column are all zero
+    sums: Vec<T>
+}
+
+impl<T> SummedAreaTable<T> where T: Numeric + Default {
+    pub fn from_fn<F>(height: usize, width: usize, value: F) -> Self
+        where F: Fn(usize, usize) -> T
+    {
+        let stride = width + 1;
+        let mut sums = vec![T::default(); (height + 1) * stride];
+
+        for y in 0..height {
+            let mut row = T::default();
+
+            for x in 0..width {
+                row = row + value(y, x);
+
+                sums[(y + 1) * stride + x + 1] = sums[y * stride + x + 1] + row;
+            }
+        }
+
+        SummedAreaTable {
+            height,
+            width,
+            sums
+        }
+    }
+
+    pub fn from_grid<G, F>(grid: &G, value: F) -> Self
+        where G: Grid, F: Fn(&G::Tile) -> T
+    {
+        let (lower, upper) = grid.bounds();
+
+        let rows = (lower.y() .. upper.y()).collect::<Vec<_>>();
+        let cols = (lower.x() .. upper.x()).collect::<Vec<_>>();
+
+        SummedAreaTable::from_fn(rows.len(), cols.len(), |y, x| {
+            value(grid.tile_at(&<G::Coord as Coordinate>::new(rows[y], cols[x])))
+        })
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    fn at(&self, y: usize, x: usize) -> T {
+        self.sums[y * (self.width + 1) + x]
+    }
+
+    // Sum of the rectangle from (top, left) up to, but excluding (bottom, right)
+    pub fn sum(&self, top: usize, left: usize, bottom: usize, right: usize) -> T {
+        assert!(top <= bottom && bottom <= self.height, "row range out of bounds");
+        assert!(left <= right && right <= self.width, "column range out of bounds");
+
+        // Add before subtracting so unsigned sums never dip below zero
+        (self.at(bottom, right) + self.at(top, left)) - (self.at(top, right) + self.at(bottom, left))
+    }
+
+    pub fn square_sum(&self, top: usize, left: usize, size: usize) -> T {
+        self.sum(top, left, top + size, left + size)
+    }
+}
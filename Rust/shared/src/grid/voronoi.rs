@@ -0,0 +1,448 @@
+use std::collections::VecDeque;
+
+use super::{adjacent, Connectivity, Coord};
+use crate::geom::BoundingBox;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Metric {
+    Manhattan, Chebyshev, Euclidean
+}
+
+impl Metric {
+    // Orders distances the same way the metric does, but stays in integers by
+    // leaving Euclidean distances squared
+    pub fn rank(&self, a: &Coord, b: &Coord) -> isize {
+        let (dy, dx) = ((a.0 - b.0).abs(), (a.1 - b.1).abs());
+
+        match self {
+            Metric::Manhattan => dy + dx,
+            Metric::Chebyshev => dy.max(dx),
+            Metric::Euclidean => dy * dy + dx * dx
+        }
+    }
+
+    pub fn distance(&self, a: &Coord, b: &Coord) -> f64 {
+        match self {
+            Metric::Euclidean => (self.rank(a, b) as f64).sqrt(),
+            _ => self.rank(a, b) as f64
+        }
+    }
+
+    // Moves on the grid that step the distance by exactly one, if any
+    fn connectivity(&self) -> Option<Connectivity> {
+        match self {
+            Metric::Manhattan => Some(Connectivity::Four),
+            Metric::Chebyshev => Some(Connectivity::Eight),
+            Metric::Euclidean => None
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Nearest {
+    Site(usize),
+    Tie
+}
+
+// Rectangular area with an exclusive upper bound, stored row by row
+#[derive(Debug, Clone)]
+struct Partition {
+    lower: Coord,
+    upper: Coord,
+    cells: Vec<Nearest>
+}
+
+impl Partition {
+    // Smallest area holding every point, still to be filled in
+    fn around<'a, I>(points: I) -> Self
+        where I: IntoIterator<Item = &'a Coord>
+    {
+        let bbox = points.into_iter().map(|c| (c.1, c.0)).collect::<BoundingBox<isize>>();
+        let ((x0, y0), (x1, y1)) = bbox.corners().expect("area around no points");
+
+        Partition {
+            lower: Coord(y0, x0),
+            upper: Coord(y1 + 1, x1 + 1),
+            cells: Vec::new()
+        }
+    }
+
+    fn contains(&self, c: &Coord) -> bool {
+        c.0 >= self.lower.0 && c.0 < self.upper.0 && c.1 >= self.lower.1 && c.1 < self.upper.1
+    }
+
+    fn index(&self, c: &Coord) -> usize {
+        ((c.0 - self.lower.0) * (self.upper.1 - self.lower.1) + (c.1 - self.lower.1)) as usize
+    }
+
+    fn coords(&self) -> impl Iterator<Item = Coord> {
+        let (lower, upper) = (self.lower, self.upper);
+
+        (lower.0 .. upper.0).flat_map(move |y| (lower.1 .. upper.1).map(move |x| Coord(y, x)))
+    }
+
+    fn border(&self) -> impl Iterator<Item = Coord> + '_ {
+        self.coords().filter(move |c| c.0 == self.lower.0 || c.0 == self.upper.0 - 1
+            || c.1 == self.lower.1 || c.1 == self.upper.1 - 1)
+    }
+}
+
+// Nearest-site partition of the lattice. The partition is materialized over an
+// area large enough to hold every bounded region, whether a region is bounded
+// is decided exactly for each metric instead of by probing a larger area.
+#[derive(Debug, Clone)]
+pub struct Voronoi {
+    sites: Vec<Coord>,
+    metric: Metric,
+    partition: Partition,
+    unbounded: Vec<bool>
+}
+
+fn cross(o: &Coord, a: &Coord, b: &Coord) -> isize {
+    (a.1 - o.1) * (b.0 - o.0) - (a.0 - o.0) * (b.1 - o.1)
+}
+
+// Vertices of the convex hull in order, collinear points dropped (monotone chain)
+fn convex_hull(sites: &[Coord]) -> Vec<Coord> {
+    let mut points = sites.to_vec();
+
+    points.sort_by_key(|c| (c.1, c.0));
+    points.dedup();
+
+    if points.len() < 3 {
+        return points;
+    }
+
+    let mut hull: Vec<Coord> = Vec::with_capacity(points.len() * 2);
+
+    for pass in 0..2 {
+        let start = hull.len();
+
+        let iter: Box<dyn Iterator<Item = &Coord>> = if pass == 0 {
+            Box::new(points.iter())
+        } else {
+            Box::new(points.iter().rev())
+        };
+
+        for p in iter {
+            while hull.len() >= start + 2 && cross(&hull[hull.len() - 2], &hull[hull.len() - 1], p) <= 0 {
+                hull.pop();
+            }
+
+            hull.push(*p);
+        }
+
+        hull.pop();
+    }
+
+    hull
+}
+
+impl Voronoi {
+    pub fn new(sites: Vec<Coord>, metric: Metric) -> Self {
+        assert!(!sites.is_empty(), "voronoi partition needs at least one site");
+
+        let mut voronoi = Voronoi {
+            unbounded: vec![false; sites.len()],
+            partition: Partition::around(&sites),
+            sites,
+            metric
+        };
+
+        voronoi.unbounded = voronoi.find_unbounded();
+
+        let (lower, upper) = voronoi.bounded_extent();
+        voronoi.partition = voronoi.partition_area(lower, upper);
+
+        voronoi
+    }
+
+    pub fn sites(&self) -> &[Coord] {
+        &self.sites
+    }
+
+    pub fn metric(&self) -> Metric {
+        self.metric
+    }
+
+    // Area the partition was materialized over, exclusive upper bound
+    pub fn bounds(&self) -> (Coord, Coord) {
+        (self.partition.lower, self.partition.upper)
+    }
+
+    fn nearest_direct(&self, c: &Coord) -> Nearest {
+        let mut best = (isize::MAX, Nearest::Tie);
+
+        for (i, s) in self.sites.iter().enumerate() {
+            let rank = self.metric.rank(c, s);
+
+            if rank < best.0 {
+                best = (rank, Nearest::Site(i));
+            } else if rank == best.0 {
+                best.1 = Nearest::Tie;
+            }
+        }
+
+        best.1
+    }
+
+    pub fn nearest(&self, c: &Coord) -> Nearest {
+        if self.partition.contains(c) {
+            self.partition.cells[self.partition.index(c)]
+        } else {
+            self.nearest_direct(c)
+        }
+    }
+
+    fn partition_area(&self, lower: Coord, upper: Coord) -> Partition {
+        let mut partition = Partition { lower, upper, cells: Vec::new() };
+
+        match self.metric.connectivity() {
+            Some(connectivity) => {
+                // Multi-source BFS, layer by layer. A cell reached from two
+                // different owners within the same layer is a tie, and ties
+                // spread outwards just like owners do.
+                let mut dist = vec![usize::MAX; partition.coords().count()];
+                partition.cells = vec![Nearest::Tie; dist.len()];
+
+                let mut queue = VecDeque::new();
+
+                for (i, s) in self.sites.iter().enumerate() {
+                    let idx = partition.index(s);
+
+                    if dist[idx] == 0 {
+                        partition.cells[idx] = Nearest::Tie;
+                    } else {
+                        dist[idx] = 0;
+                        partition.cells[idx] = Nearest::Site(i);
+
+                        queue.push_back(*s);
+                    }
+                }
+
+                while let Some(c) = queue.pop_front() {
+                    let idx = partition.index(&c);
+                    let owner = partition.cells[idx];
+
+                    for n in adjacent(&c, connectivity) {
+                        if !partition.contains(&n) {
+                            continue;
+                        }
+
+                        let nidx = partition.index(&n);
+
+                        if dist[nidx] == usize::MAX {
+                            dist[nidx] = dist[idx] + 1;
+                            partition.cells[nidx] = owner;
+
+                            queue.push_back(n);
+                        } else if dist[nidx] == dist[idx] + 1 && partition.cells[nidx] != owner {
+                            partition.cells[nidx] = Nearest::Tie;
+                        }
+                    }
+                }
+            },
+
+            None => {
+                partition.cells = partition.coords().map(|c| self.nearest_direct(&c)).collect();
+            }
+        }
+
+        partition
+    }
+
+    // Area holding every bounded region
+    fn bounded_extent(&self) -> (Coord, Coord) {
+        let Partition { lower, upper, .. } = Partition::around(&self.sites);
+
+        match self.metric {
+            // Anything outside the bounding box of the sites belongs to an
+            // unbounded region, see find_unbounded
+            Metric::Manhattan => (lower, upper),
+
+            // Same on the diagonals, the box there is one diagonal step larger
+            Metric::Chebyshev => {
+                let diagonals = self.sites.iter().map(|s| Coord(s.1 + s.0, s.1 - s.0)).collect::<Vec<_>>();
+                let Partition { lower: dl, upper: du, .. } = Partition::around(&diagonals);
+
+                let (umin, umax, vmin, vmax) = (dl.0 - 1, du.0, dl.1 - 1, du.1);
+
+                (Coord((umin - vmax) / 2 - 1, (umin + vmin) / 2 - 1),
+                 Coord((umax - vmin) / 2 + 2, (umax + vmax) / 2 + 2))
+            },
+
+            // Clip every bounded cell out of the plane to find how far it reaches
+            Metric::Euclidean => {
+                let (mut min, mut max) = ((lower.0 as f64, lower.1 as f64), ((upper.0 - 1) as f64, (upper.1 - 1) as f64));
+
+                for (i, site) in self.sites.iter().enumerate() {
+                    // Duplicated sites tie everywhere and own nothing
+                    if self.unbounded[i] || self.nearest_direct(site) == Nearest::Tie {
+                        continue;
+                    }
+
+                    for (y, x) in self.euclidean_cell(site) {
+                        min = (min.0.min(y), min.1.min(x));
+                        max = (max.0.max(y), max.1.max(x));
+                    }
+                }
+
+                (Coord(min.0.floor() as isize - 1, min.1.floor() as isize - 1),
+                 Coord(max.0.ceil() as isize + 2, max.1.ceil() as isize + 2))
+            }
+        }
+    }
+
+    // Polygon of points at least as close to the site as to any other site,
+    // starting from a square far larger than any bounded cell can be
+    fn euclidean_cell(&self, site: &Coord) -> Vec<(f64, f64)> {
+        const FAR: f64 = 1e12;
+
+        let (sy, sx) = (site.0 as f64, site.1 as f64);
+        let mut polygon = vec![(sy - FAR, sx - FAR), (sy - FAR, sx + FAR), (sy + FAR, sx + FAR), (sy + FAR, sx - FAR)];
+
+        for other in &self.sites {
+            if other == site {
+                continue;
+            }
+
+            // Half-plane 2p.(o - s) <= |o|^2 - |s|^2
+            let (ny, nx) = (2.0 * (other.0 - site.0) as f64, 2.0 * (other.1 - site.1) as f64);
+            let limit = (other.0 * other.0 + other.1 * other.1 - site.0 * site.0 - site.1 * site.1) as f64;
+
+            let side = |p: &(f64, f64)| p.0 * ny + p.1 * nx - limit;
+
+            let mut clipped = Vec::with_capacity(polygon.len() + 1);
+
+            for i in 0..polygon.len() {
+                let (a, b) = (polygon[i], polygon[(i + 1) % polygon.len()]);
+                let (da, db) = (side(&a), side(&b));
+
+                if da <= 0.0 {
+                    clipped.push(a);
+                }
+
+                if (da < 0.0 && db > 0.0) || (da > 0.0 && db < 0.0) {
+                    let t = da / (da - db);
+
+                    clipped.push((a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t));
+                }
+            }
+
+            polygon = clipped;
+        }
+
+        polygon
+    }
+
+    fn find_unbounded(&self) -> Vec<bool> {
+        let mut unbounded = vec![false; self.sites.len()];
+
+        let mut mark = |nearest| if let Nearest::Site(s) = nearest {
+            unbounded[s] = true;
+        };
+
+        match self.metric {
+            Metric::Manhattan => {
+                // Stepping outwards from the bounding box of the sites moves
+                // away from every site by the same amount, so whoever owns a
+                // border cell owns everything beyond it.
+                for c in Partition::around(&self.sites).border() {
+                    mark(self.nearest_direct(&c));
+                }
+            },
+
+            Metric::Chebyshev => {
+                // Chebyshev is Manhattan on the diagonals u = x + y, v = x - y,
+                // where only cells with u and v of equal parity exist. A
+                // diagonal step moves u or v by two, so the border is two wide.
+                let diagonals = self.sites.iter().map(|s| Coord(s.1 + s.0, s.1 - s.0)).collect::<Vec<_>>();
+                let Partition { lower, upper, .. } = Partition::around(&diagonals);
+
+                for u in lower.0 - 1 ..= upper.0 {
+                    for v in lower.1 - 1 ..= upper.1 {
+                        let on_border = u <= lower.0 || u >= upper.0 - 1 || v <= lower.1 || v >= upper.1 - 1;
+
+                        if on_border && (u + v) % 2 == 0 {
+                            mark(self.nearest_direct(&Coord((u - v) / 2, (u + v) / 2)));
+                        }
+                    }
+                }
+            },
+
+            Metric::Euclidean => {
+                // Exactly the sites on the boundary of the convex hull have
+                // unbounded cells
+                let hull = convex_hull(&self.sites);
+
+                for (i, s) in self.sites.iter().enumerate() {
+                    if let Nearest::Tie = self.nearest_direct(s) {
+                        continue;
+                    }
+
+                    unbounded[i] = hull.len() < 3 || (0..hull.len()).any(|h| {
+                        let (a, b) = (&hull[h], &hull[(h + 1) % hull.len()]);
+
+                        cross(a, b, s) == 0
+                            && s.0 >= a.0.min(b.0) && s.0 <= a.0.max(b.0)
+                            && s.1 >= a.1.min(b.1) && s.1 <= a.1.max(b.1)
+                    });
+                }
+            }
+        }
+
+        unbounded
+    }
+
+    pub fn is_unbounded(&self, site: usize) -> bool {
+        self.unbounded[site]
+    }
+
+    // Cells closer to the site than to any other, None for unbounded regions
+    pub fn region_size(&self, site: usize) -> Option<usize> {
+        if self.unbounded[site] {
+            None
+        } else {
+            Some(self.partition.cells.iter().filter(|&&n| n == Nearest::Site(site)).count())
+        }
+    }
+
+    pub fn region_sizes(&self) -> Vec<Option<usize>> {
+        let mut sizes = vec![0; self.sites.len()];
+
+        for cell in &self.partition.cells {
+            if let Nearest::Site(s) = cell {
+                sizes[*s] += 1;
+            }
+        }
+
+        sizes.into_iter().enumerate().map(|(s, size)| {
+            if self.unbounded[s] { None } else { Some(size) }
+        }).collect()
+    }
+
+    // Cells within the materialized area that are equally close to several sites
+    pub fn ties(&self) -> impl Iterator<Item = Coord> + '_ {
+        self.partition.coords().filter(move |c| self.partition.cells[self.partition.index(c)] == Nearest::Tie)
+    }
+
+    pub fn total_distance(&self, c: &Coord) -> f64 {
+        self.sites.iter().map(|s| self.metric.distance(c, s)).sum()
+    }
+
+    // Number of cells whose summed distance to all sites is below the limit
+    pub fn total_distance_area(&self, limit: f64) -> usize {
+        // A cell m steps outside the bounding box is at least m away from
+        // every site, so nothing beyond limit / sites can qualify
+        let margin = (limit / self.sites.len() as f64).ceil().max(0.0) as isize + 1;
+        let Partition { lower, upper, .. } = Partition::around(&self.sites);
+
+        let area = Partition {
+            lower: Coord(lower.0 - margin, lower.1 - margin),
+            upper: Coord(upper.0 + margin, upper.1 + margin),
+            cells: Vec::new()
+        };
+
+        area.coords().filter(|c| self.total_distance(c) < limit).count()
+    }
+}
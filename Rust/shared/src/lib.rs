@@ -11,8 +11,1058 @@ pub mod input {
 }
 
 pub mod grid;
+pub mod geom;
 pub mod cpu;
 
 #[cfg(test)]
 mod tests {
+    use crate::geom::*;
+
+    #[test]
+    fn interval_bounds() {
+        let i = Interval::inclusive(3, 5);
+
+        assert_eq!(i, Interval::new(3, 6));
+        assert_eq!(i.len(), 3);
+        assert_eq!(i.last(), Some(5));
+        assert!(i.contains(5) && !i.contains(6));
+
+        assert!(Interval::new(4, 4).is_empty());
+        assert_eq!(Interval::new(4, 2).len(), 0);
+    }
+
+    #[test]
+    fn interval_intersection() {
+        let a = Interval::new(0, 5);
+
+        assert_eq!(a.intersection(&Interval::new(3, 8)), Some(Interval::new(3, 5)));
+        assert_eq!(a.intersection(&Interval::new(5, 8)), None);
+        assert_eq!(a.union(&Interval::new(7, 8)), Interval::new(0, 8));
+    }
+
+    #[test]
+    fn interval_set_merges() {
+        let mut set = IntervalSet::new();
+
+        set.insert(Interval::new(10, 12));
+        set.insert(Interval::new(0, 3));
+        set.insert(Interval::new(3, 5));
+        set.insert(Interval::new(8, 11));
+
+        assert_eq!(set.intervals(), &[Interval::new(0, 5), Interval::new(8, 12)]);
+        assert_eq!(set.len(), 9);
+        assert!(set.contains(4) && !set.contains(5) && set.contains(11));
+
+        let other = vec![Interval::new(2, 9)].into_iter().collect::<IntervalSet<_>>();
+
+        assert_eq!(set.intersection(&other).intervals(), &[Interval::new(2, 5), Interval::new(8, 9)]);
+        assert_eq!(set.union(&other).intervals(), &[Interval::new(0, 12)]);
+    }
+
+    #[test]
+    fn rect_overlap() {
+        // Two day 3 style claims, #1 @ 1,3: 4x4 and #2 @ 3,1: 4x4
+        let a = Rect::from_size((1, 3), 4, 4);
+        let b = Rect::from_size((3, 1), 4, 4);
+        let c = Rect::from_size((5, 5), 2, 2);
+
+        assert_eq!(a.intersection(&b), Some(Rect::inclusive((3, 3), (4, 4))));
+        assert_eq!(a.intersection(&b).map(|r| r.area()), Some(4));
+        assert!(!a.overlaps(&c) && !b.overlaps(&c));
+
+        assert_eq!(a.union(&c), Rect::exclusive((1, 3), (7, 7)));
+        assert!(a.union(&c).contains_rect(&c));
+        assert!(a.contains((4, 6)) && !a.contains((5, 6)));
+    }
+
+    #[test]
+    fn rect_points() {
+        let r = Rect::inclusive((0, 0), (1, 1));
+
+        assert_eq!(r.points().collect::<Vec<_>>(), vec![(0, 0), (1, 0), (0, 1), (1, 1)]);
+        assert_eq!(Rect::exclusive((2, 2), (2, 5)).area(), 0);
+    }
+
+    #[test]
+    fn bounding_box() {
+        let bb = vec![(3, -1), (-2, 4), (0, 0)].into_iter().collect::<BoundingBox<i32>>();
+
+        assert_eq!(bb.corners(), Some(((-2, -1), (3, 4))));
+        assert_eq!(bb.rect(), Some(Rect::exclusive((-2, -1), (4, 5))));
+        assert!(BoundingBox::<i32>::new().rect().is_none());
+    }
+
+    // Tiles for grid tests, written as characters
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    struct Tile(char);
+
+    impl crate::grid::GridTile for Tile {
+        fn to_char(&self) -> char {
+            self.0
+        }
+
+        fn color(&self) -> crate::grid::TileColor {
+            crate::grid::TileColor::NoColor
+        }
+    }
+
+    // Walls are '#', anything else takes a step to enter
+    impl crate::grid::NavigatableTile for Tile {
+        fn movement_cost(&self, other: &Self) -> crate::grid::MovementCost {
+            match other.0 {
+                '#' => crate::grid::MovementCost::Impassable,
+                _ => crate::grid::MovementCost::Passable(1)
+            }
+        }
+    }
+
+    fn tiles(s: &str) -> Vec<Vec<Tile>> {
+        s.lines().map(|line| line.chars().map(Tile).collect()).collect()
+    }
+
+    #[test]
+    fn flood_fill_regions() {
+        use crate::grid::{flood_fill, Connectivity, Coord};
+
+        let grid = tiles("##..#\n#...#\n.#...");
+        let region = flood_fill(&grid, Coord(0, 0), Connectivity::Four, |t| t.0 == '#');
+
+        assert_eq!(region.cells.iter().cloned().collect::<Vec<_>>(), vec![Coord(0, 0), Coord(0, 1), Coord(1, 0)]);
+        assert_eq!((region.area(), region.perimeter), (3, 8));
+        assert_eq!(region.bounds, (Coord(0, 0), Coord(2, 2)));
+        assert!(region.touches_border);
+
+        // Diagonals join (2, 1) to the corner, the perimeter still only counts sides
+        let region = flood_fill(&grid, Coord(0, 0), Connectivity::Eight, |t| t.0 == '#');
+
+        assert!(region.contains(&Coord(2, 1)) && !region.contains(&Coord(0, 4)));
+        assert_eq!((region.area(), region.perimeter), (4, 12));
+
+        assert_eq!(flood_fill(&grid, Coord(1, 1), Connectivity::Four, |t| t.0 == '#').area(), 0);
+        assert_eq!(flood_fill(&grid, Coord(5, 5), Connectivity::Four, |_| true).area(), 0);
+    }
+
+    #[test]
+    fn component_labels() {
+        use crate::grid::{label_components, Connectivity, Coord};
+
+        let grid = tiles("##..#\n#...#\n.#...");
+        let walls = |t: &Tile| if t.0 == '#' { Some(()) } else { None };
+
+        let four = label_components(&grid, Connectivity::Four, walls);
+
+        assert_eq!(four.len(), 3);
+        assert_eq!(four.regions().iter().map(|r| r.area()).collect::<Vec<_>>(), vec![3, 2, 1]);
+        assert_eq!((four.label(&Coord(1, 4)), four.label(&Coord(2, 1)), four.label(&Coord(1, 1))), (Some(1), Some(2), None));
+
+        let eight = label_components(&grid, Connectivity::Eight, walls);
+
+        assert_eq!(eight.len(), 2);
+        assert_eq!(eight.region_of(&Coord(2, 1)).map(|r| r.area()), Some(4));
+
+        // Every tile keyed by its character, so the floor is labelled as well
+        let all = label_components(&grid, Connectivity::Four, |t| Some(t.0));
+
+        assert_eq!(all.len(), 5);
+        assert_eq!(all.regions().iter().map(|r| r.area()).sum::<usize>(), 15);
+    }
+
+    #[test]
+    fn distance_maps() {
+        use std::collections::BTreeMap;
+        use crate::grid::{distances, multi_source_distances, summed_distances, Coord, GridGraph};
+
+        let grid = tiles("#######\n#.....#\n#.#.#.#\n#.....#\n#######");
+        let graph = GridGraph(&grid);
+        let from_corner = distances(&graph, Coord(1, 1));
+
+        assert_eq!(from_corner.len(), 13);
+        assert!(!from_corner.contains(&Coord(2, 2)));
+        assert_eq!(from_corner.get(&Coord(1, 5)), Some(4));
+        assert_eq!(from_corner.max(), Some((Coord(3, 5), 6)));
+
+        // Both are two steps away, the one first in reading order wins
+        assert_eq!(from_corner.nearest(vec![Coord(3, 1), Coord(1, 3)]), Some((Coord(1, 3), 2)));
+        assert_eq!(from_corner.nearest(vec![Coord(3, 1), Coord(0, 0)]), Some((Coord(3, 1), 2)));
+
+        let both = multi_source_distances(&graph, vec![Coord(1, 1), Coord(3, 5)]);
+
+        assert_eq!(both.max(), Some((Coord(1, 4), 3)));
+        assert_eq!((both.count_below(2), both.count_at_least(3)), (6, 3));
+
+        let summed = summed_distances(&[from_corner, distances(&graph, Coord(3, 5))]);
+
+        assert_eq!(summed.len(), 13);
+        assert!(summed.iter().all(|(_, d)| d >= 6));
+
+        // Explicit graphs, where node 4 can only be left
+        let mut links = BTreeMap::new();
+
+        links.insert(1, vec![2]);
+        links.insert(2, vec![3]);
+        links.insert(4, vec![1]);
+
+        let from_one = distances(&links, 1);
+
+        assert_eq!(from_one.iter().collect::<Vec<_>>(), vec![(1, 0), (2, 1), (3, 2)]);
+        assert_eq!(from_one.nearest(vec![4, 3]), Some((3, 2)));
+        assert_eq!(summed_distances(&[from_one, distances(&links, 4)]).iter().collect::<Vec<_>>(), vec![(1, 1), (2, 3), (3, 5)]);
+    }
+
+    #[test]
+    fn voronoi_day6() {
+        use crate::grid::{Coord, Metric, Voronoi};
+
+        let sites = vec![Coord(1, 1), Coord(6, 1), Coord(3, 8), Coord(4, 3), Coord(5, 5), Coord(9, 8)];
+        let field = Voronoi::new(sites, Metric::Manhattan);
+
+        assert_eq!(field.region_sizes(), vec![None, None, None, Some(9), Some(17), None]);
+        assert_eq!(field.total_distance_area(32.0), 16);
+    }
+
+    #[test]
+    fn voronoi_metrics() {
+        use crate::grid::{Coord, Metric, Nearest, Voronoi};
+
+        // Corners around a center, one more site halfway along the top edge,
+        // one duplicated site and a few scattered ones
+        let layouts = vec![
+            vec![Coord(0, 0), Coord(0, 4), Coord(4, 0), Coord(4, 4), Coord(2, 2)],
+            vec![Coord(0, 0), Coord(0, 4), Coord(4, 0), Coord(4, 4), Coord(2, 2), Coord(0, 2)],
+            vec![Coord(1, 1), Coord(5, 2), Coord(5, 2), Coord(3, 7), Coord(-2, 4)],
+            vec![Coord(0, 0), Coord(0, 2), Coord(3, 7), Coord(6, 1), Coord(5, 4), Coord(2, 3)]
+        ];
+
+        for sites in layouts {
+            for &metric in [Metric::Manhattan, Metric::Chebyshev, Metric::Euclidean].iter() {
+                let field = Voronoi::new(sites.clone(), metric);
+                let nearest = |c: &Coord| {
+                    let ranks = sites.iter().map(|s| metric.rank(c, s)).collect::<Vec<_>>();
+                    let best = *ranks.iter().min().unwrap();
+
+                    match ranks.iter().filter(|&&r| r == best).count() {
+                        1 => Nearest::Site(ranks.iter().position(|&r| r == best).unwrap()),
+                        _ => Nearest::Tie
+                    }
+                };
+
+                let area = |margin: isize| (-2 - margin ..= 9 + margin).flat_map(move |y| (-2 - margin ..= 9 + margin).map(move |x| Coord(y, x)));
+
+                for c in area(10) {
+                    assert_eq!(field.nearest(&c), nearest(&c), "{:?} at {:?} with {:?}", metric, c, sites);
+                }
+
+                // Far out, only unbounded regions are left
+                for site in 0..sites.len() {
+                    let owned = area(30).filter(|c| nearest(c) == Nearest::Site(site)).collect::<Vec<_>>();
+                    let far = owned.iter().any(|c| c.0 == -32 || c.0 == 39 || c.1 == -32 || c.1 == 39);
+
+                    assert_eq!(field.is_unbounded(site), far, "{:?} site {:?} of {:?}", metric, sites[site], sites);
+
+                    if !far {
+                        assert_eq!(field.region_size(site), Some(owned.len()));
+                    }
+                }
+            }
+        }
+
+        // The center owns its row and column, the diagonals between it and the
+        // corners are ties for every metric
+        for &metric in [Metric::Manhattan, Metric::Chebyshev, Metric::Euclidean].iter() {
+            let field = Voronoi::new(vec![Coord(0, 0), Coord(0, 4), Coord(4, 0), Coord(4, 4), Coord(2, 2)], metric);
+
+            assert_eq!(field.region_size(4), Some(5));
+            assert!(field.ties().any(|c| c == Coord(1, 1)) && field.ties().any(|c| c == Coord(3, 3)));
+        }
+
+        // The edge site is on the hull, so it is unbounded under Euclidean, but
+        // Chebyshev ties it off with the corners
+        let edge = vec![Coord(0, 0), Coord(0, 4), Coord(4, 0), Coord(4, 4), Coord(2, 2), Coord(0, 2)];
+
+        assert!(Voronoi::new(edge.clone(), Metric::Euclidean).is_unbounded(5));
+        assert!(Voronoi::new(edge.clone(), Metric::Manhattan).is_unbounded(5));
+        assert!(!Voronoi::new(edge, Metric::Chebyshev).is_unbounded(5));
+    }
+
+    #[test]
+    fn summed_area_sums() {
+        use crate::grid::SummedAreaTable;
+
+        let value = |y: usize, x: usize| ((y * 7 + x * 3) % 11) as i64 - 5;
+        let table = SummedAreaTable::from_fn(6, 8, value);
+
+        for top in 0..=6 {
+            for left in 0..=8 {
+                for size in 0..=(6 - top).min(8 - left) {
+                    let brute = (top..top + size).flat_map(|y| (left..left + size).map(move |x| (y, x))).map(|(y, x)| value(y, x)).sum::<i64>();
+
+                    assert_eq!(table.square_sum(top, left, size), brute, "{}x{} at {},{}", size, size, top, left);
+                }
+            }
+        }
+
+        assert_eq!(table.sum(1, 2, 4, 3), (1..4).map(|y| value(y, 2)).sum::<i64>());
+
+        // Unsigned values from a grid of digits
+        let grid = tiles("123\n456");
+        let digits = SummedAreaTable::from_grid(&grid, |t| t.0.to_digit(10).unwrap());
+
+        assert_eq!((digits.height(), digits.width()), (2, 3));
+        assert_eq!((digits.sum(0, 0, 2, 3), digits.sum(1, 1, 2, 3), digits.square_sum(0, 1, 2)), (21, 11, 16));
+
+        // Day 11 example, the best 3x3 square for serial 18 is at 33,45 with a total of 29
+        let power = |y: usize, x: usize| {
+            let rack = x as i64 + 11;
+
+            ((rack * (y as i64 + 1) + 18) * rack / 100) % 10 - 5
+        };
+
+        assert_eq!(SummedAreaTable::from_fn(300, 300, power).square_sum(44, 32, 3), 29);
+    }
+
+    #[test]
+    fn opcode_discovery() {
+        use crate::cpu::{discovery, Mnemonic, SolveError};
+
+        let input = "Before: [3, 2, 1, 1]\n9 2 1 2\nAfter:  [3, 2, 2, 1]\n\n\n\n9 2 1 2\n";
+        let (samples, rest) = discovery::parse_samples(input).unwrap();
+
+        assert_eq!(rest, "9 2 1 2\n");
+        assert_eq!(samples[0].candidates(), vec![Mnemonic::Addi, Mnemonic::Mulr, Mnemonic::Seti]);
+
+        match discovery::solve(&samples) {
+            Err(SolveError::Ambiguous(solved, open)) => assert!(solved.is_empty() && open[&9].len() == 3),
+            other => panic!("expected an ambiguous result, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn program_parsing() {
+        use crate::cpu::{InstructionSet, ParseErrorKind, Program};
+
+        let source = "#ip 3\n; counts r1 down\nstart: seti 5 300 1 // B is unused\nloop:\n  addi 1 -1 1\n  gtri 1 0 2\n  addr 2 3 3\n  seti loop-1 0 3\n";
+        let program = source.parse::<Program>().unwrap();
+        let ops = program.instructions();
+
+        assert_eq!((program.ip_register(), program.len()), (Some(3), 5));
+        assert_eq!((program.label_address("start"), program.label_address("loop")), (Some(0), Some(1)));
+        assert_eq!((ops[0].to_string(), ops[4].to_string()), ("seti 5 0 1".to_string(), "seti 0 0 3".to_string()));
+
+        let errors = [
+            ("addr 1 2 3\nfoo 1 2 3", 2, ParseErrorKind::UnknownMnemonic("foo".to_string())),
+            ("seti 1 0 300", 1, ParseErrorKind::InvalidRegister(300)),
+            ("\naddr -1 2 3", 2, ParseErrorKind::InvalidRegister(-1)),
+            ("addi 1", 1, ParseErrorKind::MissingOperand),
+            ("seti 1 2 3 4", 1, ParseErrorKind::TooManyOperands),
+            ("seti 1 2 3\n\n; nothing\nseti far 0 1", 4, ParseErrorKind::UnknownLabel("far".to_string())),
+            ("a:\na: seti 0 0 0", 2, ParseErrorKind::DuplicateLabel("a".to_string())),
+            ("#ip 1\n#ip 2", 2, ParseErrorKind::DuplicateDirective("#ip".to_string())),
+            ("#ip 9000", 1, ParseErrorKind::InvalidRegister(9000)),
+            ("addi 1 x+ 2", 1, ParseErrorKind::InvalidOperand("x+".to_string())),
+
+            // Label arithmetic that does not fit a word
+            ("seti 1 0 1\nloop: seti loop+9223372036854775807 0 1", 2, ParseErrorKind::InvalidOperand("loop+9223372036854775807".to_string())),
+            ("seti x--9223372036854775808 0 1", 1, ParseErrorKind::InvalidOperand("x--9223372036854775808".to_string()))
+        ];
+
+        for (source, line, kind) in errors.iter() {
+            let error = source.parse::<Program>().unwrap_err();
+
+            assert_eq!((error.line, &error.kind), (*line, kind), "{:?}", source);
+        }
+
+        // Trailing unused operands can be left out of extended instructions
+        let extended = Program::parse_with("out 2\nnop\nhalt 0 0 0", &InstructionSet::extended()).unwrap();
+
+        assert_eq!(extended.len(), 3);
+        assert_eq!(Program::parse("nop").unwrap_err().kind, ParseErrorKind::UnknownMnemonic("nop".to_string()));
+    }
+
+    #[test]
+    fn machine_halts() {
+        use crate::cpu::{AluError, Halt, InstructionSet, Machine, Program};
+
+        // Day 19 example
+        let program = "#ip 0\nseti 5 0 1\nseti 6 0 2\naddi 0 1 0\naddr 1 2 3\nsetr 1 0 0\nseti 8 0 4\nseti 9 0 5\n".parse::<Program>().unwrap();
+        let mut machine = Machine::new(program.clone());
+
+        assert_eq!(machine.run_for(0), Halt::StepLimit);
+        assert_eq!(machine.run_for(2), Halt::StepLimit);
+        assert_eq!((machine.ip(), machine.steps()), (2, 2));
+
+        // Finishing within the budget reports how it finished
+        assert_eq!(machine.run_for(3), Halt::IpOutOfRange(7));
+        assert_eq!((machine.steps(), *machine.registers()), (5, [6, 5, 6, 0, 0, 9]));
+        assert_eq!(machine.run_for(10), Halt::IpOutOfRange(7));
+        assert_eq!(Machine::new(program).run(), Halt::IpOutOfRange(7));
+
+        // A fault leaves the machine on the failing instruction
+        let mut faulty = Machine::new("seti 1 0 2\nseti 1 0 9".parse::<Program>().unwrap());
+
+        match faulty.run() {
+            Halt::Fault(fault) => assert_eq!((fault.ip, fault.register()), (1, Some(9))),
+            other => panic!("expected a fault, got {:?}", other)
+        }
+
+        assert_eq!((faulty.ip(), faulty.steps(), faulty.registers()[2]), (1, 1, 1));
+
+        let mut unbound = Machine::new("seti 1 0 2".parse::<Program>().unwrap());
+
+        unbound.bind_ip(Some(6));
+
+        match unbound.run() {
+            Halt::Fault(fault) => assert_eq!(fault.error, AluError::InvalidRegister(6)),
+            other => panic!("expected a fault, got {:?}", other)
+        }
+
+        let mut stopping = Machine::new(Program::parse_with("seti 4 0 1\nout 1\nhalt\nout 1", &InstructionSet::extended()).unwrap());
+
+        assert_eq!(stopping.run(), Halt::Stopped(2));
+        assert_eq!(stopping.take_output(), vec![4]);
+    }
+
+    #[test]
+    fn debugger_commands() {
+        use crate::cpu::{Debugger, Halt, Machine, Program, Stop, Watch};
+
+        let program = "#ip 0\nseti 5 0 1\nseti 6 0 2\naddi 0 1 0\naddr 1 2 3\nsetr 1 0 0\nseti 8 0 4\nseti 9 0 5\n".parse::<Program>().unwrap();
+        let mut debugger = Debugger::new(Machine::new(program.clone()));
+
+        assert_eq!(debugger.command("b 4"), Ok("breakpoint at 4".to_string()));
+        assert_eq!(debugger.command("c"), Ok("breakpoint at 4\nip=4 [3, 5, 6, 0, 0, 0] setr 1 0 0".to_string()));
+        assert_eq!(debugger.listing(1), "     3  addr 1 2 3\n>*   4  setr 1 0 0\n     5  seti 8 0 4");
+
+        // Continuing from a breakpoint moves past it
+        assert_eq!(debugger.resume(), Stop::Halted(Halt::IpOutOfRange(7)));
+        assert_eq!((debugger.hits(2), debugger.hits(3)), (1, 0));
+        assert_eq!(debugger.command("d 4"), Ok("removed breakpoint at 4".to_string()));
+        assert_eq!(debugger.command("d 4"), Err("no breakpoint at 4".to_string()));
+
+        let mut debugger = Debugger::new(Machine::new(program.clone()));
+
+        assert_eq!(debugger.command("s 2"), Ok("stepped over 1\nip=2 [1, 5, 6, 0, 0, 0] addi 0 1 0".to_string()));
+        assert_eq!(debugger.command("set r3 7"), Ok("ip=2 [1, 5, 6, 7, 0, 0] addi 0 1 0".to_string()));
+        assert_eq!(debugger.command("set ip 6"), Ok("ip=6 [1, 5, 6, 7, 0, 0] seti 9 0 5".to_string()));
+        assert_eq!(debugger.command("i"), Ok("breakpoints: []\nwatchpoints: []\nsteps: 2".to_string()));
+
+        // Value watchpoints only fire on a change to the value
+        let mut debugger = Debugger::new(Machine::new(program.clone()));
+
+        assert_eq!(debugger.command("w r2=6"), Ok("watching Value(2, 6)".to_string()));
+        assert_eq!(debugger.resume(), Stop::Watchpoint { ip: 1, watch: Watch::Value(2, 6), old: 0, new: 6 });
+        assert_eq!(debugger.command("w r0"), Ok("watching Write(0)".to_string()));
+        // The ip register read as the instruction's address
+        assert_eq!(debugger.resume(), Stop::Watchpoint { ip: 2, watch: Watch::Write(0), old: 2, new: 3 });
+        assert_eq!(debugger.command("uw r0"), Ok("removed watchpoints on r0".to_string()));
+        assert_eq!(debugger.command("u 6"), Ok("reached 6 for the 1. time\nip=6 [5, 5, 6, 0, 0, 0] seti 9 0 5".to_string()));
+
+        for (command, error) in [("w r9", "no such register: r9"), ("w r6=1", "no such register: r6"), ("uw r-1", "no such register: r-1"),
+                                 ("w rr1", "not a register: rr1"), ("w 1", "not a register: 1"), ("set 3 7", "not a register: 3"),
+                                 ("b -1", "not an address: -1"), ("set r1", "missing argument"),
+                                 ("restore", "nothing saved"), ("bogus", "unknown command: bogus"), ("", "empty command")].iter() {
+            assert_eq!(debugger.command(command), Err(error.to_string()));
+        }
+    }
+
+    #[test]
+    fn trace_recording() {
+        use crate::cpu::{trace, Halt, InstructionSet, Machine, Program, Trace, TraceEntry, TraceFilter};
+
+        let summary = |t: &Trace| t.entries().map(|e: &TraceEntry| (e.step, e.ip, e.opcode, e.write)).collect::<Vec<_>>();
+        let program = "#ip 0\nseti 5 0 1\nseti 6 0 2\naddi 0 1 0\naddr 1 2 3\nsetr 1 0 0\nseti 8 0 4\nseti 9 0 5\n".parse::<Program>().unwrap();
+
+        let mut machine = Machine::new(program.clone());
+        let mut full = Trace::new(&machine, TraceFilter::new(), None);
+
+        assert_eq!(trace::record(&mut machine, &mut full, None).unwrap(), Halt::IpOutOfRange(7));
+        assert_eq!(full.entries().map(|e| e.ip).collect::<Vec<_>>(), vec![0, 1, 2, 4, 6]);
+
+        // Both stores to r0 change it from the address of the instruction storing
+        let change = full.last_change(0).unwrap();
+
+        assert_eq!((change.ip, change.write.map(|w| (w.old, w.new))), (4, Some((4, 5))));
+        assert_eq!(full.entries().filter_map(|e| e.write.filter(|w| w.register == 0)).map(|w| w.old).collect::<Vec<_>>(), vec![2, 4]);
+        assert!(full.last_change(4).is_none());
+        assert_eq!(full.writes(0).map(|(e, v)| (e.ip, v)).collect::<Vec<_>>(), vec![(2, 3), (4, 5)]);
+
+        assert_eq!(full.values_at(4, 1), Some(vec![5]));
+        assert_eq!((full.values_at(4, 6), full.values_at(4, -1)), (None, None));
+
+        // The ring buffer keeps the newest entries, but the states and counts stay right
+        let mut machine = Machine::new(program.clone());
+        let mut ring = Trace::new(&machine, TraceFilter::new(), Some(2));
+
+        trace::record(&mut machine, &mut ring, None).unwrap();
+
+        assert_eq!((ring.len(), ring.dropped(), ring.count(0), ring.count(5)), (2, 3, 1, 0));
+        assert_eq!(ring.entries().map(|e| e.ip).collect::<Vec<_>>(), vec![4, 6]);
+        assert_eq!(ring.states().unwrap().map(|(_, regs)| regs).collect::<Vec<_>>(), full.states().unwrap().skip(3).map(|(_, regs)| regs).collect::<Vec<_>>());
+        assert!(ring.last_change(1).is_none());
+
+        // Saved traces load back, with extended instructions as long as the set knows them
+        let mut saved = Vec::new();
+
+        full.save(&mut saved).unwrap();
+
+        let loaded = Trace::load(&saved[..], &InstructionSet::base(), None).unwrap();
+
+        assert_eq!(summary(&loaded), summary(&full));
+        assert_eq!(loaded.values_at(4, 0), full.values_at(4, 0));
+
+        let extended = Program::parse_with("seti 7 0 1\ndivi 1 2 2\nout 2", &InstructionSet::extended()).unwrap();
+        let mut machine = Machine::new(extended);
+        let mut filtered = Trace::new(&machine, TraceFilter::new().ips(vec![1, 2]), None);
+
+        trace::record(&mut machine, &mut filtered, None).unwrap();
+
+        let mut saved = Vec::new();
+
+        filtered.save(&mut saved).unwrap();
+
+        let loaded = Trace::load(&saved[..], &InstructionSet::extended(), None).unwrap();
+
+        assert_eq!(summary(&loaded), summary(&filtered));
+        assert!(loaded.states().is_none() && loaded.values_at(2, 2).is_none());
+        assert!(Trace::load(&saved[..], &InstructionSet::base(), None).is_err());
+
+        // Registers the machine does not have
+        for corrupt in ["registers 0 0 0 0 0 0 ip 6\n", "registers 0 0 0 0 0 0\n0 0 seti 1 0 1 r7 0 1\n", "registers 0 0 0 0 0\n"].iter() {
+            assert!(Trace::load(corrupt.as_bytes(), &InstructionSet::base(), None).is_err(), "{:?}", corrupt);
+        }
+    }
+
+    #[test]
+    fn decompiler_output() {
+        use crate::cpu::{decompile, Program};
+
+        // Straight-line code, compound assignments where the target is an operand
+        let plain = "seti 1 0 0\nmuli 0 2 0\nbani 0 255 0".parse::<Program>().unwrap();
+
+        assert_eq!(decompile(&plain), "r0 = 1;                                  // 0\nr0 *= 2;                                 // 1\nr0 &= 255;                               // 2\n");
+
+        // A branch over a jump over the else part
+        let branching = "#ip 5\nseti 3 0 1\ngtri 1 2 2\naddr 2 5 5\nseti 5 0 5\naddi 1 1 1\nseti 6 0 5\nseti 0 0 1\nmulr 1 1 0\n".parse::<Program>().unwrap();
+        let expected = [
+            "// #ip 5",
+            "r1 = 3;                                  // 0",
+            "r2 = r1 > 2;                             // 1",
+            "if (r2) {                                // 2, 3",
+            "    r1 += 1;                             // 4",
+            "} else {                                 // 5",
+            "    r1 = 0;                              // 6",
+            "}",
+            "r0 = r1 * r1;                            // 7"
+        ];
+
+        assert_eq!(decompile(&branching).lines().collect::<Vec<_>>(), expected);
+
+        // Day 19: the nested loops come out as such, the computed jump and the setup after it stay gotos
+        let day19 = decompile(&include_str!("../../day19/input").parse::<Program>().unwrap());
+        let lines = day19.lines().map(|l| l.trim_end()).collect::<Vec<_>>();
+
+        for expected in [
+            "goto L17;                                // 0",
+            "L1:",
+            "do {",
+            "    do {",
+            "        if (r5) {                        // 5, 6",
+            "            r0 += r4;                    // 7",
+            "    } while (!r5);                       // 10, 11",
+            "} while (!r5);                           // 14, 15",
+            "halt;                                    // 16",
+            "jump(r0 + 26);                           // 25",
+            "goto L1;                                 // 35"
+        ].iter() {
+            assert!(lines.contains(expected), "missing {:?} in\n{}", expected, day19);
+        }
+
+        assert_eq!(lines.iter().filter(|l| l.starts_with('L')).count(), 2);
+    }
+
+    #[test]
+    fn register_count() {
+        use crate::cpu::{registers_match, Alu, AluError, Mnemonic, Opcode};
+
+        let mut alu = Alu::with_registers([1, 2, 3, 4]);
+
+        assert_eq!(alu.eval(&Opcode::build(Mnemonic::Addr, 0, 3, 2).unwrap()), Ok(()));
+        assert_eq!(alu.eval(&Opcode::build(Mnemonic::Addr, 0, 4, 2).unwrap()), Err(AluError::InvalidRegister(4)));
+        assert_eq!(alu.eval(&Opcode::build(Mnemonic::Seti, 0, 0, 4).unwrap()), Err(AluError::InvalidRegister(4)));
+
+        // Register operands that do not fit a register number are not truncated to one
+        assert_eq!(Opcode::decode(&[Mnemonic::Setr.number().unwrap(), 257, 0, 0]), None);
+        assert_eq!(Opcode::build(Mnemonic::Addr, 0, -129, 2), None);
+        assert!(Opcode::build(Mnemonic::Seti, 257, 1000, 0).is_some());
+        assert_eq!(Opcode::try_all(&[0, 300, 0, 0]).map(|op| op.mnemonic()).collect::<Vec<_>>(), vec![Mnemonic::Gtir, Mnemonic::Eqir, Mnemonic::Seti]);
+
+        assert!(alu.matches(&[Some(1), None, Some(5)]));
+        assert!(!registers_match(&alu.regs, &[None, None, None, None, Some(0)]));
+    }
+
+    #[test]
+    fn arithmetic_faults() {
+        use crate::cpu::{ArithmeticMode, AluError, CompiledMachine, Halt, Machine, Program, Word};
+
+        let program = "seti 1 0 1\nmuli 1 2 1\nseti 0 0 0\n".parse::<Program>().unwrap();
+        let mut machine = Machine::new(program.clone());
+
+        machine.set_ip(1);
+        machine.registers_mut()[1] = Word::MAX;
+
+        let mut compiled = CompiledMachine::from_machine(&machine);
+
+        for halt in &[machine.clone().run(), compiled.clone().run()] {
+            match halt {
+                Halt::Fault(fault) => assert_eq!((fault.ip, fault.error, fault.opcode), (1, AluError::Overflow, program.instructions()[1])),
+                other => panic!("expected an overflow, got {:?}", other)
+            }
+        }
+
+        machine.set_mode(ArithmeticMode::Saturating);
+        compiled.set_mode(ArithmeticMode::Wrapping);
+
+        assert_eq!((machine.run(), machine.registers()[1]), (Halt::IpOutOfRange(3), Word::MAX));
+        assert_eq!((compiled.run(), compiled.registers()[1]), (Halt::IpOutOfRange(3), -2));
+    }
+
+    #[test]
+    fn optimized_loops() {
+        use crate::cpu::{CompiledMachine, Halt, Idiom, Machine, Program};
+
+        for (source, r0) in &[(include_str!("../../day19/input"), 0), (include_str!("../../day21/input"), 3345459)] {
+            let program = source.parse::<Program>().unwrap();
+
+            let mut plain = CompiledMachine::new(&program);
+            let mut optimized = CompiledMachine::new(&program);
+
+            let macros = optimized.optimize();
+
+            assert_eq!(macros.len(), 1);
+            assert!(matches!(macros[0].idiom, Idiom::DivisorSum { .. } | Idiom::Divide { divisor: 256, .. }));
+
+            plain.registers_mut()[0] = *r0;
+            optimized.registers_mut()[0] = *r0;
+
+            assert_eq!(plain.run(), optimized.run());
+            assert!(matches!(optimized.run(), Halt::IpOutOfRange(_)));
+            assert_eq!((plain.registers(), plain.steps()), (optimized.registers(), optimized.steps()));
+        }
+
+        // Dividing r1 by 7, the steps of a macro are those of the loop it replaced
+        let program = "#ip 5\nseti 0 0 2\naddi 2 1 3\nmuli 3 7 3\ngtrr 3 1 3\naddr 3 5 5\naddi 5 1 5\nseti 8 0 5\naddi 2 1 2\nseti 0 0 5\nmulr 2 2 0".parse::<Program>().unwrap();
+
+        for &dividend in &[-5, 0, 6, 7, 1000] {
+            let mut machine = Machine::new(program.clone());
+            let mut optimized = CompiledMachine::new(&program);
+
+            assert_eq!(optimized.optimize().len(), 1);
+
+            machine.registers_mut()[1] = dividend;
+            optimized.registers_mut()[1] = dividend;
+
+            assert_eq!(machine.run(), optimized.run());
+            assert_eq!((machine.registers(), machine.steps()), (optimized.registers(), optimized.steps()), "{}", dividend);
+        }
+
+        // Running for a limited number of steps never splits a macro
+        let mut optimized = CompiledMachine::new(&program);
+
+        optimized.optimize();
+        optimized.registers_mut()[1] = 70;
+
+        assert_eq!(optimized.run_for(1), Halt::StepLimit);
+        assert_eq!((optimized.ip(), optimized.steps(), optimized.registers()[2]), (9, 76, 10));
+    }
+
+    #[test]
+    fn extended_instructions() {
+        use crate::cpu::{CompiledMachine, Halt, InstructionSet, Machine, Program};
+
+        let source = "#ip 5\nseti 17 0 0\ndivi 0 5 1\nmodi 0 5 2\nout 1\nout 2\nnop\nhalt\nseti 99 0 0\n";
+
+        assert!(Program::parse(source).is_err());
+
+        let program = Program::parse_with(source, &InstructionSet::extended()).unwrap();
+        let reparsed = Program::parse_with(&program.to_string(), &InstructionSet::extended()).unwrap();
+
+        assert_eq!(program.instructions(), reparsed.instructions());
+
+        let mut machine = Machine::new(program.clone());
+        let mut compiled = CompiledMachine::new(&program);
+
+        assert_eq!(machine.run(), Halt::Stopped(6));
+        assert_eq!(compiled.run(), Halt::Stopped(6));
+        assert_eq!((machine.output(), &machine.registers()[..3]), (&[3, 2][..], &[17, 3, 2][..]));
+        assert_eq!((compiled.output(), compiled.registers()), (machine.output(), machine.registers()));
+    }
+
+    #[test]
+    fn profile_counts() {
+        use crate::cpu::{Halt, Machine, Profile, Program, ValueRange};
+
+        // Counts r1 up to 10
+        let program = "#ip 0\nseti 0 0 1\naddi 1 1 1\ngtri 1 9 2\naddr 0 2 0\nseti 0 0 0\n".parse::<Program>().unwrap();
+        let mut machine = Machine::new(program.clone());
+        let mut profile = Profile::new();
+
+        assert_eq!(profile.record(&mut machine, None), Halt::IpOutOfRange(5));
+        assert_eq!((profile.count(0), profile.count(1), profile.count(4)), (1, 10, 9));
+        assert_eq!(profile.total(), 1 + 10 * 3 + 9);
+        assert_eq!(profile.range(2, 1), Some(ValueRange { min: 1, max: 10 }));
+
+        let loops = profile.hot_loops(&program);
+
+        assert_eq!((loops.len(), loops[0].head, loops[0].count), (1, 1, 39));
+        assert!(profile.listing(&program).contains("10  25.00%    1: addi 1 1 1  <- loop 0"));
+    }
+
+    #[test]
+    fn snapshots() {
+        use crate::cpu::{CompiledMachine, Difference, Machine, Program, Snapshot};
+
+        let program = include_str!("../../day21/input").parse::<Program>().unwrap();
+        let mut machine = Machine::new(program.clone());
+
+        machine.run_for(1000);
+
+        let checkpoint = machine.snapshot();
+        let text = checkpoint.to_string();
+
+        assert_eq!(text.parse::<Snapshot>(), Ok(checkpoint.clone()));
+        assert!(text.parse::<Snapshot<4>>().is_err());
+
+        machine.run_for(1000);
+        let later = machine.snapshot();
+
+        assert!(checkpoint.diff(&later).contains(&Difference::Steps(1000, 2000)));
+        assert!(machine.restore(&checkpoint));
+        assert_eq!(machine.snapshot(), checkpoint);
+
+        // Continuing from the checkpoint ends up in the same place on either machine
+        let mut compiled = CompiledMachine::new(&program);
+
+        assert!(compiled.restore(&checkpoint));
+
+        machine.run_for(1000);
+        compiled.run_for(1000);
+
+        assert_eq!(machine.snapshot(), later);
+        assert_eq!(compiled.snapshot(), later);
+
+        let other = "#ip 0\nseti 0 0 1\n".parse::<Program>().unwrap();
+
+        assert!(!Machine::new(other).restore(&checkpoint));
+    }
+
+    #[test]
+    fn observers() {
+        use crate::cpu::{Halt, Machine, Mnemonic, Observers, Program};
+
+        let program = include_str!("../../day21/input").parse::<Program>().unwrap();
+        let mut machine = Machine::new(program);
+
+        // The exit check compares the candidate against r0
+        let first = Observers::new()
+            .comparison(|c| if c.ip == 28 && c.mnemonic == Mnemonic::Eqrr { Some((c.a, c.b, c.result)) } else { None })
+            .run(&mut machine);
+
+        assert_eq!(first, Ok((3345459, 0, false)));
+
+        // Stopping at an ip and continuing moves on to its next visit
+        let mut visits = Observers::new().at(28, |regs| Some(regs[1]));
+
+        assert_eq!(visits.run(&mut machine), Ok(8214606));
+        assert_eq!(visits.run(&mut machine), Ok(7064809));
+
+        let steps = Observers::new().every(1000, |steps, _| Some(steps)).run(&mut machine);
+
+        assert_eq!(steps.map(|s| s % 1000), Ok(0));
+
+        // Counts r1 up to 3
+        let program = "#ip 0\naddi 1 1 1\ngtri 1 2 2\naddr 0 2 0\nseti -1 0 0\n".parse::<Program>().unwrap();
+        let mut writes = Vec::new();
+
+        let result = Observers::<()>::new()
+            .write(1, |ip, w| { writes.push((ip, w.old, w.new)); None })
+            .run(&mut Machine::new(program));
+
+        assert_eq!(result, Err(Halt::IpOutOfRange(4)));
+        assert_eq!(writes, vec![(0, 0, 1), (0, 1, 2), (0, 2, 3)]);
+    }
+
+    #[test]
+    fn control_flow_loops() {
+        use crate::cpu::{decompile, Cfg, Halt, Machine, Program, Target, Word};
+
+        // Day 19 enters its loops only through the computed jump at 25
+        let program = include_str!("../../day19/input").parse::<Program>().unwrap();
+        let cfg = Cfg::new(&program);
+        let start = |b: usize| cfg.blocks()[b].start;
+
+        let block = cfg.block_of(25).unwrap();
+
+        assert!(cfg.blocks()[block].computed && cfg.blocks()[block].successors.is_empty());
+        assert_eq!(cfg.roots().into_iter().map(start).collect::<Vec<_>>(), vec![0, 26, 27]);
+
+        let loops = cfg.loops();
+
+        assert_eq!(loops.iter().map(|l| start(l.head)).collect::<Vec<_>>(), vec![2, 3]);
+        assert_eq!(loops[0].blocks.iter().map(|&b| start(b)).collect::<Vec<_>>(), vec![2, 3, 6, 7, 8, 11, 12, 15]);
+        assert_eq!(loops[1].latches.iter().map(|&b| start(b)).collect::<Vec<_>>(), vec![11]);
+        assert!(cfg.dominates(cfg.block_of(2).unwrap(), cfg.block_of(14).unwrap()));
+        assert_eq!(cfg.immediate_dominator(cfg.block_of(1).unwrap()), cfg.block_of(1));
+
+        // Without computed jumps dead code stays unreachable
+        let dead = Cfg::new(&"#ip 0\nseti 2 0 0\naddi 1 1 1\nseti 0 0 0\naddi 1 1 1".parse::<Program>().unwrap());
+
+        assert_eq!(dead.roots(), vec![0]);
+        assert!(dead.loops().is_empty());
+        assert_eq!(dead.immediate_dominator(dead.block_of(1).unwrap()), None);
+
+        // A jump to the largest address leaves the program like the machine does
+        let far = "#ip 1\nseti 9223372036854775807 0 1".parse::<Program>().unwrap();
+
+        assert_eq!(Cfg::new(&far).blocks()[0].successors, vec![Target::Exit]);
+        assert_eq!(decompile(&far), "// #ip 1\nhalt;                                    // 0\n");
+        assert_eq!(Machine::new(far).run(), Halt::IpOutOfRange(Word::MIN));
+    }
+
+    #[test]
+    fn halting_analysis() {
+        use crate::cpu::{halting, CompiledMachine, ExitCheck, Halt, HaltingError, Machine, Program};
+
+        let program = include_str!("../../day21/input").parse::<Program>().unwrap();
+
+        assert_eq!(halting::exit_checks(&program), Ok(vec![ExitCheck { ip: 28, register: 1, exit_steps: 2 }]));
+
+        // Counts are exact on the interpreter, even if it is too slow to see the values repeat
+        let analysis = halting::analyze(&program, &mut Machine::new(program.clone()), Some(100_000)).unwrap();
+        let first = analysis.first().unwrap();
+        let mut machine = Machine::new(program.clone());
+
+        machine.registers_mut()[0] = first.value;
+
+        assert_eq!((machine.run(), machine.steps()), (Halt::IpOutOfRange(31), first.steps));
+        assert_eq!((analysis.halt, analysis.last()), (Some(Halt::StepLimit), None));
+
+        let mut compiled = CompiledMachine::new(&program);
+
+        compiled.optimize();
+
+        let optimized = halting::analyze(&program, &mut compiled, None).unwrap();
+
+        // Macros count the instructions of the loops they replaced
+        assert_eq!(optimized.first(), analysis.first());
+        assert_eq!(optimized.first().map(|c| c.value), Some(3345459));
+        assert_eq!(optimized.last().map(|c| c.value), Some(5857354));
+
+        let day19 = include_str!("../../day19/input").parse::<Program>().unwrap();
+
+        assert!(matches!(halting::exit_checks(&day19), Err(HaltingError::InputUsed(_))));
+    }
+
+    #[test]
+    fn symbolic_execution() {
+        use std::rc::Rc;
+        use crate::cpu::{symbolic, BinOp, Condition, End, Explorer, Expr, Program, Word};
+
+        // Exits at 4 if r0 + 3 > 10, and right away at 5 otherwise
+        let program = "#ip 5\naddi 0 3 1\ngtri 1 10 2\naddr 2 5 5\nseti 98 0 5\nseti 99 0 5\n".parse::<Program>().unwrap();
+        let paths = Explorer::new(&program).input(0).explore(100, 16);
+
+        assert_eq!(paths.len(), 2);
+
+        for path in &paths {
+            let solution = path.solve().unwrap();
+            let domain = &solution.inputs[&0];
+
+            assert!(solution.unsolved.is_empty());
+
+            match path.last {
+                Some(3) => assert_eq!((path.end.clone(), domain.max, domain.min), (End::Exit(99), Some(7), None)),
+                Some(4) => assert_eq!((path.end.clone(), domain.min, domain.max), (End::Exit(100), Some(8), None)),
+                _ => panic!("unexpected path {:?}", path)
+            }
+        }
+
+        // The first way out of day21 needs r0 to be the first value it compares
+        let day21 = include_str!("../../day21/input").parse::<Program>().unwrap();
+        let paths = Explorer::new(&day21).input(0).explore(10_000, 1);
+        let solution = paths[0].solve().unwrap();
+
+        assert_eq!((paths[0].end.clone(), paths[0].last), (End::Exit(31), Some(29)));
+        assert_eq!(solution.inputs[&0].value(), Some(3345459));
+
+        // Jumping to the largest address leaves the program
+        let far = "#ip 4\nseti 9223372036854775807 0 4".parse::<Program>().unwrap();
+        let paths = Explorer::new(&far).explore(100, 4);
+
+        assert_eq!(paths.iter().map(|p| p.end.clone()).collect::<Vec<_>>(), vec![End::Exit(Word::MIN)]);
+
+        // Masking is not linear, so it is left to the caller
+        let masked = Rc::new(Expr::Binary(BinOp::And, Rc::new(Expr::Input(0)), Rc::new(Expr::Const(255))));
+        let conditions = [Condition { expr: Rc::new(Expr::Binary(BinOp::Eq, masked, Rc::new(Expr::Const(7)))), holds: true }];
+
+        assert_eq!(symbolic::solve(&conditions).map(|s| (s.inputs.len(), s.unsolved.len())), Some((0, 1)));
+    }
+
+    #[test]
+    fn bytecode() {
+        use crate::cpu::{bytecode, BytecodeErrorKind, InstructionSet, Opcode, OpcodeMap, Program};
+
+        let map = OpcodeMap::builtin();
+        let day19 = include_str!("../../day19/input").parse::<Program>().unwrap();
+        let bytes = bytecode::encode(&day19, &map);
+        let (program, decoded) = bytecode::decode(&bytes, &InstructionSet::base()).unwrap();
+
+        assert_eq!((program.to_string(), decoded), (day19.to_string(), map.clone()));
+        assert!(bytes.len() < day19.to_string().len());
+
+        // Every instruction round trips through the numeric form as well
+        for op in day19.instructions() {
+            assert_eq!(map.encode(op).and_then(|raw| Opcode::decode(&raw)), Some(*op));
+        }
+
+        // Extensions get numbers of their own and need to be known when reading back
+        let source = "#ip 3\nseti 10 0 0\ndivi 0 3 1\nout 1\nhalt\n";
+        let extended = Program::parse_with(source, &InstructionSet::extended()).unwrap();
+        let bytes = bytecode::encode(&extended, &map);
+
+        let error = |bytes: &[u8], set: &InstructionSet| bytecode::decode(bytes, set).map(|(p, _)| p.to_string()).map_err(|e| e.kind);
+
+        assert_eq!(error(&bytes, &InstructionSet::extended()), Ok(extended.to_string()));
+        assert_eq!(error(&bytes, &InstructionSet::base()), Err(BytecodeErrorKind::UnknownMnemonic("divi".to_string())));
+
+        let error = |bytes: &[u8]| error(bytes, &InstructionSet::extended()).map(|_| ());
+        let mut corrupt = bytes.clone();
+
+        // The register of `out`, then the checksum
+        *corrupt.iter_mut().rev().nth(9).unwrap() ^= 1;
+        assert_eq!(error(&corrupt), Err(BytecodeErrorKind::InvalidRegister(-2)));
+
+        corrupt = bytes.clone();
+        *corrupt.last_mut().unwrap() ^= 1;
+        assert_eq!(error(&corrupt), Err(BytecodeErrorKind::ChecksumMismatch));
+
+        // Renumbering `bori`, which the program does not use, still decodes to the same program
+        corrupt = bytes.clone();
+        assert_eq!(corrupt[8], 0);
+        corrupt[8] = 1;
+        assert_eq!(error(&corrupt), Err(BytecodeErrorKind::ChecksumMismatch));
+
+        assert_eq!(error(&bytes[..bytes.len() - 1]), Err(BytecodeErrorKind::Truncated));
+        assert_eq!(error(&[&bytes[..], &[0]].concat()), Err(BytecodeErrorKind::TrailingBytes));
+        assert_eq!(error(b"#ip 3"), Err(BytecodeErrorKind::BadMagic));
+        assert_eq!(error(b"ELFC\x02"), Err(BytecodeErrorKind::UnsupportedVersion(2)));
+    }
+
+    #[test]
+    fn opcode_semantics() {
+        use std::collections::BTreeSet;
+        use crate::cpu::{difftest, Alu, Machine, Mnemonic, Opcode, Program};
+
+        let registers = [5, 3, 2, 9, 0, 0];
+
+        // Every row stores to r4, rows repeat a mnemonic where an operand read
+        // the wrong way would still give the right result
+        let table = [
+            (Mnemonic::Addr, 0, 1, 8),
+            (Mnemonic::Addi, 0, 7, 12),
+            (Mnemonic::Mulr, 1, 2, 6),
+            (Mnemonic::Muli, 1, -4, -12),
+            (Mnemonic::Banr, 0, 1, 1),
+            (Mnemonic::Bani, 3, 12, 8),
+            (Mnemonic::Borr, 0, 2, 7),
+            (Mnemonic::Bori, 1, 8, 11),
+            (Mnemonic::Setr, 3, 7, 9),
+            (Mnemonic::Seti, 6, 7, 6),
+            (Mnemonic::Gtir, 4, 1, 1),
+            (Mnemonic::Gtir, 3, 1, 0),
+            (Mnemonic::Gtri, 0, 3, 1),
+            (Mnemonic::Gtri, 1, 3, 0),
+            (Mnemonic::Gtrr, 3, 0, 1),
+            (Mnemonic::Gtrr, 0, 3, 0),
+            (Mnemonic::Eqir, 3, 1, 1),
+            (Mnemonic::Eqir, 1, 1, 0),
+            (Mnemonic::Eqri, 2, 2, 1),
+            (Mnemonic::Eqri, 1, 1, 0),
+            (Mnemonic::Eqrr, 1, 1, 1),
+            (Mnemonic::Eqrr, 0, 3, 0)
+        ];
+
+        assert_eq!(table.iter().map(|row| row.0).collect::<BTreeSet<_>>().len(), Mnemonic::ALL.len());
+
+        for &(mnemonic, a, b, expected) in table.iter() {
+            let op = Opcode::build(mnemonic, a, b, 4).unwrap();
+            let program = Program::new(None, vec![op]);
+            let mut alu = Alu::with_registers(registers);
+            let mut machine = Machine::with_registers(program.clone(), registers);
+            let mut compiled = difftest::compiled(&program, registers);
+
+            alu.eval(&op).unwrap();
+            machine.step().unwrap();
+            compiled.step().unwrap();
+
+            for (name, result) in [("alu", alu.regs), ("machine", *machine.registers()), ("compiled", *compiled.registers())].iter() {
+                let mut want = registers;
+
+                want[4] = expected;
+                assert_eq!(*result, want, "{} on {}", op, name);
+            }
+        }
+
+        // B is unused for setr and seti, whatever the raw instruction says
+        for &mnemonic in [Mnemonic::Setr, Mnemonic::Seti].iter() {
+            let op = Opcode::decode(&[mnemonic.number().unwrap(), 1, 5, 2]).unwrap();
+
+            assert_eq!(op.to_string(), format!("{} 1 0 2", mnemonic.name()));
+        }
+    }
+
+    #[test]
+    fn instruction_set_table() {
+        use crate::cpu::{isa, InstructionSet, Mnemonic, Opcode, Operand, ParseErrorKind, UnknownMnemonic};
+
+        for (definition, &mnemonic) in isa::BASE.iter().zip(Mnemonic::ALL.iter()) {
+            assert_eq!(definition.mnemonic, mnemonic);
+            assert_eq!(mnemonic.to_string().parse::<Mnemonic>().unwrap(), mnemonic);
+            assert_eq!(Mnemonic::from_number(definition.number), Some(mnemonic));
+
+            let op = Opcode::build(mnemonic, 1, 2, 3).unwrap();
+
+            assert_eq!(op.to_string().parse::<Opcode>().unwrap(), op);
+        }
+
+        let op = "gtir 4 1 2".parse::<Opcode>().unwrap();
+
+        assert_eq!((op.mnemonic(), op.a(), op.b(), op.c()), (Mnemonic::Gtir, 4, 1, 2));
+        assert_eq!(op.mnemonic().shape().a, Operand::Immediate);
+        assert_eq!("setr 3 7 1".parse::<Opcode>().unwrap().b(), 0);
+
+        assert_eq!("divr".parse::<Mnemonic>(), Err(UnknownMnemonic("divr".to_string())));
+        assert!(matches!("#ip 1\nseti 0 0 0".parse::<Opcode>().unwrap_err().kind, ParseErrorKind::NotAnInstruction(_)));
+
+        let listing = InstructionSet::extended().to_string();
+
+        assert_eq!(listing.lines().count(), 23);
+        assert!(listing.lines().any(|line| line == "gtir  A rB rC   rC = A > rB"));
+        assert!(listing.lines().any(|line| line == "out   rA - -    output rA"));
+    }
+
+    #[test]
+    fn differential() {
+        use crate::cpu::{difftest, Generator, InstructionSet};
+
+        for seed in 0..4 {
+            let mut generator = Generator::new(seed, &InstructionSet::extended());
+
+            if let Err(mismatch) = difftest::fuzz(&mut generator, 2000, 200, &difftest::compiled) {
+                panic!("compiled machine differs:\n{}", mismatch);
+            }
+
+            if let Err(mismatch) = difftest::fuzz(&mut generator, 2000, 200, &difftest::optimized) {
+                panic!("optimized machine differs:\n{}", mismatch);
+            }
+        }
+    }
 }